@@ -0,0 +1,94 @@
+//! CPUインタプリタのベンチマーク.
+//!
+//! プログラムをBRKまで実行し、1秒あたりの命令数(elem/s)を計測する.
+//!
+//! ```text
+//! % cargo bench --bench cpu
+//! ```
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use nes_rs::cpu::bus::Bus;
+use nes_rs::cpu::cpu::Cpu;
+use nes_rs::rom::cartridge::RamCartridge;
+
+/// レジスタのみを使う演算のループ(64 x 256回)
+const ARITHMETIC: [u8; 18] = [
+    0xa0, 0x40, //       LDY #$40
+    0xa2, 0x00, //       outer: LDX #$00
+    0x8a, //             inner: TXA
+    0x69, 0x03, //       ADC #$03
+    0x49, 0x5a, //       EOR #$5A
+    0x0a, //             ASL A
+    0x6a, //             ROR A
+    0xca, //             DEX
+    0xd0, 0xf6, //       BNE inner
+    0x88, //             DEY
+    0xd0, 0xf1, //       BNE outer
+    0x00, //             BRK
+];
+
+/// Busの各領域を読み書きするループ(32 x 256回)
+const MEMORY: [u8; 42] = [
+    0xa9, 0x00, //       LDA #$00
+    0x85, 0x10, //       STA $10
+    0xa9, 0x03, //       LDA #$03
+    0x85, 0x11, //       STA $11        ($10) = $0300
+    0xa0, 0x20, //       LDY #$20
+    0xa2, 0x00, //       outer: LDX #$00
+    0xbd, 0x00, 0x80, // inner: LDA $8000,X  PRG-ROM
+    0x9d, 0x00, 0x02, // STA $0200,X    RAM
+    0x9d, 0x00, 0x60, // STA $6000,X    PRG-RAM
+    0x5d, 0x00, 0x0a, // EOR $0A00,X    RAMのミラー
+    0x91, 0x10, //       STA ($10),Y
+    0xfe, 0x00, 0x04, // INC $0400,X
+    0xad, 0x02, 0x20, // LDA $2002      PPUSTATUS
+    0x8d, 0x15, 0x40, // STA $4015      APU
+    0xca, //             DEX
+    0xd0, 0xe6, //       BNE inner
+    0x88, //             DEY
+    0xd0, 0xe1, //       BNE outer
+    0x00, //             BRK
+];
+
+fn cpu(program: &[u8]) -> Cpu<'static> {
+    let bus = Bus::new(RamCartridge::new(program, 0x2000), |_| {});
+    let mut cpu = Cpu::new(bus);
+    cpu.reset();
+    cpu
+}
+
+/// BRKまでに実行する命令数
+fn instruction_count(program: &[u8]) -> u64 {
+    let mut cpu = cpu(program);
+    let mut count = 0;
+    while cpu.step() {
+        count += 1;
+    }
+    count
+}
+
+fn bench_program(c: &mut Criterion, name: &str, program: &[u8]) {
+    let mut group = c.benchmark_group("cpu");
+    group.throughput(Throughput::Elements(instruction_count(program)));
+    group.bench_function(name, |b| {
+        b.iter_batched(
+            || cpu(program),
+            |mut cpu| {
+                cpu.run();
+                cpu
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn arithmetic(c: &mut Criterion) {
+    bench_program(c, "arithmetic", &ARITHMETIC);
+}
+
+fn memory(c: &mut Criterion) {
+    bench_program(c, "memory", &MEMORY);
+}
+
+criterion_group!(benches, arithmetic, memory);
+criterion_main!(benches);
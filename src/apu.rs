@@ -0,0 +1,7 @@
+#[allow(clippy::module_inception)]
+pub mod apu;
+pub mod buffer;
+pub mod dmc;
+pub mod frame_counter;
+pub mod length_counter;
+pub mod mixer;
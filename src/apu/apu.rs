@@ -0,0 +1,363 @@
+use crate::apu::buffer::SampleBuffer;
+use crate::apu::dmc::Dmc;
+use crate::apu::frame_counter::FrameCounter;
+use crate::apu::length_counter::LengthCounter;
+use crate::apu::mixer::Mixer;
+use crate::cpu::state::{StateReader, StateWriter};
+use crate::error::NesError;
+
+/// NTSCのCPUクロック(Hz)
+pub const CPU_CLOCK: u64 = 1_789_773;
+/// 出力するサンプリングレート(Hz)
+pub const SAMPLE_RATE: u64 = 44_100;
+/// 取り出されていないサンプルを保持する数の上限(0.5秒分). 超えた分は古いものから破棄する
+const SAMPLE_QUEUE_SIZE: usize = SAMPLE_RATE as usize / 2;
+
+/// 長さカウンタを持つチャンネル(パルス1,パルス2,三角波,ノイズ)のうち三角波の番号
+const TRIANGLE: usize = 2;
+
+/// APU struct
+/// APUのレジスタはCPUから見て0x4000~0x4017
+///
+/// 現状は各チャンネルの長さカウンタとIRQの発生源(フレームカウンタ、DMC)のみを扱う.
+/// CPUのIRQ線は2つのIRQフラグの論理和となる.
+///
+/// | address |  R/W | contents |
+/// |---------|---------|---------|
+/// |0x4000, 0x4004, 0x400C| W| --H- ----: 長さカウンタ停止|
+/// |0x4008| W| H--- ----: 長さカウンタ停止|
+/// |0x4003, 0x4007, 0x400B, 0x400F| W| LLLL L---: 長さカウンタのロード|
+/// |0x4010| W| DMC IRQ許可、ループ、レート|
+/// |0x4015| R| IF-D NT21: DMC IRQ, フレームIRQ, 各チャンネルの状態|
+/// |0x4015| W| ---D NT21: 各チャンネルの有効フラグ|
+/// |0x4017| W| MI-- ----: フレームカウンタのモード、IRQ禁止|
+///
+/// * 0x4015 の読み出しはフレームIRQをクリアするがDMC IRQはクリアしない
+/// * 0x4015 の読み出しのbit0~3は有効フラグではなく長さカウンタが残っているかどうか
+/// * DMC IRQは 0x4015 への書き込み、または 0x4010 のIRQ許可フラグを0にするとクリアされる
+#[derive(Debug, Clone)]
+pub struct Apu {
+    ///パルス1,パルス2,三角波,ノイズの長さカウンタ(0x4015 bit0~3)
+    pub length_counters: [LengthCounter; 4],
+    pub dmc: Dmc,
+    pub frame_counter: FrameCounter,
+    mixer: Mixer,
+    ///出力したサンプル. Noneの場合はサンプルを生成しない
+    samples: Option<SampleBuffer>,
+    ///サンプリングの位相(CPUサイクル x SAMPLE_RATE)
+    sample_phase: u64,
+}
+
+impl Apu {
+    ///APUコンストラクタ
+    pub fn new() -> Self {
+        Apu {
+            length_counters: Default::default(),
+            dmc: Dmc::new(),
+            frame_counter: FrameCounter::new(),
+            mixer: Mixer::new(),
+            samples: None,
+            sample_phase: 0,
+        }
+    }
+
+    /// リセット. 全チャンネルを無効化($4015 = 0)し、フレームカウンタを初期化する.
+    pub fn reset(&mut self) {
+        self.write_status(0);
+        self.frame_counter.reset();
+    }
+
+    /// CPUサイクル分APUを進める.
+    ///
+    /// # Parameters
+    /// * `cycles` - CPUサイクル
+    pub fn tick(&mut self, cycles: u8) {
+        if self.frame_counter.tick(cycles) {
+            self.clock_length_counters();
+        }
+        self.dmc.tick(cycles);
+
+        //CPUクロックからSAMPLE_RATEへ整数演算で間引くため、
+        //1フレーム(約29780.5CPUサイクル)あたりのサンプル数は常に約734個になる
+        if let Some(samples) = &mut self.samples {
+            self.sample_phase += cycles as u64 * SAMPLE_RATE;
+            while self.sample_phase >= CPU_CLOCK {
+                self.sample_phase -= CPU_CLOCK;
+                //各チャンネルの波形は未実装のため無音
+                samples.push(self.mixer.mix(0, 0, 0, 0, 0));
+            }
+        }
+    }
+
+    /// サンプルの生成を開始する. 生成したサンプルは`take_samples`で取り出す.
+    /// 取り出さない場合は直近0.5秒分のみ保持する.
+    pub fn enable_samples(&mut self) {
+        self.samples
+            .get_or_insert_with(|| SampleBuffer::new(SAMPLE_QUEUE_SIZE));
+    }
+
+    /// サンプルを生成しているかどうか
+    pub fn samples_enabled(&self) -> bool {
+        self.samples.is_some()
+    }
+
+    /// 取り出されていないサンプルの数
+    pub fn queued_samples(&self) -> usize {
+        self.samples.as_ref().map_or(0, |samples| samples.len())
+    }
+
+    /// 前回の呼び出し以降に生成したサンプル(SAMPLE_RATE Hz)を取り出す.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        match &mut self.samples {
+            Some(samples) => samples.drain(),
+            None => Vec::new(),
+        }
+    }
+
+    fn clock_length_counters(&mut self) {
+        for length_counter in self.length_counters.iter_mut() {
+            length_counter.clock();
+        }
+    }
+
+    /// CPUのIRQ線の状態
+    pub fn irq(&self) -> bool {
+        self.frame_counter.irq || self.dmc.irq
+    }
+
+    /// 0x4015 の読み出し. フレームIRQのみクリアする.
+    pub fn read_status(&mut self) -> u8 {
+        let mut data = 0;
+        for (i, length_counter) in self.length_counters.iter().enumerate() {
+            if length_counter.is_active() {
+                data |= 1 << i;
+            }
+        }
+        if self.dmc.bytes_remaining > 0 {
+            data |= 0b0001_0000;
+        }
+        if self.frame_counter.irq {
+            data |= 0b0100_0000;
+        }
+        if self.dmc.irq {
+            data |= 0b1000_0000;
+        }
+        self.frame_counter.irq = false;
+        data
+    }
+
+    /// 0x4015 への書き込み. DMC IRQをクリアする.
+    pub fn write_status(&mut self, data: u8) {
+        for (i, length_counter) in self.length_counters.iter_mut().enumerate() {
+            length_counter.set_enabled(data & (1 << i) != 0);
+        }
+        self.dmc.set_enabled(data & 0b1_0000 != 0);
+        self.dmc.irq = false;
+    }
+
+    /// 0x4000～0x400F への書き込み. 長さカウンタに関係するレジスタのみ扱う.
+    ///
+    /// # Parameters
+    /// * `addr` - アドレス
+    /// * `data` - 書き込む値
+    pub fn write_channel(&mut self, addr: u16, data: u8) {
+        let channel = ((addr - 0x4000) / 4) as usize;
+        let length_counter = &mut self.length_counters[channel];
+        match addr & 0b11 {
+            0 if channel == TRIANGLE => length_counter.halt = data & 0b1000_0000 != 0,
+            0 => length_counter.halt = data & 0b0010_0000 != 0,
+            3 => length_counter.load(data >> 3),
+            _ => {}
+        }
+    }
+
+    /// 0x4010 への書き込み
+    pub fn write_dmc_flags(&mut self, data: u8) {
+        self.dmc.write_flags(data);
+    }
+
+    /// 0x4017 への書き込み. 5-step モードにすると直ちにハーフフレームの処理を行う.
+    pub fn write_frame_counter(&mut self, data: u8) {
+        self.frame_counter.write(data);
+        if self.frame_counter.five_step_mode {
+            self.clock_length_counters();
+        }
+    }
+
+    /// セーブステートに書き出す. 生成済みの音声サンプルは含まない.
+    pub fn write_state(&self, w: &mut StateWriter) {
+        for counter in self.length_counters.iter() {
+            w.bool(counter.enabled);
+            w.bool(counter.halt);
+            w.u8(counter.counter);
+        }
+        self.dmc.write_state(w);
+        self.frame_counter.write_state(w);
+        w.u64(self.sample_phase);
+    }
+
+    /// `write_state`で書き出した状態を読み込む
+    pub fn read_state(&mut self, r: &mut StateReader) -> Result<(), NesError> {
+        for counter in self.length_counters.iter_mut() {
+            counter.enabled = r.bool()?;
+            counter.halt = r.bool()?;
+            counter.counter = r.u8()?;
+        }
+        self.dmc.read_state(r)?;
+        self.frame_counter.read_state(r)?;
+        self.sample_phase = r.u64()?;
+        Ok(())
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod apu_test {
+    use super::*;
+
+    fn raise_frame_irq(apu: &mut Apu) {
+        for _ in 0..(29830 / 10 + 1) {
+            apu.tick(10);
+        }
+        assert!(apu.frame_counter.irq);
+    }
+
+    fn raise_dmc_irq(apu: &mut Apu) {
+        //IRQ許可、最速レート(54サイクル/bit)、1byteのサンプル
+        apu.write_dmc_flags(0b1000_1111);
+        apu.write_status(0b1_0000);
+        for _ in 0..(54 * 8 / 2) {
+            apu.tick(2);
+        }
+        assert!(apu.dmc.irq);
+    }
+
+    #[test]
+    fn read_status_clears_only_frame_irq() {
+        let mut apu = Apu::new();
+        raise_dmc_irq(&mut apu);
+        raise_frame_irq(&mut apu);
+
+        assert_eq!(apu.read_status() & 0b1100_0000, 0b1100_0000);
+        assert!(!apu.frame_counter.irq);
+        assert!(apu.dmc.irq);
+        assert!(apu.irq());
+
+        assert_eq!(apu.read_status() & 0b1100_0000, 0b1000_0000);
+    }
+
+    #[test]
+    fn read_status_with_all_channels_active() {
+        let mut apu = Apu::new();
+        //最長のサンプル
+        apu.dmc.write_sample_length(0xff);
+        apu.write_status(0b1_1111);
+        for addr in [0x4003, 0x4007, 0x400b, 0x400f] {
+            //長さ254
+            apu.write_channel(addr, 0b0000_1000);
+        }
+        raise_frame_irq(&mut apu);
+
+        assert_eq!(apu.read_status(), 0b0101_1111);
+        assert_eq!(apu.read_status(), 0b0001_1111);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn read_status_reflects_length_counters() {
+        let mut apu = Apu::new();
+        apu.write_status(0b1111);
+        //長さ2
+        apu.write_channel(0x4003, 0b0001_1000);
+        apu.write_channel(0x400b, 0b0001_1000);
+        //三角波は停止フラグで減算しない
+        apu.write_channel(0x4008, 0b1000_0000);
+        assert_eq!(apu.read_status() & 0b1111, 0b0101);
+
+        raise_frame_irq(&mut apu);
+        assert_eq!(apu.read_status() & 0b1111, 0b0100);
+
+        apu.write_status(0);
+        assert_eq!(apu.read_status() & 0b1111, 0);
+    }
+
+    #[test]
+    fn length_load_ignored_while_disabled() {
+        let mut apu = Apu::new();
+        apu.write_channel(0x4007, 0b0000_1000);
+        assert_eq!(apu.read_status() & 0b1111, 0);
+    }
+
+    #[test]
+    fn samples_only_when_enabled() {
+        let mut apu = Apu::new();
+        apu.tick(200);
+        assert!(apu.take_samples().is_empty());
+
+        apu.enable_samples();
+        //1秒分(CPU_CLOCKサイクル)でちょうどSAMPLE_RATE個
+        let mut total = 0;
+        for i in 0..(CPU_CLOCK / 100) {
+            apu.tick(100);
+            if i % 1000 == 999 {
+                total += apu.take_samples().len();
+            }
+        }
+        apu.tick((CPU_CLOCK % 100) as u8);
+        assert_eq!(total + apu.queued_samples(), SAMPLE_RATE as usize);
+        apu.take_samples();
+        assert_eq!(apu.queued_samples(), 0);
+        assert!(apu.take_samples().is_empty());
+
+        //取り出さない場合は古いサンプルから破棄する
+        for _ in 0..(CPU_CLOCK / 100) {
+            apu.tick(100);
+        }
+        assert_eq!(apu.take_samples().len(), SAMPLE_QUEUE_SIZE);
+    }
+
+    #[test]
+    fn write_status_clears_dmc_irq() {
+        let mut apu = Apu::new();
+        raise_frame_irq(&mut apu);
+        raise_dmc_irq(&mut apu);
+
+        apu.write_status(0);
+        assert!(!apu.dmc.irq);
+        assert!(apu.frame_counter.irq);
+        assert!(apu.irq());
+
+        apu.read_status();
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn write_dmc_flags_clears_dmc_irq() {
+        let mut apu = Apu::new();
+        raise_dmc_irq(&mut apu);
+        assert!(apu.irq());
+
+        apu.write_dmc_flags(0);
+        assert!(!apu.dmc.irq);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn frame_irq_inhibit() {
+        let mut apu = Apu::new();
+        raise_frame_irq(&mut apu);
+
+        apu.write_frame_counter(0b0100_0000);
+        assert!(!apu.irq());
+
+        for _ in 0..(29830 / 10 + 1) {
+            apu.tick(10);
+        }
+        assert!(!apu.irq());
+    }
+}
@@ -0,0 +1,134 @@
+/// SampleBuffer Struct
+/// APUが生成したサンプルをオーディオバックエンドへ渡すためのリングバッファ.
+///
+/// * 満杯の状態でpushすると最も古いサンプルを破棄する
+/// * 空の状態でpopするとアンダーランとして数え、最後に出力したサンプルを繰り返す
+///   (無音へ急に落ちることによるノイズを避けるため)
+#[derive(Debug, Clone)]
+pub struct SampleBuffer {
+    samples: Vec<f32>,
+    read_pos: usize,
+    len: usize,
+    last_sample: f32,
+    underruns: usize,
+}
+
+impl SampleBuffer {
+    ///SampleBufferコンストラクタ
+    ///
+    /// # Parameters
+    /// * `capacity` - バッファサイズ(サンプル数)
+    pub fn new(capacity: usize) -> Self {
+        SampleBuffer {
+            samples: vec![0.0; capacity.max(1)],
+            read_pos: 0,
+            len: 0,
+            last_sample: 0.0,
+            underruns: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// アンダーランの発生回数
+    pub fn underruns(&self) -> usize {
+        self.underruns
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        let write_pos = (self.read_pos + self.len) % self.capacity();
+        self.samples[write_pos] = sample;
+        if self.len == self.capacity() {
+            self.read_pos = (self.read_pos + 1) % self.capacity();
+        } else {
+            self.len += 1;
+        }
+    }
+
+    pub fn pop(&mut self) -> f32 {
+        if self.len == 0 {
+            self.underruns += 1;
+            return self.last_sample;
+        }
+        let sample = self.samples[self.read_pos];
+        self.read_pos = (self.read_pos + 1) % self.capacity();
+        self.len -= 1;
+        self.last_sample = sample;
+        sample
+    }
+
+    /// バッファ内のサンプルを古い順にすべて取り出す. アンダーランとしては数えない.
+    pub fn drain(&mut self) -> Vec<f32> {
+        let mut samples = Vec::with_capacity(self.len);
+        while self.len > 0 {
+            samples.push(self.pop());
+        }
+        samples
+    }
+
+    /// オーディオバックエンドの出力バッファを埋める.
+    ///
+    /// # Parameters
+    /// * `out` - 出力先
+    pub fn fill(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = self.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod buffer_test {
+    use super::*;
+
+    #[test]
+    fn underrun_repeats_last_sample() {
+        let mut buffer = SampleBuffer::new(8);
+        buffer.push(0.25);
+        buffer.push(0.5);
+
+        let mut out = [0.0; 5];
+        buffer.fill(&mut out);
+
+        assert_eq!(out, [0.25, 0.5, 0.5, 0.5, 0.5]);
+        assert_eq!(buffer.underruns(), 3);
+        assert!(buffer.is_empty());
+
+        buffer.push(0.75);
+        assert_eq!(buffer.pop(), 0.75);
+        assert_eq!(buffer.underruns(), 3);
+    }
+
+    #[test]
+    fn overrun_drops_oldest() {
+        let mut buffer = SampleBuffer::new(3);
+        for sample in [0.1, 0.2, 0.3, 0.4] {
+            buffer.push(sample);
+        }
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.pop(), 0.2);
+        assert_eq!(buffer.pop(), 0.3);
+        assert_eq!(buffer.pop(), 0.4);
+    }
+
+    #[test]
+    fn drain_does_not_underrun() {
+        let mut buffer = SampleBuffer::new(3);
+        for sample in [0.1, 0.2, 0.3, 0.4] {
+            buffer.push(sample);
+        }
+        assert_eq!(buffer.drain(), vec![0.2, 0.3, 0.4]);
+        assert!(buffer.drain().is_empty());
+        assert_eq!(buffer.underruns(), 0);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::cpu::state::{StateReader, StateWriter};
+use crate::error::NesError;
+
+/// NTSCのDMCレート(CPUサイクル/1bit)
+const RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+/// DMC(Delta Modulation Channel) Struct
+///
+/// サンプル再生の進行とIRQの発生のみを扱い、出力レベルは扱わない.
+///
+/// | address | contents |
+/// |---------|---------|
+/// |0x4010| IL-- RRRR: IRQ enable, Loop, Rate index|
+/// |0x4012| サンプルアドレス = $C000 + A * 64|
+/// |0x4013| サンプル長 = L * 16 + 1 bytes|
+#[derive(Debug, Clone)]
+pub struct Dmc {
+    pub irq_enabled: bool,
+    pub loop_flag: bool,
+    pub rate: u16,
+    pub sample_addr: u16,
+    pub sample_length: u16,
+    pub current_addr: u16,
+    pub bytes_remaining: u16,
+    ///DMC IRQフラグ
+    pub irq: bool,
+    cycles: usize,
+}
+
+impl Dmc {
+    pub fn new() -> Self {
+        Dmc {
+            irq_enabled: false,
+            loop_flag: false,
+            rate: RATE_TABLE[0],
+            sample_addr: 0xc000,
+            sample_length: 1,
+            current_addr: 0xc000,
+            bytes_remaining: 0,
+            irq: false,
+            cycles: 0,
+        }
+    }
+
+    /// $4010 への書き込み. IRQ許可フラグが0になるとDMC IRQはクリアされる.
+    pub fn write_flags(&mut self, data: u8) {
+        self.irq_enabled = data & 0b1000_0000 != 0;
+        self.loop_flag = data & 0b0100_0000 != 0;
+        self.rate = RATE_TABLE[(data & 0b1111) as usize];
+        if !self.irq_enabled {
+            self.irq = false;
+        }
+    }
+
+    /// $4012 への書き込み
+    pub fn write_sample_addr(&mut self, data: u8) {
+        self.sample_addr = 0xc000 + (data as u16) * 64;
+    }
+
+    /// $4013 への書き込み
+    pub fn write_sample_length(&mut self, data: u8) {
+        self.sample_length = (data as u16) * 16 + 1;
+    }
+
+    /// $4015 のbit4による有効/無効の切り替え
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.cycles = 0;
+            self.restart();
+        }
+    }
+
+    /// 次のサンプルの読み出し(DMA)までのCPUサイクル. 再生していない場合はNone.
+    /// 1byteを再生し終えた時点で次のバイトを読み出すものとして扱う.
+    pub fn cycles_until_fetch(&self) -> Option<usize> {
+        if self.bytes_remaining == 0 {
+            return None;
+        }
+        //レートを下げた直後は既に1byte分の周期を過ぎていることがある
+        Some((self.rate as usize * 8).saturating_sub(self.cycles))
+    }
+
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    /// CPUサイクル分サンプル再生を進める.
+    /// 1byte(8bit)を再生し終える毎に残りバイト数を減らし、
+    /// 最後のバイトでループしない場合はIRQを発生させる.
+    ///
+    /// # Parameters
+    /// * `cycles` - CPUサイクル
+    pub fn tick(&mut self, cycles: u8) {
+        if self.bytes_remaining == 0 {
+            return;
+        }
+        self.cycles += cycles as usize;
+        let byte_period = self.rate as usize * 8;
+        while self.cycles >= byte_period && self.bytes_remaining > 0 {
+            self.cycles -= byte_period;
+            self.current_addr = self.current_addr.wrapping_add(1) | 0x8000;
+            self.bytes_remaining -= 1;
+            if self.bytes_remaining == 0 {
+                if self.loop_flag {
+                    self.restart();
+                } else if self.irq_enabled {
+                    self.irq = true;
+                }
+            }
+        }
+    }
+
+    /// セーブステートに書き出す
+    pub fn write_state(&self, w: &mut StateWriter) {
+        w.bool(self.irq_enabled);
+        w.bool(self.loop_flag);
+        w.u16(self.rate);
+        w.u16(self.sample_addr);
+        w.u16(self.sample_length);
+        w.u16(self.current_addr);
+        w.u16(self.bytes_remaining);
+        w.bool(self.irq);
+        w.u64(self.cycles as u64);
+    }
+
+    /// `write_state`で書き出した状態を読み込む
+    pub fn read_state(&mut self, r: &mut StateReader) -> Result<(), NesError> {
+        self.irq_enabled = r.bool()?;
+        self.loop_flag = r.bool()?;
+        self.rate = r.u16()?;
+        self.sample_addr = r.u16()?;
+        self.sample_length = r.u16()?;
+        self.current_addr = r.u16()?;
+        self.bytes_remaining = r.u16()?;
+        self.irq = r.bool()?;
+        self.cycles = r.u64()? as usize;
+        Ok(())
+    }
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod dmc_test {
+    use super::*;
+
+    #[test]
+    fn cycles_until_fetch_after_rate_change() {
+        let mut dmc = Dmc::new();
+        dmc.write_sample_length(1);
+        dmc.set_enabled(true);
+        dmc.tick(255);
+        dmc.tick(255);
+        assert_eq!(dmc.cycles_until_fetch(), Some(428 * 8 - 510));
+
+        //1byteの周期(54 * 8)を既に過ぎている
+        dmc.write_flags(0x0f);
+        assert_eq!(dmc.cycles_until_fetch(), Some(0));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::cpu::state::{StateReader, StateWriter};
+use crate::error::NesError;
+
+/// 4-step モードでフレームIRQが発生する周期(CPUサイクル)
+const FOUR_STEP_PERIOD: usize = 29830;
+/// 5-step モードの周期(CPUサイクル)
+const FIVE_STEP_PERIOD: usize = 37282;
+/// 4-step モードのハーフフレーム(長さカウンタの減算)のタイミング
+const FOUR_STEP_HALF_FRAMES: [usize; 2] = [14913, 29829];
+/// 5-step モードのハーフフレームのタイミング
+const FIVE_STEP_HALF_FRAMES: [usize; 2] = [14913, 37281];
+
+/// Frame Counter Struct ($4017)
+///
+/// 7  bit  0
+/// ---- ----
+/// MI.. ....
+/// ||
+/// |+-------- IRQ inhibit flag
+/// +--------- Sequencer mode: 0 selects 4-step sequence, 1 selects 5-step sequence
+///
+/// 4-step モードでIRQ禁止フラグが0の場合、シーケンスの最後にフレームIRQを発生させる.
+#[derive(Debug, Clone)]
+pub struct FrameCounter {
+    pub five_step_mode: bool,
+    pub irq_inhibit: bool,
+    ///フレームIRQフラグ
+    pub irq: bool,
+    cycles: usize,
+}
+
+impl FrameCounter {
+    pub fn new() -> Self {
+        FrameCounter {
+            five_step_mode: false,
+            irq_inhibit: false,
+            irq: false,
+            cycles: 0,
+        }
+    }
+
+    /// $4017 への書き込み. IRQ禁止フラグが立つとフレームIRQはクリアされる.
+    pub fn write(&mut self, data: u8) {
+        self.five_step_mode = data & 0b1000_0000 != 0;
+        self.irq_inhibit = data & 0b0100_0000 != 0;
+        if self.irq_inhibit {
+            self.irq = false;
+        }
+        self.cycles = 0;
+    }
+
+    /// リセット. モードとIRQ禁止フラグは保持し、シーケンサとIRQをクリアする.
+    pub fn reset(&mut self) {
+        self.irq = false;
+        self.cycles = 0;
+    }
+
+    /// CPUサイクル分シーケンサを進め、ハーフフレームに達したかどうかを返す.
+    ///
+    /// # Parameters
+    /// * `cycles` - CPUサイクル
+    pub fn tick(&mut self, cycles: u8) -> bool {
+        let (half_frames, period) = if self.five_step_mode {
+            (FIVE_STEP_HALF_FRAMES, FIVE_STEP_PERIOD)
+        } else {
+            (FOUR_STEP_HALF_FRAMES, FOUR_STEP_PERIOD)
+        };
+        let prev = self.cycles;
+        self.cycles += cycles as usize;
+        let half_frame = half_frames
+            .iter()
+            .any(|&step| prev < step && step <= self.cycles);
+        if self.cycles >= period {
+            self.cycles -= period;
+            if !self.five_step_mode && !self.irq_inhibit {
+                self.irq = true;
+            }
+        }
+        half_frame
+    }
+
+    /// セーブステートに書き出す
+    pub fn write_state(&self, w: &mut StateWriter) {
+        w.bool(self.five_step_mode);
+        w.bool(self.irq_inhibit);
+        w.bool(self.irq);
+        w.u64(self.cycles as u64);
+    }
+
+    /// `write_state`で書き出した状態を読み込む
+    pub fn read_state(&mut self, r: &mut StateReader) -> Result<(), NesError> {
+        self.five_step_mode = r.bool()?;
+        self.irq_inhibit = r.bool()?;
+        self.irq = r.bool()?;
+        self.cycles = r.u64()? as usize;
+        Ok(())
+    }
+}
+
+impl Default for FrameCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
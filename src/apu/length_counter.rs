@@ -0,0 +1,55 @@
+/// 長さカウンタのロード値(0x4003/0x4007/0x400B/0x400F の上位5bitで選択)
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Length Counter Struct
+/// パルス1,2、三角波、ノイズが持つ長さカウンタ.
+/// カウンタが0のチャンネルは無音になり、0x4015の読み出しで対応するビットが0になる.
+///
+/// * `enabled` - 0x4015 のチャンネル有効フラグ. 無効にするとカウンタは0になる
+/// * `halt` - 1の間はフレームカウンタによる減算を止める
+/// * `counter` - 残りの長さ(ハーフフレーム単位)
+#[derive(Debug, Default, Clone)]
+pub struct LengthCounter {
+    pub enabled: bool,
+    pub halt: bool,
+    pub counter: u8,
+}
+
+impl LengthCounter {
+    pub fn new() -> Self {
+        LengthCounter::default()
+    }
+
+    /// 0x4015 による有効/無効の切り替え
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    /// カウンタをロードする. チャンネルが無効な場合は無視する.
+    ///
+    /// # Parameters
+    /// * `index` - LENGTH_TABLE のインデックス(レジスタの上位5bit)
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index & 0b1_1111) as usize];
+        }
+    }
+
+    /// フレームカウンタのハーフフレーム毎の減算
+    pub fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    /// カウンタが残っている(チャンネルが発音中)かどうか
+    pub fn is_active(&self) -> bool {
+        self.counter > 0
+    }
+}
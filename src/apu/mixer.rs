@@ -0,0 +1,100 @@
+/// Mixer Struct
+/// 各チャンネルの出力を非線形のルックアップテーブルで合成する.
+///
+/// * pulse_table\[n\] = 95.52 / (8128.0 / n + 100)  (n = pulse1 + pulse2)
+/// * tnd_table\[n\] = 163.67 / (24329.0 / n + 100)  (n = 3 * triangle + 2 * noise + dmc)
+///
+/// # Reference
+/// * https://www.nesdev.org/wiki/APU_Mixer
+#[derive(Debug, Clone)]
+pub struct Mixer {
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+}
+
+impl Mixer {
+    ///Mixerコンストラクタ
+    pub fn new() -> Self {
+        let mut pulse_table = [0.0; 31];
+        for (n, value) in pulse_table.iter_mut().enumerate().skip(1) {
+            *value = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+        let mut tnd_table = [0.0; 203];
+        for (n, value) in tnd_table.iter_mut().enumerate().skip(1) {
+            *value = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+        Mixer {
+            pulse_table,
+            tnd_table,
+        }
+    }
+
+    /// 各チャンネルの出力(DAC値)を合成する. 戻り値は0.0～1.0.
+    /// 範囲外の値はテーブルの外を読まないよう上限(15または127)に丸める.
+    ///
+    /// # Parameters
+    /// * `pulse1` - 矩形波1 (0～15)
+    /// * `pulse2` - 矩形波2 (0～15)
+    /// * `triangle` - 三角波 (0～15)
+    /// * `noise` - ノイズ (0～15)
+    /// * `dmc` - DMC (0～127)
+    pub fn mix(&self, pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+        let pulse_out = self.pulse_table[(pulse1.min(15) + pulse2.min(15)) as usize];
+        let tnd_index =
+            3 * triangle.min(15) as usize + 2 * noise.min(15) as usize + dmc.min(127) as usize;
+        let tnd_out = self.tnd_table[tnd_index];
+        pulse_out + tnd_out
+    }
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod mixer_test {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 0.0001,
+            "actual: {}, expected: {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn silence() {
+        assert_eq!(Mixer::new().mix(0, 0, 0, 0, 0), 0.0);
+    }
+
+    #[test]
+    fn table_values() {
+        let mixer = Mixer::new();
+        assert_close(mixer.mix(15, 15, 0, 0, 0), 0.25751);
+        assert_close(mixer.mix(8, 0, 0, 0, 0), 0.08559);
+        assert_close(mixer.mix(0, 0, 15, 15, 127), 0.74247);
+        assert_close(mixer.mix(0, 0, 0, 0, 64), 0.34088);
+        assert_close(mixer.mix(15, 15, 15, 15, 127), 0.25751 + 0.74247);
+    }
+
+    #[test]
+    fn nonlinear() {
+        let mixer = Mixer::new();
+        //2つの矩形波を同時に鳴らしても単純な2倍にはならない
+        assert!(mixer.mix(15, 15, 0, 0, 0) < 2.0 * mixer.mix(15, 0, 0, 0, 0));
+    }
+
+    #[test]
+    fn out_of_range_is_clamped() {
+        let mixer = Mixer::new();
+        assert_eq!(
+            mixer.mix(255, 255, 255, 255, 255),
+            mixer.mix(15, 15, 15, 15, 127)
+        );
+        assert_eq!(mixer.mix(16, 0, 0, 0, 128), mixer.mix(15, 0, 0, 0, 127));
+    }
+}
@@ -1,3 +1,10 @@
+#[cfg(test)]
+pub(crate) mod assembler;
 pub mod bus;
 pub mod cpu;
+#[cfg(test)]
+pub(crate) mod flat_bus;
+#[cfg(all(test, feature = "golden"))]
+mod golden;
 pub mod opcodes;
+pub mod state;
@@ -0,0 +1,275 @@
+//! CPUテスト用の簡易アセンブラ.
+//!
+//! 6502アセンブリのサブセットを機械語に変換する. 命令の符号化は`OPCODES_MAP`の定義を使う.
+//!
+//! | 記法 |  detail |
+//! |---------|---------|
+//! |`LDA #$05` `LDA #5` | Immediate(16進数/10進数)|
+//! |`LDA $10` `LDA $10,X` `LDX $10,Y` | ZeroPage(2桁の16進数)|
+//! |`LDA $0200` `LDA $0200,X` `LDA label,Y` | Absolute(4桁の16進数またはラベル)|
+//! |`LDA ($10,X)` `LDA ($10),Y` `JMP ($0200)` | Indirect|
+//! |`ASL` `ASL A` | Accumulator / Implied|
+//! |`BNE label` `BNE $8000` | 相対分岐(分岐先のアドレスを書く)|
+//! |`label:` | ラベル(同じ行に命令を書いてもよい)|
+//! |`; comment` | コメント|
+//!
+//! 非公式命令は`*`を付けずに書く(`LAX $10`). 公式命令と同じ名前の場合は公式命令になる.
+//! テスト用のため、書式の誤りはpanicする.
+use super::cpu::AddressingMode;
+use super::opcodes::{OpCode, CPU_OPS_CODES};
+use std::collections::HashMap;
+
+/// オペランドの値
+enum Value {
+    Number(u16),
+    Label(String),
+}
+
+/// オペランドの書式
+enum Operand {
+    /// オペランドなし、またはアキュムレータ
+    None,
+    Immediate(u8),
+    ZeroPage(u8, Option<char>),
+    Absolute(Value, Option<char>),
+    IndirectX(u8),
+    IndirectY(u8),
+    Indirect(Value),
+}
+
+/// ラベルの参照(アドレスが決まった後に書き込む)
+struct Fixup {
+    pos: usize,
+    label: String,
+    relative: bool,
+}
+
+/// アセンブリを機械語に変換する.
+///
+/// # Parameters
+/// * `origin` - 先頭の命令を配置するアドレス(ラベルと分岐先の計算に使う)
+/// * `source` - アセンブリ(1行に1命令)
+pub fn assemble(origin: u16, source: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut labels = HashMap::new();
+    let mut fixups = Vec::new();
+
+    for line in source.lines() {
+        let mut line = line.split(';').next().unwrap().trim();
+        if let Some((label, rest)) = line.split_once(':') {
+            labels.insert(label.trim().to_string(), origin + bytes.len() as u16);
+            line = rest.trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let (mnemonic, operand) = match line.split_once(char::is_whitespace) {
+            Some((mnemonic, operand)) => (mnemonic, operand.trim()),
+            None => (line, ""),
+        };
+        let mnemonic = mnemonic.to_ascii_uppercase();
+        let operand = parse_operand(operand);
+        let opcode = find_opcode(&mnemonic, &operand)
+            .unwrap_or_else(|| panic!("unsupported instruction: {}", line));
+        bytes.push(opcode.code);
+
+        match operand {
+            Operand::None => {}
+            Operand::Immediate(value) | Operand::IndirectX(value) | Operand::IndirectY(value) => {
+                bytes.push(value)
+            }
+            //ゼロページの命令がない場合はAbsoluteで符号化する
+            Operand::ZeroPage(value, _) if opcode.len == 3 => bytes.extend_from_slice(&[value, 0]),
+            Operand::ZeroPage(value, _) => bytes.push(value),
+            Operand::Absolute(value, _) | Operand::Indirect(value) => {
+                //分岐命令(2byte)は相対アドレス
+                let relative = opcode.len == 2;
+                let target = match value {
+                    Value::Number(addr) => addr,
+                    //ラベルのアドレスは最後に書き込む
+                    Value::Label(label) => {
+                        fixups.push(Fixup {
+                            pos: bytes.len(),
+                            label,
+                            relative,
+                        });
+                        origin + bytes.len() as u16 + 1
+                    }
+                };
+                if relative {
+                    bytes.push(relative_offset(origin, bytes.len(), target));
+                } else {
+                    bytes.extend_from_slice(&target.to_le_bytes());
+                }
+            }
+        }
+    }
+
+    for fixup in fixups {
+        let target = *labels
+            .get(&fixup.label)
+            .unwrap_or_else(|| panic!("undefined label: {}", fixup.label));
+        if fixup.relative {
+            bytes[fixup.pos] = relative_offset(origin, fixup.pos, target);
+        } else {
+            bytes[fixup.pos..fixup.pos + 2].copy_from_slice(&target.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+/// 分岐命令のオペランドの位置`pos`から`target`への相対アドレス
+fn relative_offset(origin: u16, pos: usize, target: u16) -> u8 {
+    let next = origin as i32 + pos as i32 + 1;
+    let offset = target as i32 - next;
+    assert!(
+        (-128..=127).contains(&offset),
+        "branch target ${:04X} is out of range",
+        target
+    );
+    offset as u8
+}
+
+fn parse_number(text: &str) -> u16 {
+    let parsed = match text.strip_prefix('$') {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.unwrap_or_else(|_| panic!("invalid number: {}", text))
+}
+
+fn parse_value(text: &str) -> Value {
+    if text.starts_with('$') || text.starts_with(|c: char| c.is_ascii_digit()) {
+        Value::Number(parse_number(text))
+    } else {
+        Value::Label(text.to_string())
+    }
+}
+
+fn parse_operand(text: &str) -> Operand {
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let upper = text.to_ascii_uppercase();
+    if text.is_empty() || upper == "A" {
+        return Operand::None;
+    }
+    if let Some(value) = text.strip_prefix('#') {
+        return Operand::Immediate(parse_number(value) as u8);
+    }
+    if upper.starts_with('(') {
+        let pointer = &text[1..text.len() - 3];
+        if upper.ends_with(",X)") {
+            return Operand::IndirectX(parse_number(pointer) as u8);
+        }
+        if upper.ends_with("),Y") {
+            return Operand::IndirectY(parse_number(pointer) as u8);
+        }
+        let pointer = text[1..].strip_suffix(')').expect("missing `)`");
+        return Operand::Indirect(parse_value(pointer));
+    }
+    let (value, index) = match text.split_once(',') {
+        Some((value, index)) => (value, index.chars().next().map(|c| c.to_ascii_uppercase())),
+        None => (text.as_str(), None),
+    };
+    //2桁の16進数はゼロページ
+    if value.starts_with('$') && value.len() <= 3 {
+        return Operand::ZeroPage(parse_number(value) as u8, index);
+    }
+    Operand::Absolute(parse_value(value), index)
+}
+
+/// 書式に合う命令を探す. 公式命令を優先し、ゼロページがない場合はAbsoluteで符号化する.
+fn find_opcode(mnemonic: &str, operand: &Operand) -> Option<&'static OpCode> {
+    let modes: Vec<(AddressingMode, Option<u8>)> = match operand {
+        Operand::None => vec![(AddressingMode::NoneAddressing, Some(1))],
+        Operand::Immediate(_) => vec![(AddressingMode::Immediate, None)],
+        Operand::ZeroPage(_, index) => vec![
+            (zero_page_mode(*index), None),
+            (absolute_mode(*index), None),
+        ],
+        Operand::Absolute(_, index) => vec![
+            (absolute_mode(*index), None),
+            //分岐命令
+            (AddressingMode::NoneAddressing, Some(2)),
+            //JMP/JSR
+            (AddressingMode::NoneAddressing, Some(3)),
+        ],
+        Operand::IndirectX(_) => vec![(AddressingMode::Indirect_X, None)],
+        Operand::IndirectY(_) => vec![(AddressingMode::Indirect_Y, None)],
+        Operand::Indirect(_) if mnemonic == "JMP" => {
+            return CPU_OPS_CODES.iter().find(|op| op.code == 0x6c)
+        }
+        Operand::Indirect(_) => return None,
+    };
+    let unofficial = format!("*{}", mnemonic);
+    for (mode, len) in modes {
+        for name in [mnemonic, unofficial.as_str()] {
+            let found = CPU_OPS_CODES.iter().find(|op| {
+                op.mnemonic == name
+                    && op.mode == mode
+                    && len.map_or(true, |len| op.len == len)
+                    && op.code != 0x6c
+            });
+            if found.is_some() {
+                return found;
+            }
+        }
+    }
+    None
+}
+
+fn zero_page_mode(index: Option<char>) -> AddressingMode {
+    match index {
+        Some('X') => AddressingMode::ZeroPage_X,
+        Some('Y') => AddressingMode::ZeroPage_Y,
+        _ => AddressingMode::ZeroPage,
+    }
+}
+
+fn absolute_mode(index: Option<char>) -> AddressingMode {
+    match index {
+        Some('X') => AddressingMode::Absolute_X,
+        Some('Y') => AddressingMode::Absolute_Y,
+        _ => AddressingMode::Absolute,
+    }
+}
+
+#[cfg(test)]
+mod assembler_test {
+    use super::*;
+
+    #[test]
+    fn assemble_modes_and_labels() {
+        let program = assemble(
+            0x8000,
+            "
+            start:  ldx #$03     ; カウンタ
+            loop:   LDA $0200,X
+                    sta ($10),y
+                    ASL A
+                    LAX $10
+                    DEX
+                    BNE loop
+                    LDA $02,Y    ; ゼロページ,YがないのでAbsolute,Y
+                    JSR sub
+                    JMP ($0200)
+            sub:    JMP start
+            ",
+        );
+        assert_eq!(
+            program,
+            vec![
+                0xa2, 0x03, //
+                0xbd, 0x00, 0x02, //
+                0x91, 0x10, //
+                0x0a, //
+                0xa7, 0x10, //
+                0xca, //
+                0xd0, 0xf5, //
+                0xb9, 0x02, 0x00, //
+                0x20, 0x16, 0x80, //
+                0x6c, 0x00, 0x02, //
+                0x4c, 0x00, 0x80,
+            ]
+        );
+    }
+}
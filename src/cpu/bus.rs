@@ -1,95 +1,504 @@
+use crate::apu::apu::Apu;
+use crate::cpu::state::{StateReader, StateWriter};
+use crate::error::NesError;
+use crate::joypad::controller::Controller;
+use crate::joypad::joypad::Joypad;
 use crate::ppu::ppu::Ppu;
 use crate::ppu::ppu::TPpu;
-use crate::{rom::rom::Rom, Memory};
+use crate::rom::cartridge::Cartridge;
+use crate::rom::rom::Mirroring;
+use crate::Memory;
+use std::ops::RangeInclusive;
 
 const RAM: u16 = 0x0000;
 const RAM_MIRRORS_END: u16 = 0x1FFF;
 const PPU_REGISTERS: u16 = 0x2000;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
 
+/// LDA $4016 等の絶対アドレス指定の読み出しは命令の4サイクル目.
+/// Busのサイクルは命令の実行後に進むため、読み出し時点のサイクルからのオフセットになる.
+const CONTROLLER_READ_CYCLE: usize = 4;
+
+const PPU_REGISTER_NAMES: [&str; 8] = [
+    "PPUCTRL",
+    "PPUMASK",
+    "PPUSTATUS",
+    "OAMADDR",
+    "OAMDATA",
+    "PPUSCROLL",
+    "PPUADDR",
+    "PPUDATA",
+];
+
+/// CPUから見たメモリ領域の種類
+///
+/// | address |  region |
+/// |---------|---------|
+/// |0x0000～0x07FF| Ram|
+/// |0x0800～0x1FFF| RamMirror|
+/// |0x2000～0x3FFF| PpuRegister (0x2008以降は0x2000～0x2007のミラー)|
+/// |0x4000～0x4013, 0x4015| ApuRegister|
+/// |0x4014| PpuRegister("OAMDMA")|
+/// |0x4016| Joypad (読み出しはパッド1、書き込みは両ポートのストローブ)|
+/// |0x4017| 読み出しはJoypad(パッド2)、書き込みはApuRegister(フレームカウンタ)|
+/// |0x4018～0x5FFF| Unmapped|
+/// |0x6000～0x7FFF| PrgRam|
+/// |0x8000～0xFFFF| PrgRom|
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MemRegion {
+    Ram,
+    RamMirror,
+    PpuRegister(&'static str),
+    ApuRegister,
+    Joypad,
+    PrgRam,
+    PrgRom,
+    Unmapped,
+}
+
+/// メモリアクセスの方向. 0x4017のように読み書きで接続先が異なるアドレスがある
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MemAccess {
+    Read,
+    Write,
+}
+
+/// CpuBus Trait
+/// CPUが接続するバス. メモリの読み書きに加え、サイクルの進行と割り込み線を扱う.
+/// NESでは`Bus`、CPU単体のテストでは全域をRAMとして扱うバスを使う.
+pub trait CpuBus: Memory {
+    /// CPUサイクル分バスに接続された機器を進める
+    fn tick(&mut self, cycles: u8);
+
+    /// 電源投入からのCPUサイクル数
+    fn cycles(&self) -> usize;
+
+    /// NMIが発生していれば取り出す
+    fn poll_nmi_status(&mut self) -> Option<u8>;
+
+    /// IRQ線の状態
+    fn poll_irq_status(&self) -> bool;
+
+    /// 副作用なしにメモリを読む(デバッガ用)
+    fn peek(&self, addr: u16) -> u8;
+
+    /// 実行中の命令のアドレスを設定する
+    fn set_instruction_pc(&mut self, _pc: u16) {}
+
+    /// 命令の実行後に、書き込まれたOAM DMAを実行する
+    fn run_pending_oam_dma(&mut self) {}
+}
+
+/// 読み出しの監視(アドレスの範囲とコールバック)
+type ReadWatchpoint<'call> = (RangeInclusive<u16>, Box<dyn FnMut(u16) + 'call>);
+
+/// PPUを引数にとるコールバック(ゲームループ用、VBLANK用)
+type PpuCallback<'call> = Box<dyn FnMut(&Ppu) + 'call>;
+
 /// Bus Struct
 /// RAMに直接アクセスできるモジュール
 pub struct Bus<'call> {
     cpu_vram: [u8; 2048],
-    program_data: Vec<u8>,
+    cartridge: Box<dyn Cartridge + 'call>,
     ppu: Ppu,
+    apu: Apu,
     cycles: usize,
-    gameloop_callback: Box<dyn FnMut(&Ppu) + 'call>,
+    frame_complete: bool,
+    gameloop_callback: PpuCallback<'call>,
+    ///VBLANKの開始(スキャンライン241)で呼ばれるコールバック
+    vblank_callback: Option<PpuCallback<'call>>,
+    ///コントローラポート1(0x4016)と2(0x4017)に接続された入力機器
+    controllers: [Box<dyn Controller + 'call>; 2],
+    ///RAMの各バイトが書き込み済みかどうか(1bit/1byte)
+    ram_written: [u8; 256],
+    ///未初期化RAMの読み出しを通知するコールバック
+    uninit_read_callback: Option<Box<dyn FnMut(u16, u16) + 'call>>,
+    ///読み出しを監視するアドレスの範囲と、読み出し毎に呼ばれるコールバック
+    read_watchpoint: Option<ReadWatchpoint<'call>>,
+    ///実行中の命令のアドレス
+    instruction_pc: u16,
+    ///DMCのDMAとコントローラの読み出しが重なった場合の読み出しの破損を再現する
+    dmc_controller_glitch: bool,
+    ///0x4014に書き込まれた、未転送のOAM DMAのページ
+    oam_dma_page: Option<u8>,
+    ///カートリッジの代わりに使用するミラーリング(デバッグ用)
+    forced_mirroring: Option<Mirroring>,
 }
 
 impl<'a> Bus<'a> {
     /// Busコンストラクタ
     ///
     /// # Parameters
-    /// * `rom` - Rom
+    /// * `cartridge` - カートリッジ(Rom等)
     /// * `gameloop_callback` - ループ処理用コールバック
-    pub fn new<'call, F>(rom: Rom, gameloop_callback: F) -> Bus<'call>
+    pub fn new<'call, C, F>(cartridge: C, gameloop_callback: F) -> Bus<'call>
     where
+        C: Cartridge + 'call,
         F: FnMut(&Ppu) + 'call,
     {
         //PPU作成
-        let ppu = Ppu::new_ppu(rom.char_data, rom.screen_mirroring);
+        let ppu = Ppu::from_cartridge(&cartridge);
 
         Bus {
             cpu_vram: [0; 2048],
-            program_data: rom.program_data,
+            cartridge: Box::new(cartridge),
             ppu,
+            apu: Apu::new(),
             cycles: 0,
+            frame_complete: false,
             gameloop_callback: Box::from(gameloop_callback),
+            vblank_callback: None,
+            controllers: [Box::new(Joypad::new()), Box::new(Joypad::new())],
+            ram_written: [0; 256],
+            uninit_read_callback: None,
+            read_watchpoint: None,
+            dmc_controller_glitch: false,
+            oam_dma_page: None,
+            instruction_pc: 0,
+            forced_mirroring: None,
         }
     }
 
-    fn read_program_data(&self, mut addr: u16) -> u8 {
-        addr -= 0x8000;
-        if self.program_data.len() == 0x4000 && addr >= 0x4000 {
-            addr %= 0x4000;
+    /// コントローラポートに入力機器を接続する. 初期状態は標準コントローラ.
+    ///
+    /// # Parameters
+    /// * `port` - ポート番号(0: 0x4016, 1: 0x4017)
+    /// * `controller` - 入力機器
+    pub fn set_controller<C>(&mut self, port: usize, controller: C)
+    where
+        C: Controller + 'a,
+    {
+        self.controllers[port] = Box::new(controller);
+    }
+
+    /// 未初期化RAMの読み出しを検出するモードを有効にする.
+    /// RAMは一度書き込まれるまで未初期化として扱われ、未初期化のバイトを読み出すと
+    /// `callback` が読み出したアドレス(0x0000～0x07FF)と命令のアドレス(PC)を引数に呼ばれる.
+    ///
+    /// # Parameters
+    /// * `callback` - 読み出したアドレスとPCを引数にとるクロージャ
+    pub fn set_uninit_read_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(u16, u16) + 'a,
+    {
+        self.ram_written = [0; 256];
+        self.uninit_read_callback = Some(Box::new(callback));
+    }
+
+    /// 読み出しのウォッチポイントを設定する. `range`内のアドレスを読み出す毎に
+    /// `callback`が読み出したアドレスを引数に呼ばれる.
+    /// CPUとDMAの読み出しが対象で、副作用のない`peek`は対象外.
+    ///
+    /// # Parameters
+    /// * `range` - 監視するアドレスの範囲
+    /// * `callback` - 読み出したアドレスを引数にとるクロージャ
+    pub fn set_read_watchpoint<F>(&mut self, range: RangeInclusive<u16>, callback: F)
+    where
+        F: FnMut(u16) + 'a,
+    {
+        self.read_watchpoint = Some((range, Box::new(callback)));
+    }
+
+    /// VBLANKの開始(VBLANKフラグが立つスキャンライン241)で呼ばれるコールバックを設定する.
+    /// フレームの終わりで呼ばれるゲームループ用コールバックとは別に、1フレームに1回呼ばれる.
+    ///
+    /// # Parameters
+    /// * `callback` - PPUを引数にとるクロージャ
+    pub fn set_vblank_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(&Ppu) + 'a,
+    {
+        self.vblank_callback = Some(Box::new(callback));
+    }
+
+    /// DMCのDMAとコントローラの読み出しが重なった場合の破損を再現するかどうか(デフォルトfalse).
+    ///
+    /// 実機ではDMCのサンプル読み出し(DMA)が0x4016/0x4017の読み出しと重なると、
+    /// コントローラが2回クロックされて1bit読み飛ばされる.
+    /// DMCを使うゲームの一部(スーパーマリオブラザーズ3等)は、同じ値が2回続けて読めるまで
+    /// コントローラを読み直してこれを回避しており、回避していないゲームでは入力が化ける.
+    ///
+    /// DMA自体は実装していないため、DMCが1byteを再生し終えるサイクルが
+    /// 絶対アドレス指定の命令の読み出しサイクルと一致した場合に破損させる.
+    ///
+    /// # Parameters
+    /// * `enabled` - 再現するかどうか
+    pub fn set_dmc_controller_glitch(&mut self, enabled: bool) {
+        self.dmc_controller_glitch = enabled;
+    }
+
+    /// DMCによるコントローラの読み飛ばしを再現するかどうか(`set_dmc_controller_glitch`)
+    pub fn dmc_controller_glitch(&self) -> bool {
+        self.dmc_controller_glitch
+    }
+
+    /// カートリッジのミラーリングを上書きする(マッパーのミラーリングのデバッグ用).
+    /// 上書き中はマッパーがミラーリングを切り替えてもPPUに反映しない.
+    ///
+    /// # Parameters
+    /// * `mirroring` - 使用するミラーリング. Noneの場合はカートリッジのミラーリングに戻す
+    pub fn force_mirroring(&mut self, mirroring: Option<Mirroring>) {
+        self.forced_mirroring = mirroring;
+        let mirroring = self
+            .forced_mirroring
+            .clone()
+            .unwrap_or_else(|| self.cartridge.mirroring());
+        self.ppu.set_mirroring(mirroring);
+    }
+
+    /// コントローラの読み出し. DMCの読み出しと重なった場合は2回クロックした値を返す
+    fn read_controller(&mut self, port: usize) -> u8 {
+        if self.dmc_controller_glitch
+            && self.apu.dmc.cycles_until_fetch() == Some(CONTROLLER_READ_CYCLE)
+        {
+            self.controllers[port].read();
         }
-        self.program_data[addr as usize]
+        self.controllers[port].read()
+    }
+
+    /// 実行中の命令のアドレスを設定する. 未初期化RAMの読み出しの通知に使う.
+    pub(crate) fn set_instruction_pc(&mut self, pc: u16) {
+        self.instruction_pc = pc;
+    }
+
+    /// アドレスがどのメモリ領域に対応するかを返す.
+    /// `mem_read`/`mem_write`の振り分けと同じ対応になる.
+    ///
+    /// # Parameters
+    /// * `addr` - CPUから見たアドレス
+    /// * `access` - 読み出しか書き込みか
+    pub fn classify(addr: u16, access: MemAccess) -> MemRegion {
+        match addr {
+            RAM..=0x07FF => MemRegion::Ram,
+            0x0800..=RAM_MIRRORS_END => MemRegion::RamMirror,
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                MemRegion::PpuRegister(PPU_REGISTER_NAMES[(addr & 0b111) as usize])
+            }
+            0x4014 => MemRegion::PpuRegister("OAMDMA"),
+            0x4000..=0x4013 | 0x4015 => MemRegion::ApuRegister,
+            0x4016 => MemRegion::Joypad,
+            0x4017 => match access {
+                MemAccess::Read => MemRegion::Joypad,
+                MemAccess::Write => MemRegion::ApuRegister,
+            },
+            0x6000..=0x7FFF => MemRegion::PrgRam,
+            0x8000..=0xFFFF => MemRegion::PrgRom,
+            _ => MemRegion::Unmapped,
+        }
+    }
+
+    /// 電源投入からのCPUサイクル数
+    pub fn cycles(&self) -> usize {
+        self.cycles
     }
 
     pub fn tick(&mut self, cycles: u8) {
         self.cycles += cycles as usize;
+        self.apu.tick(cycles);
         let new_frame = self.ppu.tick(cycles * 3);
+        if self.ppu.take_vblank_started() {
+            if let Some(callback) = &mut self.vblank_callback {
+                callback(&self.ppu);
+            }
+        }
         if new_frame {
+            self.frame_complete = true;
             (self.gameloop_callback)(&self.ppu);
         }
     }
 
+    /// カートリッジを差し替える. 電源を入れ直した状態と同様に
+    /// RAM/PPU/APUを初期化する. ゲームループ用コールバックとPPUの描画方式、
+    /// スプライト制限の設定はそのまま使用する.
+    ///
+    /// # Parameters
+    /// * `cartridge` - 新しいカートリッジ
+    pub fn load_cartridge<C>(&mut self, cartridge: C)
+    where
+        C: Cartridge + 'a,
+    {
+        let mode = self.ppu.mode();
+        let disable_sprite_limit = self.ppu.disable_sprite_limit;
+        self.ppu = Ppu::from_cartridge(&cartridge);
+        self.ppu.set_mode(mode);
+        self.ppu.disable_sprite_limit = disable_sprite_limit;
+        let audio = self.apu.samples_enabled();
+        self.apu = Apu::new();
+        if audio {
+            self.apu.enable_samples();
+        }
+        self.cartridge = Box::new(cartridge);
+        self.cpu_vram = [0; 2048];
+        self.ram_written = [0; 256];
+        self.cycles = 0;
+        self.frame_complete = false;
+        self.forced_mirroring = None;
+    }
+
+    /// リセットボタンによるPPU/APUのリセット.
+    pub fn reset(&mut self) {
+        self.ppu.reset();
+        self.apu.reset();
+    }
+
+    /// 0x4014への書き込みによるOAM DMAを実行する. 転送の間CPUは停止する(サイクルを進める).
+    /// 書き込んだ命令の実行後に呼ぶ. 停止中もPPU/APUは動作し続ける.
+    ///
+    /// 書き込みの次のサイクル(停止を始めるサイクル)が偶数なら513、奇数なら514サイクル停止する.
+    /// 最初の1～2サイクルは待機で、その後は2サイクル毎に1byteを読み出してOAMに書き込むため、
+    /// 転送中のPPUからは書き込みが1byteずつ進んで見える.
+    /// 描画中のDMAがスプライト評価に与える影響は再現していない.
+    pub fn run_pending_oam_dma(&mut self) {
+        let hi = match self.oam_dma_page.take() {
+            Some(page) => (page as u16) << 8,
+            None => return,
+        };
+        let align_cycles = if self.cycles % 2 == 1 { 2 } else { 1 };
+        for _ in 0..align_cycles {
+            self.tick(1);
+        }
+        for i in 0..256u16 {
+            self.tick(1);
+            let data = self.mem_read(hi + i);
+            self.tick(1);
+            self.ppu.write_oam_dma_byte(data);
+        }
+    }
+
+    /// 前回呼び出し以降にフレームが完了していればtrueを返す.
+    pub fn take_frame_complete(&mut self) -> bool {
+        std::mem::take(&mut self.frame_complete)
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
     pub fn poll_nmi_status(&mut self) -> Option<u8> {
         self.ppu.nmi_interrupt.take()
     }
-}
 
-impl Memory for Bus<'_> {
-    fn mem_read(&mut self, addr: u16) -> u8 {
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    /// CPUのRAM(0x0000～0x07FF)
+    pub(crate) fn ram(&self) -> &[u8; 2048] {
+        &self.cpu_vram
+    }
+
+    /// セーブステートに含めるカートリッジの状態(`Cartridge::write_state`)
+    pub(crate) fn cartridge_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        self.cartridge.write_state(&mut w);
+        w.into_bytes()
+    }
+
+    /// セーブステートからRAM/PPU/APU、サイクル数とカートリッジの状態を戻す.
+    /// コールバックと入力機器はそのまま使用する.
+    /// カートリッジの状態が不正な場合はRAM/PPU/APUを変更せずに`NesError::InvalidState`を返す.
+    pub(crate) fn restore(
+        &mut self,
+        ram: &[u8; 2048],
+        ppu: &Ppu,
+        apu: &Apu,
+        cycles: usize,
+        cartridge: &[u8],
+    ) -> Result<(), NesError> {
+        let mut r = StateReader::new(cartridge);
+        self.cartridge.read_state(&mut r)?;
+        if r.remaining() != 0 {
+            return Err(NesError::InvalidState("trailing cartridge data"));
+        }
+        self.cpu_vram = *ram;
+        self.ppu = ppu.clone();
+        self.apu = apu.clone();
+        self.cycles = cycles;
+        self.frame_complete = false;
+        self.oam_dma_page = None;
+        Ok(())
+    }
+
+    /// IRQ線(APUのフレームIRQとDMC IRQの論理和)の状態
+    pub fn poll_irq_status(&self) -> bool {
+        self.apu.irq()
+    }
+
+    /// APUのサンプル生成を開始する
+    pub fn enable_audio(&mut self) {
+        self.apu.enable_samples();
+    }
+
+    /// APUが生成し、取り出されていないサンプルの数
+    pub fn queued_audio(&self) -> usize {
+        self.apu.queued_samples()
+    }
+
+    /// 前回の呼び出し以降にAPUが生成したサンプルを取り出す
+    pub fn take_audio(&mut self) -> Vec<f32> {
+        self.apu.take_samples()
+    }
+
+    /// カートリッジのPRG-RAM全体(セーブRAMの保存用)
+    pub fn prg_ram(&self) -> &[u8] {
+        self.cartridge.prg_ram()
+    }
+
+    /// カートリッジが無視したPRG領域への書き込みの回数(診断用)
+    pub fn ignored_cartridge_writes(&self) -> u64 {
+        self.cartridge.ignored_writes()
+    }
+
+    /// 副作用なしにメモリを読む(デバッガ用).
+    /// 読み出しで状態が変わるPPU/APU/コントローラのレジスタは0を返す.
+    ///
+    /// # Parameters
+    /// * `addr` - アドレス
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM..=RAM_MIRRORS_END => self.cpu_vram[(addr & 0b0000_0111_1111_1111) as usize],
+            0x6000..=0x7FFF => self.cartridge.read_prg_ram(addr),
+            0x8000..=0xFFFF => self.cartridge.read_prg(addr),
+            _ => 0,
+        }
+    }
+
+    /// アドレスに対応するRAM/レジスタ/カートリッジからの読み出し(ウォッチポイントの通知なし)
+    fn read_mapped(&mut self, addr: u16) -> u8 {
         match addr {
             RAM..=RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b0000_0111_1111_1111;
+                if let Some(callback) = &mut self.uninit_read_callback {
+                    let written = self.ram_written[(mirror_down_addr / 8) as usize]
+                        & (1 << (mirror_down_addr % 8));
+                    if written == 0 {
+                        callback(mirror_down_addr, self.instruction_pc);
+                    }
+                }
                 self.cpu_vram[mirror_down_addr as usize]
             }
             0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 | 0x4014 => 0,
             0x2002 => self.ppu.read_status(),
             0x2004 => self.ppu.read_oam_data(),
             0x2007 => self.ppu.read_data(),
-            0x4000..=0x4015 => {
+            0x4000..=0x4014 => {
                 //ignore APU
                 0
             }
+            0x4015 => self.apu.read_status(),
 
-            0x4016 => {
-                // ignore joypad 1;
-                0
-            }
-
-            0x4017 => {
-                // ignore joypad 2
-                0
-            }
+            0x4016 => self.read_controller(0),
+            0x4017 => self.read_controller(1),
             0x2008..=PPU_REGISTERS_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b0010_0000_0000_0111;
-                self.mem_read(mirror_down_addr)
+                self.read_mapped(mirror_down_addr)
             }
-            0x8000..=0xFFFF => self.read_program_data(addr),
+            0x6000..=0x7FFF => self.cartridge.read_prg_ram(addr),
+            0x8000..=0xFFFF => self.cartridge.read_prg(addr),
 
             _ => {
                 println!("Ignoring mem access at {}", addr);
@@ -97,12 +506,24 @@ impl Memory for Bus<'_> {
             }
         }
     }
+}
+
+impl Memory for Bus<'_> {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        if let Some((range, callback)) = &mut self.read_watchpoint {
+            if range.contains(&addr) {
+                callback(addr);
+            }
+        }
+        self.read_mapped(addr)
+    }
 
     fn mem_write(&mut self, addr: u16, data: u8) {
         match addr {
             RAM..=RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b11111111111;
                 self.cpu_vram[mirror_down_addr as usize] = data;
+                self.ram_written[(mirror_down_addr / 8) as usize] |= 1 << (mirror_down_addr % 8);
             }
             0x2000 => {
                 self.ppu.write_to_ctrl(data);
@@ -127,38 +548,43 @@ impl Memory for Bus<'_> {
             0x2007 => {
                 self.ppu.write_to_data(data);
             }
-            0x4000..=0x4013 | 0x4015 => {
+            0x4010 => self.apu.write_dmc_flags(data),
+            0x4012 => self.apu.dmc.write_sample_addr(data),
+            0x4013 => self.apu.dmc.write_sample_length(data),
+            0x4015 => self.apu.write_status(data),
+            0x4000..=0x400f => self.apu.write_channel(addr, data),
+            0x4011 => {
                 //ignore APU
             }
 
+            //ストローブは両方のポートに接続される
             0x4016 => {
-                // ignore joypad 1;
+                for controller in self.controllers.iter_mut() {
+                    controller.write(data);
+                }
             }
 
-            0x4017 => {
-                // ignore joypad 2
-            }
+            0x4017 => self.apu.write_frame_counter(data),
 
             // https://wiki.nesdev.com/w/index.php/PPU_programmer_reference#OAM_DMA_.28.244014.29_.3E_write
             0x4014 => {
-                let mut buffer: [u8; 256] = [0; 256];
-                let hi: u16 = (data as u16) << 8;
-                for i in 0..256u16 {
-                    buffer[i as usize] = self.mem_read(hi + i);
-                }
-
-                self.ppu.write_oam_dma(&buffer);
-
-                // todo: handle this eventually
-                // let add_cycles: u16 = if self.cycles % 2 == 1 { 514 } else { 513 };
-                // self.tick(add_cycles); //todo this will cause weird effects as PPU will have 513/514 * 3 ticks
+                //転送とCPUの停止は書き込んだ命令の実行後(`run_pending_oam_dma`)
+                self.oam_dma_page = Some(data);
             }
 
             0x2008..=PPU_REGISTERS_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b0010_0000_0000_0111;
                 self.mem_write(mirror_down_addr, data);
             }
-            0x8000..=0xFFFF => panic!("Attempt to write to Cartridge ROM space: {:x}", addr),
+            0x6000..=0x7FFF => self.cartridge.write_prg_ram(addr, data),
+            0x8000..=0xFFFF => {
+                self.cartridge.write_prg(addr, data);
+                //マッパーのレジスタ書き込みでミラーリングが変わった場合は即座にPPUへ反映する
+                let mirroring = self.cartridge.mirroring();
+                if self.forced_mirroring.is_none() && self.ppu.mirroring != mirroring {
+                    self.ppu.set_mirroring(mirroring);
+                }
+            }
 
             _ => {
                 println!("Ignoring mem write-access at {}", addr);
@@ -166,3 +592,300 @@ impl Memory for Bus<'_> {
         }
     }
 }
+
+impl CpuBus for Bus<'_> {
+    fn tick(&mut self, cycles: u8) {
+        Bus::tick(self, cycles)
+    }
+
+    fn cycles(&self) -> usize {
+        Bus::cycles(self)
+    }
+
+    fn poll_nmi_status(&mut self) -> Option<u8> {
+        Bus::poll_nmi_status(self)
+    }
+
+    fn poll_irq_status(&self) -> bool {
+        Bus::poll_irq_status(self)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        Bus::peek(self, addr)
+    }
+
+    fn set_instruction_pc(&mut self, pc: u16) {
+        Bus::set_instruction_pc(self, pc)
+    }
+
+    fn run_pending_oam_dma(&mut self) {
+        Bus::run_pending_oam_dma(self)
+    }
+}
+
+#[cfg(test)]
+mod bus_test {
+    use super::*;
+    use crate::cpu::cpu::Cpu;
+    use crate::ppu::ppu::SpriteEntry;
+    use crate::rom::cartridge::RamCartridge;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn classify() {
+        use MemAccess::{Read, Write};
+
+        assert_eq!(Bus::classify(0x0000, Read), MemRegion::Ram);
+        assert_eq!(Bus::classify(0x07ff, Read), MemRegion::Ram);
+        assert_eq!(Bus::classify(0x0800, Read), MemRegion::RamMirror);
+        assert_eq!(Bus::classify(0x1fff, Read), MemRegion::RamMirror);
+        assert_eq!(
+            Bus::classify(0x2000, Read),
+            MemRegion::PpuRegister("PPUCTRL")
+        );
+        assert_eq!(
+            Bus::classify(0x2007, Read),
+            MemRegion::PpuRegister("PPUDATA")
+        );
+        assert_eq!(
+            Bus::classify(0x3ffa, Read),
+            MemRegion::PpuRegister("PPUSTATUS")
+        );
+        assert_eq!(Bus::classify(0x4000, Read), MemRegion::ApuRegister);
+        assert_eq!(
+            Bus::classify(0x4014, Read),
+            MemRegion::PpuRegister("OAMDMA")
+        );
+        assert_eq!(Bus::classify(0x4015, Read), MemRegion::ApuRegister);
+        assert_eq!(Bus::classify(0x4016, Read), MemRegion::Joypad);
+        assert_eq!(Bus::classify(0x4017, Read), MemRegion::Joypad);
+        assert_eq!(Bus::classify(0x4020, Read), MemRegion::Unmapped);
+        assert_eq!(Bus::classify(0x6000, Read), MemRegion::PrgRam);
+        assert_eq!(Bus::classify(0x8000, Read), MemRegion::PrgRom);
+        assert_eq!(Bus::classify(0xffff, Read), MemRegion::PrgRom);
+
+        //0x4016の書き込みはストローブ、0x4017の書き込みはAPUのフレームカウンタ
+        assert_eq!(Bus::classify(0x4016, Write), MemRegion::Joypad);
+        assert_eq!(Bus::classify(0x4017, Write), MemRegion::ApuRegister);
+        assert_eq!(
+            Bus::classify(0x2000, Write),
+            MemRegion::PpuRegister("PPUCTRL")
+        );
+        assert_eq!(
+            Bus::classify(0x4014, Write),
+            MemRegion::PpuRegister("OAMDMA")
+        );
+    }
+
+    #[test]
+    fn vblank_callback_once_per_frame() {
+        let vblanks = Rc::new(RefCell::new(vec![]));
+        let frames = Rc::new(RefCell::new(0));
+        let frame_sink = frames.clone();
+        let mut bus = Bus::new(RamCartridge::new(&[], 0x2000), move |_| {
+            *frame_sink.borrow_mut() += 1
+        });
+        let sink = vblanks.clone();
+        bus.set_vblank_callback(move |ppu| {
+            sink.borrow_mut()
+                .push((ppu.scanline(), ppu.status.snapshot() & 0x80 != 0))
+        });
+
+        while *frames.borrow() < 3 {
+            bus.tick(1);
+        }
+        assert_eq!(*vblanks.borrow(), vec![(241, true); 3]);
+    }
+
+    #[test]
+    fn uninit_ram_read_callback() {
+        let program = [
+            0xa5, 0x10, //       LDA $10 (未初期化)
+            0x8d, 0x10, 0x08, // STA $0810 (0x0010のミラー)
+            0xa5, 0x10, //       LDA $10
+            0x00,
+        ];
+        let reports = Rc::new(RefCell::new(vec![]));
+        let mut bus = Bus::new(RamCartridge::new(&program, 0x2000), |_| {});
+        let sink = reports.clone();
+        bus.set_uninit_read_callback(move |addr, pc| sink.borrow_mut().push((addr, pc)));
+
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.run();
+
+        assert_eq!(*reports.borrow(), vec![(0x0010, 0x8000)]);
+    }
+
+    struct FixedController(u8);
+
+    impl Controller for FixedController {
+        fn read(&mut self) -> u8 {
+            self.0
+        }
+
+        fn write(&mut self, _data: u8) {}
+    }
+
+    #[test]
+    fn controller_ports() {
+        let mut bus = Bus::new(RamCartridge::new(&[], 0x2000), |_| {});
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        for _ in 0..8 {
+            assert_eq!(bus.mem_read(0x4016), 0);
+        }
+        assert_eq!(bus.mem_read(0x4016), 1);
+
+        bus.set_controller(1, FixedController(0x42));
+        assert_eq!(bus.mem_read(0x4017), 0x42);
+        assert_eq!(bus.mem_read(0x4017), 0x42);
+    }
+
+    /// 読み出す毎に0, 1, 2...を返すコントローラ
+    struct CountingController(u8);
+
+    impl Controller for CountingController {
+        fn read(&mut self) -> u8 {
+            self.0 += 1;
+            self.0 - 1
+        }
+
+        fn write(&mut self, _data: u8) {}
+    }
+
+    #[test]
+    fn dmc_fetch_corrupts_controller_read() {
+        for glitch in [false, true] {
+            let mut bus = Bus::new(RamCartridge::new(&[], 0x2000), |_| {});
+            bus.set_controller(0, CountingController(0));
+            bus.set_dmc_controller_glitch(glitch);
+            //レート15(54サイクル/bit), 17byteのサンプルを再生
+            bus.mem_write(0x4010, 0x0f);
+            bus.mem_write(0x4013, 0x01);
+            bus.mem_write(0x4015, 0x10);
+            //重ならない読み出しは破損しない
+            assert_eq!(bus.mem_read(0x4016), 0);
+
+            //命令の4サイクル目でDMCが読み出す
+            for _ in 0..54 * 8 - CONTROLLER_READ_CYCLE {
+                bus.tick(1);
+            }
+            let expected = if glitch { 2 } else { 1 };
+            assert_eq!(bus.mem_read(0x4016), expected);
+        }
+    }
+
+    /// 0x8000への書き込みのbit0でミラーリングを切り替えるカートリッジ
+    struct MirroringCartridge(Mirroring);
+
+    impl Cartridge for MirroringCartridge {
+        fn read_prg(&self, _addr: u16) -> u8 {
+            0
+        }
+
+        fn write_prg(&mut self, _addr: u16, data: u8) {
+            self.0 = if data & 1 == 1 {
+                Mirroring::HORIZONTAL
+            } else {
+                Mirroring::VERTICAL
+            };
+        }
+
+        fn char_data(&self) -> Vec<u8> {
+            vec![0; 0x2000]
+        }
+
+        fn mirroring(&self) -> Mirroring {
+            self.0.clone()
+        }
+    }
+
+    #[test]
+    fn mapper_mirroring_change_reaches_ppu() {
+        let mut bus = Bus::new(MirroringCartridge(Mirroring::VERTICAL), |_| {});
+        assert_eq!(bus.ppu().mirror_vram_addr(0x2400), 0x400);
+
+        bus.mem_write(0x8000, 1);
+        assert_eq!(bus.ppu().mirroring, Mirroring::HORIZONTAL);
+        assert_eq!(bus.ppu().mirror_vram_addr(0x2400), 0x000);
+    }
+
+    #[test]
+    fn oam_dma_advances_ppu() {
+        let mut bus = Bus::new(RamCartridge::new(&[], 0x2000), |_| {});
+        bus.mem_write(0x0205, 0x42);
+
+        //スキャンラインの境界の直前まで進める(110 * 3 = 330ドット)
+        for _ in 0..110 {
+            bus.tick(1);
+        }
+        assert_eq!(bus.ppu().scanline(), 0);
+
+        bus.mem_write(0x4014, 0x02);
+        bus.run_pending_oam_dma();
+
+        //偶数サイクルから開始したので513サイクル
+        assert_eq!(bus.cycles(), 110 + 513);
+        //(330 + 513 * 3) / 341 = 5
+        assert_eq!(bus.ppu().scanline(), 5);
+        assert_eq!(bus.ppu().oam_data[5], 0x42);
+    }
+
+    #[test]
+    fn oam_dma_interleaves_with_ppu() {
+        let transferred = RefCell::new(None);
+        let mut bus = Bus::new(RamCartridge::new(&[], 0x2000), |_| {});
+        bus.set_vblank_callback(|ppu| {
+            let count = ppu.oam_data.iter().filter(|&&data| data != 0).count();
+            *transferred.borrow_mut() = Some(count);
+        });
+        for i in 0..256u16 {
+            bus.mem_write(0x0200 + i, 0x80);
+        }
+
+        //scanline 240の60ドット目(27300 * 3 = 81900ドット)から開始する
+        for _ in 0..27300 {
+            bus.tick(1);
+        }
+        bus.mem_write(0x4014, 0x02);
+        assert!(transferred.borrow().is_none());
+        bus.run_pending_oam_dma();
+
+        //VBLANKは94サイクル目((341 - 60) / 3 の切り上げ)に始まる.
+        //待機1サイクルの後は2サイクル毎に1byte書き込むので、その時点で46byte転送済み
+        assert_eq!(*transferred.borrow(), Some(46));
+        assert_eq!(bus.ppu().scanline(), 244);
+        assert!(bus.ppu().oam_data.iter().all(|&data| data == 0x80));
+    }
+
+    #[test]
+    fn oam_dma_decoded_sprite() {
+        let mut bus = Bus::new(RamCartridge::new(&[], 0x2000), |_| {});
+        //スプライト3: Y=0x40, タイル0x12, 上下反転+背景の後ろ+パレット2, X=0x80
+        for (i, data) in [0x40, 0x12, 0b1010_0010, 0x80].iter().enumerate() {
+            bus.mem_write(0x0200 + 3 * 4 + i as u16, *data);
+        }
+        bus.mem_write(0x4014, 0x02);
+        bus.run_pending_oam_dma();
+
+        let sprites = bus.ppu().sprites();
+        assert_eq!(sprites.len(), 64);
+        assert_eq!(
+            sprites[3],
+            SpriteEntry {
+                index: 3,
+                y: 0x40,
+                tile: 0x12,
+                attributes: 0b1010_0010,
+                x: 0x80,
+                palette: 2,
+                behind_background: true,
+                flip_horizontal: false,
+                flip_vertical: true,
+            }
+        );
+        assert_eq!(bus.ppu().oam()[12..16], [0x40, 0x12, 0b1010_0010, 0x80]);
+    }
+}
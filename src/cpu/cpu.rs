@@ -1,6 +1,10 @@
+use super::bus::CpuBus;
 use super::opcodes;
+use super::state::{CpuRegisters, CpuState, MachineState};
+use crate::error::NesError;
 use crate::Bus;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::marker::PhantomData;
 
 bitflags! {
     /// # Status Register (P) http://wiki.nesdev.com/w/index.php/Status_flags
@@ -42,6 +46,19 @@ bitflags! {
     }
 }
 
+/// CPUの種類
+///
+/// | variant |  detail |
+/// |---------|---------|
+/// |Nes2A03 | NES(ファミコン)のCPU. デシマルモードは無効 (デフォルト)|
+/// |Mos6502 | 汎用の6502(NMOS). Dフラグが立っているとADC/SBCはBCD演算を行う|
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CpuVariant {
+    #[default]
+    Nes2A03,
+    Mos6502,
+}
+
 const STACK: u16 = 0x0100;
 const STACK_RESET: u8 = 0xfd;
 
@@ -60,7 +77,7 @@ const STACK_RESET: u8 = 0xfd;
 /// |S	 |8bit |	スタックポインタ|
 /// |P	 |8bit |	ステータスレジスタ|
 /// |PC	|16bit|	  プログラムカウンタ|
-pub struct Cpu<'a> {
+pub struct Cpu<'a, B = Bus<'a>> {
     pub reg_a: u8,
     pub reg_x: u8,
     pub reg_y: u8,
@@ -68,13 +85,56 @@ pub struct Cpu<'a> {
     pub status: CpuFlags,
     pub reg_pc: u16,
     //pub memory: [u8; 0xFFFF],
-    pub bus: Bus<'a>,
+    pub bus: B,
+    ///IRQの受付判定に使う割り込み禁止フラグ.
+    ///CLI/SEI/PLPによるIフラグの変更は次の命令の実行後まで反映されない.
+    irq_disable_delayed: bool,
+    ///命令の最後から2番目のサイクルまでに認識したNMI. 次の命令の前に処理する.
+    nmi_pending: bool,
+    variant: CpuVariant,
+    ///命令のアドレス毎の消費サイクル数(プロファイラ). Noneの場合は計測しない.
+    profile: Option<HashMap<u16, u64>>,
+    ///リセットベクタ(0xFFFC)の代わりに使う開始アドレス(デバッグ用)
+    entry_point: Option<u16>,
+    ///直近に実行した命令のトレース(リングバッファ, 最大件数). Noneの場合は記録しない.
+    trace: Option<(VecDeque<String>, usize)>,
+    ///BRKで実行を停止するかどうか. falseの場合はBRKを割り込みとして実行する.
+    halt_on_brk: bool,
+    ///実行中の命令のオペランドから求めたアドレス. オペランドは命令毎に1回だけ読み出す.
+    operand: Option<Operand>,
+    bus_lifetime: PhantomData<&'a ()>,
+}
+
+/// オペランドから求めたアドレス
+///
+/// | name |  detail |
+/// |---------|---------|
+/// |addr | 実効アドレス|
+/// |base | インデックスを加算する前のアドレス(ページをまたぐかどうかの判定とダミーリードに使う)|
+#[derive(Debug, Clone, Copy)]
+struct Operand {
+    addr: u16,
+    base: u16,
+}
+
+/// `Cpu::run_until` が停止した理由
+///
+/// | name |  detail |
+/// |---------|---------|
+/// |Reached | 指定したアドレスに到達した|
+/// |Halted | BRKで停止した|
+/// |InstructionLimit | 指定した命令数を実行しても到達しなかった|
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StopReason {
+    Reached,
+    Halted,
+    InstructionLimit,
 }
 
 /// Addressing Mode
 /// CPUが命令ストリームの次の1バイト or 2バイトを
 /// どのように解釈するか定義する
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum AddressingMode {
     Immediate,
@@ -98,19 +158,29 @@ pub trait Memory {
 
     fn mem_read_u16(&mut self, pos: u16) -> u16 {
         let lo = self.mem_read(pos) as u16;
-        let hi = self.mem_read(pos + 1) as u16;
+        let hi = self.mem_read(pos.wrapping_add(1)) as u16;
         (hi << 8) | (lo as u16)
     }
 
+    /// ゼロページのポインタを読み出す. 0xFFの上位バイトは0x00から読み出す.
+    ///
+    /// # Parameters
+    /// * `pos` - ゼロページのアドレス
+    fn mem_read_zp_u16(&mut self, pos: u8) -> u16 {
+        let lo = self.mem_read(pos as u16) as u16;
+        let hi = self.mem_read(pos.wrapping_add(1) as u16) as u16;
+        (hi << 8) | lo
+    }
+
     fn mem_write_u16(&mut self, pos: u16, data: u16) {
         let hi = (data >> 8) as u8;
         let lo = (data & 0xff) as u8;
         self.mem_write(pos, lo);
-        self.mem_write(pos + 1, hi);
+        self.mem_write(pos.wrapping_add(1), hi);
     }
 }
 
-impl Memory for Cpu<'_> {
+impl<B: CpuBus> Memory for Cpu<'_, B> {
     fn mem_read(&mut self, addr: u16) -> u8 {
         self.bus.mem_read(addr)
     }
@@ -129,8 +199,10 @@ impl Memory for Cpu<'_> {
 
 mod interrupt {
     #[derive(PartialEq, Eq)]
+    #[allow(clippy::upper_case_acronyms)]
     pub enum InterruptType {
         NMI,
+        IRQ,
     }
 
     #[derive(PartialEq, Eq)]
@@ -146,14 +218,29 @@ mod interrupt {
         b_flag_mask: 0b00100000,
         cpu_cycles: 2,
     };
+    pub(super) const IRQ: Interrupt = Interrupt {
+        itype: InterruptType::IRQ,
+        vector_addr: 0xfffe,
+        b_flag_mask: 0b00100000,
+        cpu_cycles: 2,
+    };
 }
 
-impl<'a> Cpu<'a> {
+impl<'a, B: CpuBus> Cpu<'a, B> {
     ///Cpuコンストラクタ
     ///
     /// # Parameters
     /// * `bus` - Bus
-    pub fn new<'b>(bus: Bus<'b>) -> Cpu<'b> {
+    pub fn new(bus: B) -> Self {
+        Cpu::new_with_variant(bus, CpuVariant::default())
+    }
+
+    ///CPUの種類を指定するCpuコンストラクタ
+    ///
+    /// # Parameters
+    /// * `bus` - Bus
+    /// * `variant` - CPUの種類
+    pub fn new_with_variant(bus: B, variant: CpuVariant) -> Self {
         Cpu {
             reg_a: 0,
             reg_x: 0,
@@ -162,6 +249,15 @@ impl<'a> Cpu<'a> {
             reg_pc: 0,
             status: CpuFlags::from_bits_truncate(0b100100),
             bus,
+            irq_disable_delayed: true,
+            nmi_pending: false,
+            variant,
+            profile: None,
+            trace: None,
+            entry_point: None,
+            halt_on_brk: true,
+            operand: None,
+            bus_lifetime: PhantomData,
         }
     }
 
@@ -172,56 +268,84 @@ impl<'a> Cpu<'a> {
     /// # Reference
     /// * https://zenn.dev/szktty/articles/nes-addressingmode
     fn get_operand_address(&mut self, mode: &AddressingMode) -> u16 {
-        match mode {
-            AddressingMode::Immediate => self.reg_pc,
+        self.resolve_operand(mode).addr
+    }
+
+    ///オペランドを読み出してアドレスを求める.
+    ///1命令の間は最初に求めた結果を使い、オペランドとポインタを2回読み出さない.
+    fn resolve_operand(&mut self, mode: &AddressingMode) -> Operand {
+        if let Some(operand) = self.operand {
+            return operand;
+        }
+        let (base, index) = match mode {
+            AddressingMode::Immediate => (self.reg_pc, 0),
 
-            AddressingMode::ZeroPage => self.mem_read(self.reg_pc) as u16,
+            AddressingMode::ZeroPage => (self.mem_read(self.reg_pc) as u16, 0),
 
-            AddressingMode::Absolute => self.mem_read_u16(self.reg_pc),
+            AddressingMode::Absolute => (self.mem_read_u16(self.reg_pc), 0),
 
             AddressingMode::ZeroPage_X => {
                 let pos = self.mem_read(self.reg_pc);
 
-                pos.wrapping_add(self.reg_x) as u16
+                (pos.wrapping_add(self.reg_x) as u16, 0)
             }
             AddressingMode::ZeroPage_Y => {
                 let pos = self.mem_read(self.reg_pc);
 
-                pos.wrapping_add(self.reg_y) as u16
-            }
-
-            AddressingMode::Absolute_X => {
-                let base = self.mem_read_u16(self.reg_pc);
-
-                base.wrapping_add(self.reg_x as u16)
+                (pos.wrapping_add(self.reg_y) as u16, 0)
             }
-            AddressingMode::Absolute_Y => {
-                let base = self.mem_read_u16(self.reg_pc);
 
-                base.wrapping_add(self.reg_y as u16)
-            }
+            AddressingMode::Absolute_X => (self.mem_read_u16(self.reg_pc), self.reg_x),
+            AddressingMode::Absolute_Y => (self.mem_read_u16(self.reg_pc), self.reg_y),
 
             AddressingMode::Indirect_X => {
                 let base = self.mem_read(self.reg_pc);
 
-                let ptr: u8 = (base as u8).wrapping_add(self.reg_x);
-                let lo = self.mem_read(ptr as u16);
-                let hi = self.mem_read(ptr.wrapping_add(1) as u16);
-                (hi as u16) << 8 | (lo as u16)
+                let ptr: u8 = base.wrapping_add(self.reg_x);
+                (self.mem_read_zp_u16(ptr), 0)
             }
             AddressingMode::Indirect_Y => {
                 let base = self.mem_read(self.reg_pc);
 
-                let lo = self.mem_read(base as u16);
-                let hi = self.mem_read((base as u8).wrapping_add(1) as u16);
-                let deref_base = (hi as u16) << 8 | (lo as u16);
-
-                deref_base.wrapping_add(self.reg_y as u16)
+                (self.mem_read_zp_u16(base), self.reg_y)
             }
             AddressingMode::NoneAddressing => {
                 panic!("mode {:?} is not supported", mode);
             }
+        };
+        let operand = Operand {
+            addr: base.wrapping_add(index as u16),
+            base,
+        };
+        self.operand = Some(operand);
+        operand
+    }
+
+    ///インデックスの加算でページをまたぐかどうか(Absolute_X/Absolute_Y/Indirect_Y).
+    ///オペランドを読み出したときのアドレスから判定する.
+    fn crosses_page(&mut self, mode: &AddressingMode) -> bool {
+        if !matches!(
+            mode,
+            AddressingMode::Absolute_X | AddressingMode::Absolute_Y | AddressingMode::Indirect_Y
+        ) {
+            return false;
+        }
+        let operand = self.resolve_operand(mode);
+        operand.base & 0xff00 != operand.addr & 0xff00
+    }
+
+    ///インデックス付きの書き込み/リードモディファイライト命令のダミーリード.
+    ///実機ではインデックスを加算した下位バイトと、桁上がり前の上位バイトのアドレスを
+    ///書き込みの前に必ず1回読み出す. (PPU/APUのレジスタでは副作用がある)
+    fn indexed_dummy_read(&mut self, mode: &AddressingMode) {
+        if !matches!(
+            mode,
+            AddressingMode::Absolute_X | AddressingMode::Absolute_Y | AddressingMode::Indirect_Y
+        ) {
+            return;
         }
+        let operand = self.resolve_operand(mode);
+        self.mem_read((operand.base & 0xff00) | (operand.addr & 0x00ff));
     }
 
     fn ldy(&mut self, mode: &AddressingMode) {
@@ -245,6 +369,7 @@ impl<'a> Cpu<'a> {
     }
 
     fn sta(&mut self, mode: &AddressingMode) {
+        self.indexed_dummy_read(mode);
         let addr = self.get_operand_address(mode);
         self.mem_write(addr, self.reg_a);
     }
@@ -291,14 +416,6 @@ impl<'a> Cpu<'a> {
         }
     }
 
-    fn update_negative_flags(&mut self, result: u8) {
-        if result >> 7 == 1 {
-            self.status.insert(CpuFlags::NEGATIV)
-        } else {
-            self.status.remove(CpuFlags::NEGATIV)
-        }
-    }
-
     fn inx(&mut self) {
         self.reg_x = self.reg_x.wrapping_add(1);
         self.update_zero_and_negative_flags(self.reg_x);
@@ -315,8 +432,290 @@ impl<'a> Cpu<'a> {
         self.reg_y = 0;
         self.reg_sp = STACK_RESET;
         self.status = CpuFlags::from_bits_truncate(0b100100);
+        self.irq_disable_delayed = true;
+        self.nmi_pending = false;
         //self.memory = [0; 0xFFFF];
-        self.reg_pc = self.mem_read_u16(0xFFFC);
+        self.reg_pc = self.reset_vector();
+    }
+
+    ///リセット時の開始アドレス. 指定がなければリセットベクタ(0xFFFC)を読む.
+    fn reset_vector(&mut self) -> u16 {
+        match self.entry_point {
+            Some(addr) => addr,
+            None => self.mem_read_u16(0xFFFC),
+        }
+    }
+
+    ///リセット時にリセットベクタの代わりに実行を開始するアドレスを設定する.
+    ///ROMのエントリポイント以外のサブルーチンを単体で動かすテスト用.
+    ///
+    /// # Parameters
+    /// * `entry_point` - 開始アドレス. Noneの場合はリセットベクタに戻す
+    pub fn set_entry_point(&mut self, entry_point: Option<u16>) {
+        self.entry_point = entry_point;
+    }
+
+    ///`set_entry_point`で設定した開始アドレス
+    pub fn entry_point(&self) -> Option<u16> {
+        self.entry_point
+    }
+
+    ///プロファイラを有効にする. 以降に実行した命令の消費サイクル数を命令のアドレス毎に集計する.
+    pub fn enable_profiler(&mut self) {
+        self.profile.get_or_insert_with(HashMap::new);
+    }
+
+    ///プロファイラの集計結果. (命令のアドレス, サイクル数)をサイクル数の多い順に返す.
+    pub fn profile_report(&self) -> Vec<(u16, u64)> {
+        let mut report: Vec<(u16, u64)> = match &self.profile {
+            Some(profile) => profile.iter().map(|(pc, cycles)| (*pc, *cycles)).collect(),
+            None => vec![],
+        };
+        report.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        report
+    }
+
+    ///直近に実行した命令を記録するリングバッファを有効にする.
+    ///メモリ使用量は`len`命令分で一定のため、常に有効にしておける.
+    ///
+    /// # Parameters
+    /// * `len` - 記録する命令数. 0の場合は無効にする
+    pub fn enable_trace_buffer(&mut self, len: usize) {
+        self.trace = (len > 0).then(|| (VecDeque::with_capacity(len), len));
+    }
+
+    ///直近に実行した命令を古い順に返す.
+    ///1行に命令のアドレス、逆アセンブル結果と実行前のレジスタを出力する.
+    pub fn recent_trace(&self) -> Vec<String> {
+        match &self.trace {
+            Some((trace, _)) => trace.iter().cloned().collect(),
+            None => vec![],
+        }
+    }
+
+    ///`start`から`count`命令を逆アセンブルし、(アドレス, 命令)を返す.
+    ///メモリは`Bus::peek`で読むため、PPUレジスタ等の状態は変わらない.
+    ///
+    /// # Parameters
+    /// * `start` - 開始アドレス
+    /// * `count` - 命令数
+    pub fn disassemble_range(&self, start: u16, count: usize) -> Vec<(u16, String)> {
+        let mut listing = Vec::with_capacity(count);
+        let mut addr = start;
+        for _ in 0..count {
+            let (instruction, len) = self.disassemble(addr);
+            listing.push((addr, instruction));
+            addr = addr.wrapping_add(len);
+        }
+        listing
+    }
+
+    ///現在のPCの前後の命令を逆アセンブルし、(アドレス, 命令, 現在のPCか)を返す(デバッガ用).
+    ///
+    ///命令長が可変のため、PCより前の命令の境界は一意に決まらない.
+    ///PCから1～`before * 3`バイト(全て3バイト命令の場合)前の各アドレスを起点に順方向に逆アセンブルし、
+    ///ちょうどPCに到達した候補から、通常のコードに現れにくい命令(非公式命令、BRK、未定義)が
+    ///最も少ないものを選ぶ. 同数の場合はより遠い起点(より多くの命令が同期している)を選ぶ.
+    ///データ領域の直後等では誤った命令を表示することがある.
+    ///
+    /// # Parameters
+    /// * `before` - PCより前に表示する命令数(最大)
+    /// * `after` - PCより後に表示する命令数
+    pub fn disassemble_window(&self, before: usize, after: usize) -> Vec<(u16, String, bool)> {
+        let pc = self.reg_pc;
+        let mut preceding = Vec::new();
+        let mut best_penalty = usize::MAX;
+        for distance in (1..=before * 3).rev() {
+            let start = pc.wrapping_sub(distance as u16);
+            let mut addr = start;
+            let mut listing = Vec::new();
+            while addr.wrapping_sub(start) < distance as u16 {
+                let (instruction, len) = self.disassemble(addr);
+                listing.push((addr, instruction, false));
+                addr = addr.wrapping_add(len);
+            }
+            if addr != pc {
+                continue;
+            }
+            let penalty = listing
+                .iter()
+                .filter(|(_, instruction, _)| {
+                    instruction.starts_with('*')
+                        || instruction.starts_with(".db")
+                        || instruction == "BRK"
+                })
+                .count();
+            if penalty < best_penalty {
+                best_penalty = penalty;
+                preceding = listing;
+            }
+        }
+        let skip = preceding.len().saturating_sub(before);
+
+        let mut window: Vec<_> = preceding.into_iter().skip(skip).collect();
+        window.extend(
+            self.disassemble_range(pc, after + 1)
+                .into_iter()
+                .map(|(addr, instruction)| (addr, instruction, addr == pc)),
+        );
+        window
+    }
+
+    ///これから実行する命令をトレースのリングバッファに記録する.
+    fn record_trace(&mut self) {
+        let (instruction, _) = self.disassemble(self.reg_pc);
+        let line = format!(
+            "{:04X}  {:<13} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            self.reg_pc,
+            instruction,
+            self.reg_a,
+            self.reg_x,
+            self.reg_y,
+            self.status.bits(),
+            self.reg_sp,
+            self.bus.cycles()
+        );
+        if let Some((trace, len)) = &mut self.trace {
+            if trace.len() == *len {
+                trace.pop_front();
+            }
+            trace.push_back(line);
+        }
+    }
+
+    ///`addr`の1命令を逆アセンブルし、(命令, 命令長)を返す.
+    fn disassemble(&self, addr: u16) -> (String, u16) {
+        let code = self.bus.peek(addr);
+        let opcode = match opcodes::OPCODES_TABLE[code as usize] {
+            Some(opcode) => opcode,
+            None => return (format!(".db ${:02X}", code), 1),
+        };
+        let byte = self.bus.peek(addr.wrapping_add(1));
+        let word = u16::from_le_bytes([byte, self.bus.peek(addr.wrapping_add(2))]);
+        let operand = match (&opcode.mode, opcode.len) {
+            (AddressingMode::Immediate, _) => format!("#${:02X}", byte),
+            (AddressingMode::ZeroPage, _) => format!("${:02X}", byte),
+            (AddressingMode::ZeroPage_X, _) => format!("${:02X},X", byte),
+            (AddressingMode::ZeroPage_Y, _) => format!("${:02X},Y", byte),
+            (AddressingMode::Absolute, _) => format!("${:04X}", word),
+            (AddressingMode::Absolute_X, _) => format!("${:04X},X", word),
+            (AddressingMode::Absolute_Y, _) => format!("${:04X},Y", word),
+            (AddressingMode::Indirect_X, _) => format!("(${:02X},X)", byte),
+            (AddressingMode::Indirect_Y, _) => format!("(${:02X}),Y", byte),
+            //ASL A, LSR A, ROL A, ROR A
+            (AddressingMode::NoneAddressing, 1) => match code {
+                0x0a | 0x4a | 0x2a | 0x6a => "A".to_string(),
+                _ => String::new(),
+            },
+            //分岐命令は分岐先のアドレス
+            (AddressingMode::NoneAddressing, 2) => {
+                let target = addr.wrapping_add(2).wrapping_add(byte as i8 as u16);
+                format!("${:04X}", target)
+            }
+            (AddressingMode::NoneAddressing, _) if code == 0x6c => format!("(${:04X})", word),
+            (AddressingMode::NoneAddressing, _) => format!("${:04X}", word),
+        };
+        let instruction = if operand.is_empty() {
+            opcode.mnemonic.to_string()
+        } else {
+            format!("{} {}", opcode.mnemonic, operand)
+        };
+        (instruction, opcode.len as u16)
+    }
+
+    ///ステータスフラグの取得
+    ///
+    /// # Parameters
+    /// * `flag` - フラグ
+    pub fn flag(&self, flag: CpuFlags) -> bool {
+        self.status.contains(flag)
+    }
+
+    ///ステータスフラグの設定
+    ///
+    /// # Parameters
+    /// * `flag` - フラグ
+    /// * `value` - 設定する値
+    pub fn set_flag(&mut self, flag: CpuFlags, value: bool) {
+        self.status.set(flag, value);
+    }
+
+    ///CPUの種類の取得
+    pub fn variant(&self) -> CpuVariant {
+        self.variant
+    }
+
+    ///プログラムカウンタの取得
+    pub fn pc(&self) -> u16 {
+        self.reg_pc
+    }
+
+    ///レジスタの値
+    pub fn registers(&self) -> CpuRegisters {
+        CpuRegisters {
+            a: self.reg_a,
+            x: self.reg_x,
+            y: self.reg_y,
+            sp: self.reg_sp,
+            status: self.status,
+            pc: self.reg_pc,
+        }
+    }
+
+    ///CPUのレジスタと割り込みの内部状態だけを複製する(RAM等は含まない).
+    pub fn snapshot(&self) -> CpuState {
+        CpuState {
+            registers: self.registers(),
+            irq_disable_delayed: self.irq_disable_delayed,
+            nmi_pending: self.nmi_pending,
+        }
+    }
+
+    ///`snapshot`で複製したCPUの状態に戻す. RAM/PPU/APUやサイクル数は戻さない.
+    ///
+    /// # Parameters
+    /// * `state` - 戻す状態
+    pub fn restore(&mut self, state: CpuState) {
+        let registers = state.registers;
+        self.reg_a = registers.a;
+        self.reg_x = registers.x;
+        self.reg_y = registers.y;
+        self.reg_sp = registers.sp;
+        self.status = registers.status;
+        self.reg_pc = registers.pc;
+        self.irq_disable_delayed = state.irq_disable_delayed;
+        self.nmi_pending = state.nmi_pending;
+    }
+
+    ///プログラムカウンタの設定.
+    ///デバッガから任意のアドレスへジャンプする際に使用する.
+    ///次の`step`は指定したアドレスの命令から実行される.
+    ///
+    /// # Parameters
+    /// * `addr` - ジャンプ先のアドレス
+    pub fn set_pc(&mut self, addr: u16) {
+        self.reg_pc = addr;
+    }
+
+    ///BRKで実行を停止するかどうかを設定する(デフォルトtrue).
+    ///falseの場合、BRKはIRQベクタ(0xFFFE)への割り込みとして実行され、
+    ///スタックにはBRKの2byte後のアドレスとBフラグを立てたステータスが積まれる.
+    ///BRKで終了しないテストROMは`run_until`で停止するアドレスを指定する.
+    ///
+    /// # Parameters
+    /// * `enabled` - BRKで停止するかどうか
+    pub fn set_halt_on_brk(&mut self, enabled: bool) {
+        self.halt_on_brk = enabled;
+    }
+
+    ///リセットボタンによるリセット.
+    ///A,X,Yは保持され、スタックポインタは3減り、IRQ禁止フラグがセットされる.
+    pub fn soft_reset(&mut self) {
+        self.reg_sp = self.reg_sp.wrapping_sub(3);
+        self.status.insert(CpuFlags::INTERRUPT_DISABLE);
+        self.irq_disable_delayed = true;
+        self.nmi_pending = false;
+        self.reg_pc = self.reset_vector();
     }
 
     fn set_carry_flag(&mut self) {
@@ -327,7 +726,17 @@ impl<'a> Cpu<'a> {
         self.status.remove(CpuFlags::CARRY)
     }
 
+    ///ADC/SBCでBCD演算を行うかどうか
+    fn decimal_enabled(&self) -> bool {
+        self.variant == CpuVariant::Mos6502 && self.status.contains(CpuFlags::DECIMAL_MODE)
+    }
+
     fn add_to_reg_a(&mut self, data: u8) {
+        if self.decimal_enabled() {
+            self.decimal_add_to_reg_a(data);
+            return;
+        }
+
         let sum = self.reg_a as u16
             + data as u16
             + (if self.status.contains(CpuFlags::CARRY) {
@@ -356,9 +765,77 @@ impl<'a> Cpu<'a> {
     }
 
     fn sub_from_reg_a(&mut self, data: u8) {
+        if self.decimal_enabled() {
+            self.decimal_sub_from_reg_a(data);
+            return;
+        }
+
         self.add_to_reg_a(((data as i8).wrapping_neg().wrapping_sub(1)) as u8);
     }
 
+    ///BCDの加算(NMOS 6502).
+    ///Zフラグはバイナリ演算の結果、N/Vフラグは上位桁の補正前の値から決まる.
+    /// # Reference
+    /// * http://www.6502.org/tutorials/decimal_mode.html
+    fn decimal_add_to_reg_a(&mut self, data: u8) {
+        let a = self.reg_a;
+        let carry = self.status.contains(CpuFlags::CARRY) as u8;
+
+        let mut lo = (a & 0x0f) + (data & 0x0f) + carry;
+        let mut hi = (a >> 4) + (data >> 4);
+        if lo > 9 {
+            lo += 6;
+        }
+        if lo > 0x0f {
+            hi += 1;
+        }
+
+        self.status.set(
+            CpuFlags::ZERO,
+            a.wrapping_add(data).wrapping_add(carry) == 0,
+        );
+        self.status.set(CpuFlags::NEGATIV, hi & 0b1000 != 0);
+        self.status.set(
+            CpuFlags::OVERFLOW,
+            (a ^ data) & 0x80 == 0 && (a ^ (hi << 4)) & 0x80 != 0,
+        );
+
+        if hi > 9 {
+            hi += 6;
+        }
+        self.status.set(CpuFlags::CARRY, hi > 0x0f);
+
+        self.reg_a = (hi << 4) | (lo & 0x0f);
+    }
+
+    ///BCDの減算(NMOS 6502). フラグはバイナリ演算と同じ.
+    /// # Reference
+    /// * http://www.6502.org/tutorials/decimal_mode.html
+    fn decimal_sub_from_reg_a(&mut self, data: u8) {
+        let a = self.reg_a;
+        let borrow = !self.status.contains(CpuFlags::CARRY) as i16;
+
+        let diff = a as i16 - data as i16 - borrow;
+        self.status.set(CpuFlags::CARRY, diff >= 0);
+        self.status.set(
+            CpuFlags::OVERFLOW,
+            (a ^ data) & (a ^ diff as u8) & 0x80 != 0,
+        );
+        self.update_zero_and_negative_flags(diff as u8);
+
+        let mut lo = (a & 0x0f) as i16 - (data & 0x0f) as i16 - borrow;
+        let mut hi = (a >> 4) as i16 - (data >> 4) as i16;
+        if lo < 0 {
+            lo -= 6;
+            hi -= 1;
+        }
+        if hi < 0 {
+            hi -= 6;
+        }
+
+        self.reg_a = ((hi << 4) | (lo & 0x0f)) as u8;
+    }
+
     fn and_with_reg_a(&mut self, data: u8) {
         self.set_reg_a(data & self.reg_a);
     }
@@ -374,7 +851,7 @@ impl<'a> Cpu<'a> {
     fn sbc(&mut self, mode: &AddressingMode) {
         let addr = self.get_operand_address(mode);
         let data = self.mem_read(addr);
-        self.add_to_reg_a(((data as i8).wrapping_neg().wrapping_sub(1)) as u8);
+        self.sub_from_reg_a(data);
     }
 
     fn adc(&mut self, mode: &AddressingMode) {
@@ -418,6 +895,7 @@ impl<'a> Cpu<'a> {
     }
 
     fn asl(&mut self, mode: &AddressingMode) -> u8 {
+        self.indexed_dummy_read(mode);
         let addr = self.get_operand_address(mode);
         let mut data = self.mem_read(addr);
         if data >> 7 == 1 {
@@ -443,6 +921,7 @@ impl<'a> Cpu<'a> {
     }
 
     fn lsr(&mut self, mode: &AddressingMode) -> u8 {
+        self.indexed_dummy_read(mode);
         let addr = self.get_operand_address(mode);
         let mut data = self.mem_read(addr);
         if data & 1 == 1 {
@@ -457,6 +936,7 @@ impl<'a> Cpu<'a> {
     }
 
     fn rol(&mut self, mode: &AddressingMode) -> u8 {
+        self.indexed_dummy_read(mode);
         let addr = self.get_operand_address(mode);
         let mut data = self.mem_read(addr);
         let old_carry = self.status.contains(CpuFlags::CARRY);
@@ -471,7 +951,8 @@ impl<'a> Cpu<'a> {
             data |= 1;
         }
         self.mem_write(addr, data);
-        self.update_negative_flags(data);
+        //メモリへのROL/RORもZフラグを更新する
+        self.update_zero_and_negative_flags(data);
         data
     }
 
@@ -492,6 +973,7 @@ impl<'a> Cpu<'a> {
     }
 
     fn ror(&mut self, mode: &AddressingMode) -> u8 {
+        self.indexed_dummy_read(mode);
         let addr = self.get_operand_address(mode);
         let mut data = self.mem_read(addr);
         let old_carry = self.status.contains(CpuFlags::CARRY);
@@ -506,7 +988,8 @@ impl<'a> Cpu<'a> {
             data |= 0b10000000;
         }
         self.mem_write(addr, data);
-        self.update_negative_flags(data);
+        //メモリへのROL/RORもZフラグを更新する
+        self.update_zero_and_negative_flags(data);
         data
     }
 
@@ -527,6 +1010,7 @@ impl<'a> Cpu<'a> {
     }
 
     fn inc(&mut self, mode: &AddressingMode) -> u8 {
+        self.indexed_dummy_read(mode);
         let addr = self.get_operand_address(mode);
         let mut data = self.mem_read(addr);
         data = data.wrapping_add(1);
@@ -546,6 +1030,7 @@ impl<'a> Cpu<'a> {
     }
 
     fn dec(&mut self, mode: &AddressingMode) -> u8 {
+        self.indexed_dummy_read(mode);
         let addr = self.get_operand_address(mode);
         let mut data = self.mem_read(addr);
         data = data.wrapping_sub(1);
@@ -565,6 +1050,15 @@ impl<'a> Cpu<'a> {
         self.status.insert(CpuFlags::BREAK2);
     }
 
+    ///BRK. 次の1byte(パディング)を読み飛ばしたアドレスとBフラグを立てたステータスを積み、
+    ///IRQベクタにジャンプする.
+    fn brk(&mut self) {
+        self.stack_push_u16(self.reg_pc.wrapping_add(1));
+        self.php();
+        self.status.insert(CpuFlags::INTERRUPT_DISABLE);
+        self.reg_pc = self.mem_read_u16(0xfffe);
+    }
+
     fn php(&mut self) {
         //http://wiki.nesdev.com/w/index.php/CPU_status_flag_behavior
         let mut flags = self.status;
@@ -590,6 +1084,10 @@ impl<'a> Cpu<'a> {
     fn compare(&mut self, mode: &AddressingMode, compare_with: u8) {
         let addr = self.get_operand_address(mode);
         let data = self.mem_read(addr);
+        self.compare_data(data, compare_with);
+    }
+
+    fn compare_data(&mut self, data: u8, compare_with: u8) {
         if data <= compare_with {
             self.status.insert(CpuFlags::CARRY);
         } else {
@@ -602,12 +1100,40 @@ impl<'a> Cpu<'a> {
     fn branch(&mut self, condition: bool) {
         if condition {
             let jump: i8 = self.mem_read(self.reg_pc) as i8;
-            let jump_addr = self.reg_pc.wrapping_add(1).wrapping_add(jump as u16);
+            let next = self.reg_pc.wrapping_add(1);
+            let jump_addr = next.wrapping_add(jump as u16);
+
+            //分岐成立時の追加サイクルでは次の命令のオペコードをダミーリードする
+            self.mem_read(next);
+            //ページをまたぐ場合は、上位バイトを補正する前のアドレスをダミーリードする
+            if next & 0xff00 != jump_addr & 0xff00 {
+                self.mem_read((next & 0xff00) | (jump_addr & 0x00ff));
+            }
 
             self.reg_pc = jump_addr;
         }
     }
 
+    ///TAS/AHX/SHX/SHY(不安定な非公式命令)の書き込み処理.
+    ///
+    /// 書き込む値は `value & (ベースアドレスの上位バイト + 1)`.
+    /// インデックスの加算でページをまたいだ場合、実効アドレスの上位バイトは
+    /// 書き込む値に置き換わる. 実機でも個体差がある命令のため、
+    /// nesdev wikiに記載されている最も一般的な挙動を採用している.
+    ///
+    /// # Parameters
+    /// * `base` - インデックス加算前のアドレス
+    /// * `index` - インデックスレジスタの値
+    /// * `value` - 書き込む値(上位バイト+1とのANDをとる前)
+    fn unstable_store(&mut self, base: u16, index: u8, value: u8) {
+        let mut addr = base.wrapping_add(index as u16);
+        let data = value & ((base >> 8) as u8).wrapping_add(1);
+        if base & 0xff00 != addr & 0xff00 {
+            addr = (data as u16) << 8 | (addr & 0x00ff);
+        }
+        self.mem_write(addr, data);
+    }
+
     fn interrupt(&mut self, interrupt: interrupt::Interrupt) {
         self.stack_push_u16(self.reg_pc);
         let mut flag = self.status;
@@ -632,533 +1158,1637 @@ impl<'a> Cpu<'a> {
     /// * `callback` - Cpuを引数にとるクロージャ
     pub fn run_with_callback<F>(&mut self, mut callback: F)
     where
-        F: FnMut(&mut Cpu),
+        F: FnMut(&mut Self),
     {
-        let opcodes: &HashMap<u8, &'static opcodes::OpCode> = &(*opcodes::OPCODES_MAP);
+        while self.step_with_callback(&mut callback) {}
+    }
 
+    ///PCが`target`に到達するまで実行する. 到達した命令は実行しない.
+    ///
+    ///リセット後に`set_pc`で開始アドレスを設定すれば、任意のアドレスからテストROMを実行できる.
+    ///nestestの自動モードは0xC000から実行し、0xC66E(RTS)に到達した時点で終了する.
+    ///
+    ///```ignore
+    ///cpu.reset();
+    ///cpu.set_pc(0xc000);
+    ///cpu.set_halt_on_brk(false);
+    ///assert_eq!(cpu.run_until(0xc66e, 100_000), StopReason::Reached);
+    ///```
+    ///
+    /// # Parameters
+    /// * `target` - 停止するアドレス
+    /// * `max_instructions` - 実行する命令数の上限(無限ループ対策)
+    pub fn run_until(&mut self, target: u16, max_instructions: usize) -> StopReason {
+        for _ in 0..max_instructions {
+            if self.reg_pc == target {
+                return StopReason::Reached;
+            }
+            if !self.step() {
+                return StopReason::Halted;
+            }
+        }
+        if self.reg_pc == target {
+            StopReason::Reached
+        } else {
+            StopReason::InstructionLimit
+        }
+    }
+
+    ///1命令実行する. BRKで停止した場合はfalseを返す.
+    pub fn step(&mut self) -> bool {
+        self.step_with_callback(|_| {})
+    }
+
+    ///現在のサブルーチンから戻るまで実行する(ステップアウト).
+    ///RTS/RTIの実行後にスタックが開始時より浅くなった時点で止まる.
+    ///ネストしたJSR/RTSや割り込みハンドラのRTIはスタックの深さで区別するため途中で止まらない.
+    ///BRKで停止した場合はfalseを返す.
+    pub fn run_until_return(&mut self) -> bool {
+        let depth = self.reg_sp;
         loop {
-            if let Some(_nmi) = self.bus.poll_nmi_status() {
-                self.interrupt(interrupt::NMI);
+            //コールバックは命令の実行前と実行後に呼ばれるため、実行前(割り込み処理後)の命令を記録する
+            let mut code = None;
+            let running = self.step_with_callback(|cpu| {
+                code.get_or_insert_with(|| cpu.bus.peek(cpu.reg_pc));
+            });
+            if !running {
+                return false;
+            }
+            //スタックは下方向に伸びるため、spが開始時より大きければ呼び出し元に戻っている
+            if matches!(code, Some(0x60) | Some(0x40))
+                && (self.reg_sp.wrapping_sub(depth) as i8) > 0
+            {
+                return true;
             }
+        }
+    }
 
-            callback(self);
+    ///1命令実行する. BRKで停止した場合はfalseを返す.
+    ///
+    /// # Parameters
+    /// * `callback` - Cpuを引数にとるクロージャ
+    pub fn step_with_callback<F>(&mut self, mut callback: F) -> bool
+    where
+        F: FnMut(&mut Self),
+    {
+        let opcodes: &[Option<&'static opcodes::OpCode>; 256] = &opcodes::OPCODES_TABLE;
 
-            let code = self.mem_read(self.reg_pc);
-            self.reg_pc += 1;
-            let program_counter_state = self.reg_pc;
+        if std::mem::take(&mut self.nmi_pending) {
+            self.interrupt(interrupt::NMI);
+        } else if self.bus.poll_irq_status() && !self.irq_disable_delayed {
+            self.interrupt(interrupt::IRQ);
+        }
+        //この命令で変更されたIフラグは次の命令の後のIRQ判定から有効になる
+        self.irq_disable_delayed = self.status.contains(CpuFlags::INTERRUPT_DISABLE);
 
-            //OpCode取得
-            let opcode = opcodes
-                .get(&code)
-                .unwrap_or_else(|| panic!("OpCode {:x} is not recognized", code));
+        callback(self);
 
-            match code {
-                0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => {
-                    self.lda(&opcode.mode);
-                }
+        if self.trace.is_some() {
+            self.record_trace();
+        }
 
-                0xAA => self.tax(),
-                0xe8 => self.inx(),
-                0x00 => return,
+        self.bus.set_instruction_pc(self.reg_pc);
+        let instruction_pc = self.reg_pc;
+        let start_cycles = self.bus.cycles();
+        let code = self.mem_read(self.reg_pc);
+        self.reg_pc = self.reg_pc.wrapping_add(1);
+        let program_counter_state = self.reg_pc;
+        self.operand = None;
+
+        //OpCode取得
+        let opcode =
+            opcodes[code as usize].unwrap_or_else(|| panic!("OpCode {:x} is not recognized", code));
+        //読み出し命令はページをまたぐと1サイクル追加される
+        let cycles = opcode.cycles
+            + (opcode.has_page_cross_penalty() && self.crosses_page(&opcode.mode)) as u8;
+
+        match code {
+            0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => {
+                self.lda(&opcode.mode);
+            }
 
-                /* CLD */ 0xd8 => self.status.remove(CpuFlags::DECIMAL_MODE),
+            0xAA => self.tax(),
+            0xe8 => self.inx(),
+            0x00 if self.halt_on_brk => return false,
+            /* BRK */ 0x00 => self.brk(),
 
-                /* CLI */ 0x58 => self.status.remove(CpuFlags::INTERRUPT_DISABLE),
+            /* CLD */ 0xd8 => self.status.remove(CpuFlags::DECIMAL_MODE),
 
-                /* CLV */ 0xb8 => self.status.remove(CpuFlags::OVERFLOW),
+            /* CLI */ 0x58 => self.status.remove(CpuFlags::INTERRUPT_DISABLE),
 
-                /* CLC */ 0x18 => self.clear_carry_flag(),
+            /* CLV */ 0xb8 => self.status.remove(CpuFlags::OVERFLOW),
 
-                /* SEC */ 0x38 => self.set_carry_flag(),
+            /* CLC */ 0x18 => self.clear_carry_flag(),
 
-                /* SEI */ 0x78 => self.status.insert(CpuFlags::INTERRUPT_DISABLE),
+            /* SEC */ 0x38 => self.set_carry_flag(),
 
-                /* SED */ 0xf8 => self.status.insert(CpuFlags::DECIMAL_MODE),
+            /* SEI */ 0x78 => self.status.insert(CpuFlags::INTERRUPT_DISABLE),
 
-                /* PHA */ 0x48 => self.stack_push(self.reg_a),
+            /* SED */ 0xf8 => self.status.insert(CpuFlags::DECIMAL_MODE),
 
-                /* PLA */
-                0x68 => {
-                    self.pla();
-                }
+            /* PHA */ 0x48 => self.stack_push(self.reg_a),
 
-                /* PHP */
-                0x08 => {
-                    self.php();
-                }
+            /* PLA */
+            0x68 => {
+                self.pla();
+            }
 
-                /* PLP */
-                0x28 => {
-                    self.plp();
-                }
+            /* PHP */
+            0x08 => {
+                self.php();
+            }
 
-                /* ADC */
-                0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => {
-                    self.adc(&opcode.mode);
-                }
+            /* PLP */
+            0x28 => {
+                self.plp();
+            }
 
-                /* SBC */
-                0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => {
-                    self.sbc(&opcode.mode);
-                }
+            /* ADC */
+            0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => {
+                self.adc(&opcode.mode);
+            }
 
-                /* AND */
-                0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => {
-                    self.and(&opcode.mode);
-                }
+            /* SBC */
+            0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => {
+                self.sbc(&opcode.mode);
+            }
 
-                /* EOR */
-                0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => {
-                    self.eor(&opcode.mode);
-                }
+            /* AND */
+            0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => {
+                self.and(&opcode.mode);
+            }
 
-                /* ORA */
-                0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => {
-                    self.ora(&opcode.mode);
-                }
+            /* EOR */
+            0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => {
+                self.eor(&opcode.mode);
+            }
 
-                /* LSR */ 0x4a => self.lsr_accumulator(),
+            /* ORA */
+            0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => {
+                self.ora(&opcode.mode);
+            }
 
-                /* LSR */
-                0x46 | 0x56 | 0x4e | 0x5e => {
-                    self.lsr(&opcode.mode);
-                }
+            /* LSR */ 0x4a => self.lsr_accumulator(),
 
-                /*ASL*/ 0x0a => self.asl_accumulator(),
+            /* LSR */
+            0x46 | 0x56 | 0x4e | 0x5e => {
+                self.lsr(&opcode.mode);
+            }
 
-                /* ASL */
-                0x06 | 0x16 | 0x0e | 0x1e => {
-                    self.asl(&opcode.mode);
-                }
+            /*ASL*/ 0x0a => self.asl_accumulator(),
 
-                /*ROL*/ 0x2a => self.rol_accumulator(),
+            /* ASL */
+            0x06 | 0x16 | 0x0e | 0x1e => {
+                self.asl(&opcode.mode);
+            }
 
-                /* ROL */
-                0x26 | 0x36 | 0x2e | 0x3e => {
-                    self.rol(&opcode.mode);
-                }
+            /*ROL*/ 0x2a => self.rol_accumulator(),
 
-                /* ROR */ 0x6a => self.ror_accumulator(),
+            /* ROL */
+            0x26 | 0x36 | 0x2e | 0x3e => {
+                self.rol(&opcode.mode);
+            }
 
-                /* ROR */
-                0x66 | 0x76 | 0x6e | 0x7e => {
-                    self.ror(&opcode.mode);
-                }
+            /* ROR */ 0x6a => self.ror_accumulator(),
 
-                /* INC */
-                0xe6 | 0xf6 | 0xee | 0xfe => {
-                    self.inc(&opcode.mode);
-                }
+            /* ROR */
+            0x66 | 0x76 | 0x6e | 0x7e => {
+                self.ror(&opcode.mode);
+            }
 
-                /* INY */
-                0xc8 => self.iny(),
+            /* INC */
+            0xe6 | 0xf6 | 0xee | 0xfe => {
+                self.inc(&opcode.mode);
+            }
 
-                /* DEC */
-                0xc6 | 0xd6 | 0xce | 0xde => {
-                    self.dec(&opcode.mode);
-                }
+            /* INY */
+            0xc8 => self.iny(),
 
-                /* DEX */
-                0xca => {
-                    self.dex();
-                }
+            /* DEC */
+            0xc6 | 0xd6 | 0xce | 0xde => {
+                self.dec(&opcode.mode);
+            }
 
-                /* DEY */
-                0x88 => {
-                    self.dey();
-                }
+            /* DEX */
+            0xca => {
+                self.dex();
+            }
 
-                /* CMP */
-                0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
-                    self.compare(&opcode.mode, self.reg_a);
-                }
+            /* DEY */
+            0x88 => {
+                self.dey();
+            }
 
-                /* CPY */
-                0xc0 | 0xc4 | 0xcc => {
-                    self.compare(&opcode.mode, self.reg_y);
-                }
+            /* CMP */
+            0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
+                self.compare(&opcode.mode, self.reg_a);
+            }
 
-                /* CPX */
-                0xe0 | 0xe4 | 0xec => self.compare(&opcode.mode, self.reg_x),
+            /* CPY */
+            0xc0 | 0xc4 | 0xcc => {
+                self.compare(&opcode.mode, self.reg_y);
+            }
 
-                /* JMP Absolute */
-                0x4c => {
-                    let mem_address = self.mem_read_u16(self.reg_pc);
-                    self.reg_pc = mem_address;
-                }
+            /* CPX */
+            0xe0 | 0xe4 | 0xec => self.compare(&opcode.mode, self.reg_x),
 
-                /* JMP Indirect */
-                0x6c => {
-                    let mem_address = self.mem_read_u16(self.reg_pc);
-                    let indirect_ref = if mem_address & 0x00FF == 0x00FF {
-                        let lo = self.mem_read(mem_address);
-                        let hi = self.mem_read(mem_address & 0xFF00);
-                        (hi as u16) << 8 | (lo as u16)
-                    } else {
-                        self.mem_read_u16(mem_address)
-                    };
-
-                    self.reg_pc = indirect_ref;
-                }
+            /* JMP Absolute */
+            0x4c => {
+                let mem_address = self.mem_read_u16(self.reg_pc);
+                self.reg_pc = mem_address;
+            }
 
-                /* JSR */
-                0x20 => {
-                    self.stack_push_u16(self.reg_pc + 2 - 1);
-                    let target_address = self.mem_read_u16(self.reg_pc);
-                    self.reg_pc = target_address
-                }
+            /* JMP Indirect */
+            0x6c => {
+                //オペコード、ポインタ下位、ポインタ上位、飛び先下位、飛び先上位の順に5サイクルで読む.
+                //ポインタの下位バイトが0xFFの場合、飛び先の上位バイトは同じページの先頭から読む(6502のバグ)
+                let mem_address = self.mem_read_u16(self.reg_pc);
+                let lo = self.mem_read(mem_address);
+                let hi_address =
+                    (mem_address & 0xff00) | (mem_address as u8).wrapping_add(1) as u16;
+                let hi = self.mem_read(hi_address);
+
+                self.reg_pc = (hi as u16) << 8 | (lo as u16);
+            }
 
-                /* RTS */
-                0x60 => {
-                    self.reg_pc = self.stack_pop_u16() + 1;
-                }
+            /* JSR */
+            0x20 => {
+                self.stack_push_u16(self.reg_pc.wrapping_add(2 - 1));
+                let target_address = self.mem_read_u16(self.reg_pc);
+                self.reg_pc = target_address
+            }
 
-                /* RTI */
-                0x40 => {
-                    self.status.bits = self.stack_pop();
-                    self.status.remove(CpuFlags::BREAK);
-                    self.status.insert(CpuFlags::BREAK2);
+            /* RTS */
+            0x60 => {
+                self.reg_pc = self.stack_pop_u16() + 1;
+            }
 
-                    self.reg_pc = self.stack_pop_u16();
-                }
+            /* RTI */
+            0x40 => {
+                self.status.bits = self.stack_pop();
+                self.status.remove(CpuFlags::BREAK);
+                self.status.insert(CpuFlags::BREAK2);
+                //RTIによるIフラグの変更は遅延せずに反映される
+                self.irq_disable_delayed = self.status.contains(CpuFlags::INTERRUPT_DISABLE);
 
-                /* BNE */
-                0xd0 => {
-                    self.branch(!self.status.contains(CpuFlags::ZERO));
-                }
+                self.reg_pc = self.stack_pop_u16();
+            }
 
-                /* BVS */
-                0x70 => {
-                    self.branch(self.status.contains(CpuFlags::OVERFLOW));
-                }
+            /* BNE */
+            0xd0 => {
+                self.branch(!self.status.contains(CpuFlags::ZERO));
+            }
 
-                /* BVC */
-                0x50 => {
-                    self.branch(!self.status.contains(CpuFlags::OVERFLOW));
-                }
+            /* BVS */
+            0x70 => {
+                self.branch(self.status.contains(CpuFlags::OVERFLOW));
+            }
 
-                /* BPL */
-                0x10 => {
-                    self.branch(!self.status.contains(CpuFlags::NEGATIV));
-                }
+            /* BVC */
+            0x50 => {
+                self.branch(!self.status.contains(CpuFlags::OVERFLOW));
+            }
 
-                /* BMI */
-                0x30 => {
-                    self.branch(self.status.contains(CpuFlags::NEGATIV));
-                }
+            /* BPL */
+            0x10 => {
+                self.branch(!self.status.contains(CpuFlags::NEGATIV));
+            }
 
-                /* BEQ */
-                0xf0 => {
-                    self.branch(self.status.contains(CpuFlags::ZERO));
-                }
+            /* BMI */
+            0x30 => {
+                self.branch(self.status.contains(CpuFlags::NEGATIV));
+            }
 
-                /* BCS */
-                0xb0 => {
-                    self.branch(self.status.contains(CpuFlags::CARRY));
-                }
+            /* BEQ */
+            0xf0 => {
+                self.branch(self.status.contains(CpuFlags::ZERO));
+            }
 
-                /* BCC */
-                0x90 => {
-                    self.branch(!self.status.contains(CpuFlags::CARRY));
-                }
+            /* BCS */
+            0xb0 => {
+                self.branch(self.status.contains(CpuFlags::CARRY));
+            }
 
-                /* BIT */
-                0x24 | 0x2c => {
-                    self.bit(&opcode.mode);
-                }
+            /* BCC */
+            0x90 => {
+                self.branch(!self.status.contains(CpuFlags::CARRY));
+            }
 
-                /* STA */
-                0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => {
-                    self.sta(&opcode.mode);
-                }
+            /* BIT */
+            0x24 | 0x2c => {
+                self.bit(&opcode.mode);
+            }
 
-                /* STX */
-                0x86 | 0x96 | 0x8e => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    self.mem_write(addr, self.reg_x);
-                }
+            /* STA */
+            0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => {
+                self.sta(&opcode.mode);
+            }
 
-                /* STY */
-                0x84 | 0x94 | 0x8c => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    self.mem_write(addr, self.reg_y);
-                }
+            /* STX */
+            0x86 | 0x96 | 0x8e => {
+                let addr = self.get_operand_address(&opcode.mode);
+                self.mem_write(addr, self.reg_x);
+            }
 
-                /* LDX */
-                0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => {
-                    self.ldx(&opcode.mode);
-                }
+            /* STY */
+            0x84 | 0x94 | 0x8c => {
+                let addr = self.get_operand_address(&opcode.mode);
+                self.mem_write(addr, self.reg_y);
+            }
 
-                /* LDY */
-                0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => {
-                    self.ldy(&opcode.mode);
-                }
+            /* LDX */
+            0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => {
+                self.ldx(&opcode.mode);
+            }
 
-                /* NOP */
-                0xea => {
-                    //do nothing
-                }
+            /* LDY */
+            0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => {
+                self.ldy(&opcode.mode);
+            }
 
-                /* TAY */
-                0xa8 => {
-                    self.reg_y = self.reg_a;
-                    self.update_zero_and_negative_flags(self.reg_y);
-                }
+            /* NOP */
+            0xea => {
+                //do nothing
+            }
 
-                /* TSX */
-                0xba => {
-                    self.reg_x = self.reg_sp;
-                    self.update_zero_and_negative_flags(self.reg_x);
-                }
+            /* TAY */
+            0xa8 => {
+                self.reg_y = self.reg_a;
+                self.update_zero_and_negative_flags(self.reg_y);
+            }
 
-                /* TXA */
-                0x8a => {
-                    self.reg_a = self.reg_x;
-                    self.update_zero_and_negative_flags(self.reg_a);
-                }
+            /* TSX */
+            0xba => {
+                self.reg_x = self.reg_sp;
+                self.update_zero_and_negative_flags(self.reg_x);
+            }
 
-                /* TXS */
-                0x9a => {
-                    self.reg_sp = self.reg_x;
-                }
+            /* TXA */
+            0x8a => {
+                self.reg_a = self.reg_x;
+                self.update_zero_and_negative_flags(self.reg_a);
+            }
 
-                /* TYA */
-                0x98 => {
-                    self.reg_a = self.reg_y;
-                    self.update_zero_and_negative_flags(self.reg_a);
-                }
+            /* TXS */
+            0x9a => {
+                self.reg_sp = self.reg_x;
+            }
 
-                /* unofficial */
-
-                /* DCP */
-                0xc7 | 0xd7 | 0xCF | 0xdF | 0xdb | 0xd3 | 0xc3 => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let mut data = self.mem_read(addr);
-                    data = data.wrapping_sub(1);
-                    self.mem_write(addr, data);
-                    // self._update_zero_and_negative_flags(data);
-                    if data <= self.reg_a {
-                        self.status.insert(CpuFlags::CARRY);
-                    }
+            /* TYA */
+            0x98 => {
+                self.reg_a = self.reg_y;
+                self.update_zero_and_negative_flags(self.reg_a);
+            }
 
-                    self.update_zero_and_negative_flags(self.reg_a.wrapping_sub(data));
-                }
+            /* unofficial */
 
-                /* RLA */
-                0x27 | 0x37 | 0x2F | 0x3F | 0x3b | 0x33 | 0x23 => {
-                    let data = self.rol(&opcode.mode);
-                    self.and_with_reg_a(data);
-                }
+            /* DCP */
+            0xc7 | 0xd7 | 0xCF | 0xdF | 0xdb | 0xd3 | 0xc3 => {
+                //DEC + CMP
+                let data = self.dec(&opcode.mode);
+                self.compare_data(data, self.reg_a);
+            }
 
-                /* SLO */
-                0x07 | 0x17 | 0x0F | 0x1f | 0x1b | 0x03 | 0x13 => {
-                    let data = self.asl(&opcode.mode);
-                    self.or_with_reg_a(data);
-                }
+            /* RLA */
+            0x27 | 0x37 | 0x2F | 0x3F | 0x3b | 0x33 | 0x23 => {
+                let data = self.rol(&opcode.mode);
+                self.and_with_reg_a(data);
+            }
 
-                /* SRE */
-                0x47 | 0x57 | 0x4F | 0x5f | 0x5b | 0x43 | 0x53 => {
-                    let data = self.lsr(&opcode.mode);
-                    self.xor_with_reg_a(data);
-                }
+            /* SLO */
+            0x07 | 0x17 | 0x0F | 0x1f | 0x1b | 0x03 | 0x13 => {
+                let data = self.asl(&opcode.mode);
+                self.or_with_reg_a(data);
+            }
 
-                /* SKB */
-                0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 => {
-                    /* 2 byte NOP (immidiate ) */
-                    // todo: might be worth doing the read
-                }
+            /* SRE */
+            0x47 | 0x57 | 0x4F | 0x5f | 0x5b | 0x43 | 0x53 => {
+                let data = self.lsr(&opcode.mode);
+                self.xor_with_reg_a(data);
+            }
 
-                /* AXS */
-                0xCB => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    let x_and_a = self.reg_x & self.reg_a;
-                    let result = x_and_a.wrapping_sub(data);
+            /* SKB */
+            0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 => {
+                /* 2 byte NOP (immidiate ) */
+                // todo: might be worth doing the read
+            }
 
-                    if data <= x_and_a {
-                        self.status.insert(CpuFlags::CARRY);
-                    }
-                    self.update_zero_and_negative_flags(result);
+            /* AXS */
+            0xCB => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                let x_and_a = self.reg_x & self.reg_a;
+                let result = x_and_a.wrapping_sub(data);
 
-                    self.reg_x = result;
+                if data <= x_and_a {
+                    self.status.insert(CpuFlags::CARRY);
                 }
+                self.update_zero_and_negative_flags(result);
 
-                /* ARR */
-                0x6B => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.and_with_reg_a(data);
-                    self.ror_accumulator();
-                    //todo: registers
-                    let result = self.reg_a;
-                    let bit_5 = (result >> 5) & 1;
-                    let bit_6 = (result >> 6) & 1;
-
-                    if bit_6 == 1 {
-                        self.status.insert(CpuFlags::CARRY)
-                    } else {
-                        self.status.remove(CpuFlags::CARRY)
-                    }
+                self.reg_x = result;
+            }
 
-                    if bit_5 ^ bit_6 == 1 {
-                        self.status.insert(CpuFlags::OVERFLOW);
-                    } else {
-                        self.status.remove(CpuFlags::OVERFLOW);
-                    }
+            /* ARR */
+            0x6B => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                self.and_with_reg_a(data);
+                self.ror_accumulator();
+                //todo: registers
+                let result = self.reg_a;
+                let bit_5 = (result >> 5) & 1;
+                let bit_6 = (result >> 6) & 1;
 
-                    self.update_zero_and_negative_flags(result);
+                if bit_6 == 1 {
+                    self.status.insert(CpuFlags::CARRY)
+                } else {
+                    self.status.remove(CpuFlags::CARRY)
                 }
 
-                /* unofficial SBC */
-                0xeb => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.sub_from_reg_a(data);
+                if bit_5 ^ bit_6 == 1 {
+                    self.status.insert(CpuFlags::OVERFLOW);
+                } else {
+                    self.status.remove(CpuFlags::OVERFLOW);
                 }
 
-                /* ANC */
-                0x0b | 0x2b => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.and_with_reg_a(data);
-                    if self.status.contains(CpuFlags::NEGATIV) {
-                        self.status.insert(CpuFlags::CARRY);
-                    } else {
-                        self.status.remove(CpuFlags::CARRY);
-                    }
-                }
+                self.update_zero_and_negative_flags(result);
+            }
 
-                /* ALR */
-                0x4b => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.and_with_reg_a(data);
-                    self.lsr_accumulator();
-                }
+            /* unofficial SBC */
+            0xeb => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                self.sub_from_reg_a(data);
+            }
 
-                /* NOP read */
-                0x04 | 0x44 | 0x64 | 0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 | 0x0c | 0x1c
-                | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let _data = self.mem_read(addr);
-                    /* do nothing */
+            /* ANC */
+            0x0b | 0x2b => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                self.and_with_reg_a(data);
+                if self.status.contains(CpuFlags::NEGATIV) {
+                    self.status.insert(CpuFlags::CARRY);
+                } else {
+                    self.status.remove(CpuFlags::CARRY);
                 }
+            }
 
-                /* RRA */
-                0x67 | 0x77 | 0x6f | 0x7f | 0x7b | 0x63 | 0x73 => {
-                    let data = self.ror(&opcode.mode);
-                    self.add_to_reg_a(data);
-                }
+            /* ALR */
+            0x4b => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                self.and_with_reg_a(data);
+                self.lsr_accumulator();
+            }
 
-                /* ISB */
-                0xe7 | 0xf7 | 0xef | 0xff | 0xfb | 0xe3 | 0xf3 => {
-                    let data = self.inc(&opcode.mode);
-                    self.sub_from_reg_a(data);
-                }
+            /* NOP read */
+            0x04 | 0x44 | 0x64 | 0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 | 0x0c | 0x1c | 0x3c
+            | 0x5c | 0x7c | 0xdc | 0xfc => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let _data = self.mem_read(addr);
+                /* do nothing */
+            }
 
-                /* NOPs */
-                0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2
-                | 0xf2 => { /* do nothing */ }
+            /* RRA */
+            0x67 | 0x77 | 0x6f | 0x7f | 0x7b | 0x63 | 0x73 => {
+                let data = self.ror(&opcode.mode);
+                self.add_to_reg_a(data);
+            }
 
-                0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => { /* do nothing */ }
+            /* ISB */
+            0xe7 | 0xf7 | 0xef | 0xff | 0xfb | 0xe3 | 0xf3 => {
+                let data = self.inc(&opcode.mode);
+                self.sub_from_reg_a(data);
+            }
 
-                /* LAX */
-                0xa7 | 0xb7 | 0xaf | 0xbf | 0xa3 | 0xb3 => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.set_reg_a(data);
-                    self.reg_x = self.reg_a;
-                }
+            /* NOPs */
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2 => { /* do nothing */
+            }
 
-                /* SAX */
-                0x87 | 0x97 | 0x8f | 0x83 => {
-                    let data = self.reg_a & self.reg_x;
-                    let addr = self.get_operand_address(&opcode.mode);
-                    self.mem_write(addr, data);
-                }
+            0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => { /* do nothing */ }
 
-                /* LXA */
-                0xab => {
-                    self.lda(&opcode.mode);
-                    self.tax();
-                }
+            /* LAX */
+            0xa7 | 0xb7 | 0xaf | 0xbf | 0xa3 | 0xb3 => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                self.set_reg_a(data);
+                self.reg_x = self.reg_a;
+            }
 
-                /* XAA */
-                0x8b => {
-                    self.reg_a = self.reg_x;
-                    self.update_zero_and_negative_flags(self.reg_a);
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let data = self.mem_read(addr);
-                    self.and_with_reg_a(data);
-                }
+            /* SAX */
+            0x87 | 0x97 | 0x8f | 0x83 => {
+                let data = self.reg_a & self.reg_x;
+                let addr = self.get_operand_address(&opcode.mode);
+                self.mem_write(addr, data);
+            }
 
-                /* LAS */
-                0xbb => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    let mut data = self.mem_read(addr);
-                    data &= self.reg_sp;
-                    self.reg_a = data;
-                    self.reg_x = data;
-                    self.reg_sp = data;
-                    self.update_zero_and_negative_flags(data);
-                }
+            /* LXA */
+            0xab => {
+                self.lda(&opcode.mode);
+                self.tax();
+            }
 
-                /* TAS */
-                0x9b => {
-                    let data = self.reg_a & self.reg_x;
-                    self.reg_sp = data;
-                    let mem_address = self.mem_read_u16(self.reg_pc) + self.reg_y as u16;
+            /* XAA */
+            0x8b => {
+                self.reg_a = self.reg_x;
+                self.update_zero_and_negative_flags(self.reg_a);
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                self.and_with_reg_a(data);
+            }
 
-                    let data = ((mem_address >> 8) as u8 + 1) & self.reg_sp;
-                    self.mem_write(mem_address, data)
-                }
+            /* LAS */
+            0xbb => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let mut data = self.mem_read(addr);
+                data &= self.reg_sp;
+                self.reg_a = data;
+                self.reg_x = data;
+                self.reg_sp = data;
+                self.update_zero_and_negative_flags(data);
+            }
 
-                /* AHX  Indirect Y */
-                0x93 => {
-                    let pos: u8 = self.mem_read(self.reg_pc);
-                    let mem_address = self.mem_read_u16(pos as u16) + self.reg_y as u16;
-                    let data = self.reg_a & self.reg_x & (mem_address >> 8) as u8;
-                    self.mem_write(mem_address, data)
-                }
+            /* TAS */
+            0x9b => {
+                self.reg_sp = self.reg_a & self.reg_x;
+                let base = self.mem_read_u16(self.reg_pc);
+                self.unstable_store(base, self.reg_y, self.reg_sp);
+            }
 
-                /* AHX Absolute Y*/
-                0x9f => {
-                    let mem_address = self.mem_read_u16(self.reg_pc) + self.reg_y as u16;
+            /* AHX  Indirect Y */
+            0x93 => {
+                let pos: u8 = self.mem_read(self.reg_pc);
+                let base = self.mem_read_zp_u16(pos);
+                self.unstable_store(base, self.reg_y, self.reg_a & self.reg_x);
+            }
 
-                    let data = self.reg_a & self.reg_x & (mem_address >> 8) as u8;
-                    self.mem_write(mem_address, data)
-                }
+            /* AHX Absolute Y*/
+            0x9f => {
+                let base = self.mem_read_u16(self.reg_pc);
+                self.unstable_store(base, self.reg_y, self.reg_a & self.reg_x);
+            }
 
-                /* SHX */
-                0x9e => {
-                    let mem_address = self.mem_read_u16(self.reg_pc) + self.reg_y as u16;
+            /* SHX */
+            0x9e => {
+                let base = self.mem_read_u16(self.reg_pc);
+                self.unstable_store(base, self.reg_y, self.reg_x);
+            }
 
-                    // todo if cross page boundry {
-                    //     mem_address &= (self.x as u16) << 8;
-                    // }
-                    let data = self.reg_x & ((mem_address >> 8) as u8 + 1);
-                    self.mem_write(mem_address, data)
-                }
+            /* SHY */
+            0x9c => {
+                let base = self.mem_read_u16(self.reg_pc);
+                self.unstable_store(base, self.reg_x, self.reg_y);
+            }
 
-                /* SHY */
-                0x9c => {
-                    let mem_address = self.mem_read_u16(self.reg_pc) + self.reg_x as u16;
-                    let data = self.reg_y & ((mem_address >> 8) as u8 + 1);
-                    self.mem_write(mem_address, data)
-                }
+            _ => todo!(),
+        }
+
+        //busのcyclesを進める.
+        //NMIは最後から2番目のサイクルまでに発生したものを認識し、この命令の後に処理する.
+        //最後のサイクルで発生したNMIは次の命令の後に処理される.
+        self.bus.tick(cycles - 1);
+        self.nmi_pending |= self.bus.poll_nmi_status().is_some();
+        self.bus.tick(1);
+        //0x4014に書き込んだ場合はOAM DMAの間停止する.
+        //DMA中に発生したNMIは次の命令の後に処理される
+        self.bus.run_pending_oam_dma();
+
+        if let Some(profile) = &mut self.profile {
+            *profile.entry(instruction_pc).or_insert(0) +=
+                (self.bus.cycles() - start_cycles) as u64;
+        }
+
+        //program counterを進める
+        if program_counter_state == self.reg_pc {
+            self.reg_pc = self.reg_pc.wrapping_add((opcode.len - 1) as u16);
+        }
 
-                _ => todo!(),
+        callback(self);
+        true
+    }
+}
+
+//セーブステートはNESのBus(RAM/PPU/APU)を持つCpuだけが扱う
+impl Cpu<'_> {
+    ///CPU/RAM/PPU/APUとカートリッジの状態を複製する.
+    pub fn save_state(&self) -> MachineState {
+        MachineState {
+            registers: self.registers(),
+            ram: *self.bus.ram(),
+            ppu: self.bus.ppu().clone(),
+            apu: self.bus.apu().clone(),
+            cycles: self.bus.cycles(),
+            cartridge: self.bus.cartridge_state(),
+        }
+    }
+
+    ///`save_state`で複製した状態に戻す.
+    ///カートリッジの状態が現在のカートリッジと合わない場合は、CPUの状態を変更せずに`NesError::InvalidState`を返す.
+    ///
+    /// # Parameters
+    /// * `state` - 戻す状態
+    pub fn load_state(&mut self, state: &MachineState) -> Result<(), NesError> {
+        self.bus.restore(
+            &state.ram,
+            &state.ppu,
+            &state.apu,
+            state.cycles,
+            &state.cartridge,
+        )?;
+        self.restore(CpuState {
+            registers: state.registers,
+            irq_disable_delayed: state.registers.status.contains(CpuFlags::INTERRUPT_DISABLE),
+            nmi_pending: false,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod cpu_test {
+    use super::*;
+    use crate::cpu::assembler::assemble;
+    use crate::cpu::flat_bus::FlatBus;
+    use crate::rom::cartridge::RamCartridge;
+
+    fn run(program: &[u8]) -> Cpu<'static> {
+        let bus = Bus::new(RamCartridge::new(program, 0x2000), |_| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.run();
+        cpu
+    }
+
+    #[test]
+    fn zero_page_pointer_at_0xff_wraps() {
+        let cpu = run(&[
+            0xa9, 0x34, //       LDA #$34
+            0x85, 0xff, //       STA $FF
+            0xa9, 0x02, //       LDA #$02
+            0x85, 0x00, //       STA $00   (ポインタ $FF/$00 = $0234)
+            0xa9, 0x99, //       LDA #$99
+            0x8d, 0x34, 0x01, // STA $0134 (折り返さない場合の参照先)
+            0xa9, 0x42, //       LDA #$42
+            0x8d, 0x34, 0x02, // STA $0234
+            0xa9, 0x43, //       LDA #$43
+            0x8d, 0x35, 0x02, // STA $0235
+            0xa0, 0x01, //       LDY #$01
+            0xb1, 0xff, //       LDA ($FF),Y
+            0x00,
+        ]);
+        assert_eq!(cpu.reg_a, 0x43);
+
+        let cpu = run(&[
+            0xa9, 0x34, //       LDA #$34
+            0x85, 0xff, //       STA $FF
+            0xa9, 0x02, //       LDA #$02
+            0x85, 0x00, //       STA $00
+            0xa9, 0x42, //       LDA #$42
+            0x8d, 0x34, 0x02, // STA $0234
+            0xa2, 0xfe, //       LDX #$FE
+            0xa9, 0x00, //       LDA #$00
+            0xa1, 0x01, //       LDA ($01,X) (ポインタ $FF)
+            0x00,
+        ]);
+        assert_eq!(cpu.reg_a, 0x42);
+    }
+
+    #[test]
+    fn mem_read_u16_wraps_at_0xffff() {
+        let mut bus = Bus::new(RamCartridge::new(&[0x00], 0x2000), |_| {});
+        bus.mem_write(0x0000, 0x12);
+        //0xFFFFの次は0x0000
+        assert_eq!(bus.mem_read_u16(0xffff) & 0xff00, 0x1200);
+        bus.mem_write(0x00ff, 0xcd);
+        bus.mem_write(0x0100, 0x99);
+        assert_eq!(bus.mem_read_zp_u16(0xff), 0x12cd);
+    }
+
+    /// 1命令ずつ実行し、各命令のサイクル数を返す
+    fn instruction_cycles(program: &[u8], count: usize) -> Vec<usize> {
+        let bus = Bus::new(RamCartridge::new(program, 0x2000), |_| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        (0..count)
+            .map(|_| {
+                let start = cpu.bus.cycles();
+                cpu.step();
+                cpu.bus.cycles() - start
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unofficial_opcode_page_cross_cycles() {
+        let cycles = instruction_cycles(
+            &[
+                0xa0, 0x01, //       LDY #$01
+                0xbf, 0x00, 0x02, // LAX $0200,Y
+                0xbf, 0xff, 0x02, // LAX $02FF,Y (ページをまたぐ)
+                0xa2, 0x01, //       LDX #$01
+                0xdf, 0x00, 0x02, // DCP $0200,X
+                0xdf, 0xff, 0x02, // DCP $02FF,X
+                0x1c, 0xff, 0x02, // NOP $02FF,X
+            ],
+            7,
+        );
+        //LAX/NOPは読み出しのみなので+1、DCP(リードモディファイライト)は常に7
+        assert_eq!(cycles, vec![2, 4, 5, 2, 7, 7, 5]);
+    }
+
+    #[test]
+    fn sta_indirect_y_loop_with_assembler() {
+        let cpu = run(&assemble(
+            0x8000,
+            "
+                    LDA #$00
+                    STA $10
+                    LDA #$03
+                    STA $11      ; ($10) = $0300
+                    LDY #$04
+            fill:   TYA
+                    STA ($10),Y
+                    DEY
+                    BNE fill
+                    LDX $0302
+                    BRK
+            ",
+        ));
+        assert_eq!(cpu.reg_x, 0x02);
+        assert_eq!(cpu.reg_y, 0x00);
+        assert_eq!(cpu.bus.peek(0x0304), 0x04);
+        assert_eq!(cpu.bus.peek(0x0300), 0x00);
+    }
+
+    #[test]
+    fn jmp_indirect_page_wrap() {
+        let program = assemble(
+            0x8000,
+            "
+                    LDA #$05
+                    STA $02FF    ; 飛び先下位
+                    LDA #$80
+                    STA $0200    ; 飛び先上位(同じページの先頭)
+                    LDA #$90
+                    STA $0300    ; ページをまたいで読んだ場合の上位
+                    JMP ($02FF)
+            ",
+        );
+        let bus = Bus::new(RamCartridge::new(&program, 0x2000), |_| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        for _ in 0..6 {
+            cpu.step();
+        }
+        let start = cpu.bus.cycles();
+        cpu.step();
+        assert_eq!(cpu.bus.cycles() - start, 5);
+        assert_eq!(cpu.pc(), 0x8005);
+    }
+
+    #[test]
+    fn run_until_from_arbitrary_pc() {
+        let program = assemble(
+            0xc000,
+            "
+                    LDX #$00
+            loop:   INX
+                    CPX #$05
+                    BNE loop
+                    BRK
+                    NOP          ; BRKのパディング
+            back:   LDA #$01
+            ",
+        );
+        let mut cpu = Cpu::new(FlatBus::new());
+        for (i, data) in program.iter().enumerate() {
+            cpu.mem_write(0xc000 + i as u16, *data);
+        }
+        //IRQハンドラ(0xD000): RTI
+        cpu.mem_write(0xd000, 0x40);
+        cpu.mem_write_u16(0xfffe, 0xd000);
+        let back = 0xc000 + program.len() as u16 - 2;
+
+        //デフォルトではBRKで停止する
+        cpu.set_pc(0xc000);
+        assert_eq!(cpu.run_until(back, 100), StopReason::Halted);
+
+        cpu.set_pc(0xc000);
+        cpu.set_halt_on_brk(false);
+        assert_eq!(cpu.run_until(back, 5), StopReason::InstructionLimit);
+        assert_eq!(cpu.run_until(back, 100), StopReason::Reached);
+        assert_eq!(cpu.pc(), back);
+        assert_eq!(cpu.reg_x, 0x05);
+        assert_eq!(cpu.reg_a, 0x00);
+        assert_eq!(cpu.reg_sp, STACK_RESET);
+        //BRKが積んだステータス(PCの後)はBフラグが立っている
+        assert_eq!(
+            cpu.bus.peek(0x0100 + STACK_RESET as u16 - 2) & 0b0011_0000,
+            0b0011_0000
+        );
+    }
+
+    /// nestestの自動モード. ROMのパスを環境変数で指定した場合のみ実行する.
+    ///
+    /// ```text
+    /// % NES_RS_NESTEST=/path/to/nestest.nes cargo test nestest
+    /// ```
+    #[test]
+    fn nestest_automated() {
+        let path = match std::env::var("NES_RS_NESTEST") {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+        let rom = crate::rom::rom::Rom::load(&path).unwrap();
+        let mut cpu = Cpu::new(Bus::new(crate::mapper::nrom::Nrom::new(rom), |_| {}));
+        cpu.reset();
+        cpu.set_pc(0xc000);
+        cpu.set_halt_on_brk(false);
+        assert_eq!(cpu.run_until(0xc66e, 100_000), StopReason::Reached);
+        //0x02, 0x03 にエラーコードが書き込まれる(0は成功)
+        assert_eq!((cpu.bus.peek(0x02), cpu.bus.peek(0x03)), (0, 0));
+    }
+
+    #[test]
+    fn set_pc_jumps_to_routine() {
+        let bus = Bus::new(
+            RamCartridge::new(
+                &[
+                    0xa9, 0x01, // LDA #$01
+                    0x00, //
+                    0xa9, 0x42, // routine: LDA #$42
+                    0x00,
+                ],
+                0x2000,
+            ),
+            |_| {},
+        );
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        assert_eq!(cpu.pc(), 0x8000);
+
+        cpu.set_pc(0x8003);
+        assert_eq!(cpu.pc(), 0x8003);
+        cpu.step();
+        assert_eq!(cpu.reg_a, 0x42);
+        assert_eq!(cpu.pc(), 0x8005);
+    }
+
+    #[test]
+    fn cli_delays_pending_irq_by_one_instruction() {
+        let program = [
+            0xa9, 0x8f, //       LDA #$8F (DMC IRQ有効)
+            0x8d, 0x10, 0x40, // STA $4010
+            0xa9, 0x00, //       LDA #$00 (1byte)
+            0x8d, 0x13, 0x40, // STA $4013
+            0xa9, 0x10, //       LDA #$10
+            0x8d, 0x15, 0x40, // STA $4015
+            0xad, 0x15, 0x40, // wait: LDA $4015
+            0x29, 0x80, //       AND #$80
+            0xf0, 0xf9, //       BEQ wait
+            0x58, //             CLI
+            0xe8, //             INX
+            0xe8, //             INX
+            0x00, //             BRK
+        ];
+        let mut cartridge = RamCartridge::new(&program, 0x2000);
+        //IRQハンドラ(0x8020): STX $00, BRK
+        cartridge.program_data[0x20..0x23].copy_from_slice(&[0x86, 0x00, 0x00]);
+        cartridge.program_data[0x7ffe] = 0x20;
+        cartridge.program_data[0x7fff] = 0x80;
+
+        let bus = Bus::new(cartridge, |_| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.run();
+
+        //CLIの次のINXを実行してからIRQが処理される
+        assert_eq!(cpu.mem_read(0x00), 1);
+    }
+
+    #[test]
+    fn indexed_store_dummy_read() {
+        let bus = Bus::new(
+            RamCartridge::new(
+                &[
+                    0xa9, 0x21, //       LDA #$21
+                    0x8d, 0x06, 0x20, // STA $2006
+                    0xa9, 0x00, //       LDA #$00
+                    0x8d, 0x06, 0x20, // STA $2006 (PPUADDR = $2100)
+                    0xa2, 0x10, //       LDX #$10
+                    0xa9, 0x55, //       LDA #$55
+                    0x9d, 0xf7, 0x20, // STA $20F7,X
+                    0x00,
+                ],
+                0x2000,
+            ),
+            |_| {},
+        );
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.run();
+
+        //$2107(PPUDATAのミラー)への書き込みの前に$2007のダミーリードで
+        //VRAMアドレスがインクリメントされる
+        assert_eq!(cpu.bus.ppu().vram[0x100], 0x00);
+        assert_eq!(cpu.bus.ppu().vram[0x101], 0x55);
+    }
+
+    #[test]
+    fn operand_bytes_are_read_once() {
+        let program = assemble(
+            0x8000,
+            "
+                    LDX #$01
+                    STA $02FF,X  ; ダミーリードあり
+                    STA ($10),Y
+                    INC $0300,X
+                    LDA $02FF,X  ; ページをまたぐ
+                    BRK
+            ",
+        );
+        let mut bus = Bus::new(RamCartridge::new(&program, 0x2000), |_| {});
+        let reads = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+        let sink = reads.clone();
+        bus.set_read_watchpoint(0x0000..=0xffff, move |addr| sink.borrow_mut().push(addr));
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.run();
+
+        let reads = reads.borrow();
+        //オペランド(STA/STA/INC/LDA)とゼロページのポインタ
+        for addr in [
+            0x8003, 0x8004, 0x8006, 0x0010, 0x0011, 0x8008, 0x8009, 0x800b, 0x800c,
+        ] {
+            let count = reads.iter().filter(|&&read| read == addr).count();
+            assert_eq!(count, 1, "${:04X} was read {} times", addr, count);
+        }
+    }
+
+    #[test]
+    fn branch_page_cross_dummy_read() {
+        // JMP $02F0
+        let mut bus = Bus::new(RamCartridge::new(&[0x4c, 0xf0, 0x02], 0x2000), |_| {});
+        let reads = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+        let sink = reads.clone();
+        bus.set_uninit_read_callback(move |addr, _pc| sink.borrow_mut().push(addr));
+        //RAM上のプログラム
+        bus.mem_write(0x02f0, 0xd0); // BNE $0310
+        bus.mem_write(0x02f1, 0x1e);
+        bus.mem_write(0x02f2, 0xea); // NOP
+        bus.mem_write(0x0310, 0x00); // BRK
+
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.step();
+        cpu.step();
+
+        assert_eq!(cpu.pc(), 0x0310);
+        //上位バイトを補正する前の$0210を読む
+        assert_eq!(*reads.borrow(), vec![0x0210]);
+    }
+
+    #[test]
+    fn flag_and_set_flag() {
+        let mut cpu = Cpu::new(FlatBus::new());
+        cpu.status = CpuFlags::empty();
+        for flag in [
+            CpuFlags::CARRY,
+            CpuFlags::ZERO,
+            CpuFlags::INTERRUPT_DISABLE,
+            CpuFlags::DECIMAL_MODE,
+            CpuFlags::BREAK,
+            CpuFlags::BREAK2,
+            CpuFlags::OVERFLOW,
+            CpuFlags::NEGATIV,
+        ] {
+            assert!(!cpu.flag(flag));
+            cpu.set_flag(flag, true);
+            assert!(cpu.flag(flag));
+            assert_eq!(cpu.status, flag);
+            cpu.set_flag(flag, false);
+            assert!(!cpu.flag(flag));
+            assert!(cpu.status.is_empty());
+        }
+    }
+
+    /// シフトして0になったらBEQで抜けるループ.
+    /// メモリへのROL/RORでZフラグが更新されないと、直前のLDX #$00のZフラグで
+    /// 1周目に抜けてしまう(またはCPXのZフラグで回り続ける).
+    fn shift_until_zero(shift: u8, value: u8) -> Cpu<'static> {
+        run(&[
+            0xa9, value, //      LDA #value
+            0x85, 0x10, //       STA $10
+            0xa2, 0x00, //       LDX #$00
+            0x18, //             CLC
+            0xe8, //             loop: INX
+            shift, 0x10, //      ROL/ROR $10
+            0xf0, 0x04, //       BEQ done
+            0xe0, 0x10, //       CPX #$10
+            0xd0, 0xf7, //       BNE loop
+            0x00, //             done: BRK
+        ])
+    }
+
+    #[test]
+    fn rol_memory_updates_zero_flag() {
+        // $20 -> $40 -> $80 -> $00 (C=1)
+        let mut cpu = shift_until_zero(0x26, 0x20);
+        assert_eq!(cpu.reg_x, 3);
+        assert_eq!(cpu.mem_read(0x10), 0x00);
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+        assert!(!cpu.status.contains(CpuFlags::NEGATIV));
+    }
+
+    #[test]
+    fn ror_memory_updates_zero_flag() {
+        // $04 -> $02 -> $01 -> $00 (C=1)
+        let mut cpu = shift_until_zero(0x66, 0x04);
+        assert_eq!(cpu.reg_x, 3);
+        assert_eq!(cpu.mem_read(0x10), 0x00);
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+    }
+
+    #[test]
+    fn profile_report() {
+        let bus = Bus::new(
+            RamCartridge::new(
+                &[
+                    0xa2, 0x00, // LDX #$00
+                    0xe8, //       loop: INX
+                    0xd0, 0xfd, // BNE loop
+                    0x00,
+                ],
+                0x2000,
+            ),
+            |_| {},
+        );
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        assert!(cpu.profile_report().is_empty());
+        cpu.enable_profiler();
+        cpu.run();
+
+        let report = cpu.profile_report();
+        assert_eq!(report.len(), 3);
+        assert_eq!(report[0].0, 0x8002);
+        assert_eq!(report[0].1, 256 * 2);
+        assert_eq!(report[1].0, 0x8003);
+        assert_eq!(report[2], (0x8000, 2));
+    }
+
+    #[test]
+    fn recent_trace_keeps_last_instructions() {
+        let bus = Bus::new(
+            RamCartridge::new(
+                &[
+                    0xa2, 0x00, // LDX #$00
+                    0xe8, //       loop: INX
+                    0xe0, 0x03, // CPX #$03
+                    0xd0, 0xfb, // BNE loop
+                    0x00,
+                ],
+                0x2000,
+            ),
+            |_| {},
+        );
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.enable_trace_buffer(4);
+        cpu.run();
+
+        let trace = cpu.recent_trace();
+        let instructions: Vec<&str> = trace.iter().map(|line| &line[..19]).collect();
+        assert_eq!(
+            instructions,
+            vec![
+                "8002  INX          ",
+                "8003  CPX #$03     ",
+                "8005  BNE $8002    ",
+                "8007  BRK          ",
+            ]
+        );
+        assert!(trace[1].contains("X:03"));
+    }
+
+    #[test]
+    fn oam_dma_cycles_by_parity() {
+        let mut stalls = vec![];
+        for offset in 0..2 {
+            let program = [
+                0xa9, 0x02, //       LDA #$02
+                0x8d, 0x14, 0x40, // STA $4014
+                0x00,
+            ];
+            let mut cpu = Cpu::new(Bus::new(RamCartridge::new(&program, 0x2000), |_| {}));
+            cpu.reset();
+            cpu.enable_trace_buffer(4);
+            for _ in 0..offset {
+                cpu.bus.tick(1);
             }
+            cpu.step();
+            let start = cpu.bus.cycles();
+            cpu.step();
+
+            //STAの4サイクルの次のサイクルから停止する
+            let stall = cpu.bus.cycles() - start - 4;
+            let expected = if (start + 4) % 2 == 0 { 513 } else { 514 };
+            assert_eq!(stall, expected);
+            assert_eq!(
+                cpu.bus.ppu().scanline() as usize,
+                cpu.bus.cycles() * 3 / 341
+            );
+            stalls.push(stall);
+
+            //トレースの次の命令のサイクル数にDMAが含まれる
+            cpu.step();
+            let trace = cpu.recent_trace();
+            assert!(trace[2].ends_with(&format!("CYC:{}", start + 4 + stall)));
+        }
+        stalls.sort();
+        assert_eq!(stalls, vec![513, 514]);
+    }
+
+    #[test]
+    fn pc_wraps_at_top_of_address_space() {
+        let mut cpu = Cpu::new(FlatBus::new());
+        cpu.mem_write(0xffff, 0xea); // NOP
+        cpu.set_pc(0xffff);
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x0000);
+
+        //オペランドは0x0000から読み出される
+        cpu.mem_write(0xffff, 0xa9); // LDA #$42
+        cpu.mem_write(0x0000, 0x42);
+        cpu.set_pc(0xffff);
+        cpu.step();
+        assert_eq!(cpu.reg_a, 0x42);
+        assert_eq!(cpu.pc(), 0x0001);
+    }
 
-            //busのcyclesを進める
-            self.bus.tick(opcode.cycles);
+    fn run_variant(program: &[u8], variant: CpuVariant) -> Cpu<'static> {
+        let bus = Bus::new(RamCartridge::new(program, 0x2000), |_| {});
+        let mut cpu = Cpu::new_with_variant(bus, variant);
+        cpu.reset();
+        cpu.run();
+        cpu
+    }
+
+    #[test]
+    fn adc_with_decimal_flag() {
+        let program = [
+            0xf8, //       SED
+            0x18, //       CLC
+            0xa9, 0x19, // LDA #$19
+            0x69, 0x28, // ADC #$28
+            0x00,
+        ];
+
+        let cpu = run_variant(&program, CpuVariant::Nes2A03);
+        assert_eq!(cpu.variant(), CpuVariant::Nes2A03);
+        assert_eq!(cpu.reg_a, 0x41);
+
+        let cpu = run_variant(&program, CpuVariant::Mos6502);
+        assert_eq!(cpu.reg_a, 0x47);
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+    }
+
+    #[test]
+    fn adc_with_decimal_flag_carry() {
+        let cpu = run_variant(
+            &[
+                0xf8, //       SED
+                0x18, //       CLC
+                0xa9, 0x99, // LDA #$99
+                0x69, 0x01, // ADC #$01
+                0x00,
+            ],
+            CpuVariant::Mos6502,
+        );
+        assert_eq!(cpu.reg_a, 0x00);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+    }
+
+    #[test]
+    fn sbc_with_decimal_flag() {
+        let program = [
+            0xf8, //       SED
+            0x38, //       SEC
+            0xa9, 0x42, // LDA #$42
+            0xe9, 0x15, // SBC #$15
+            0x00,
+        ];
+
+        let cpu = run_variant(&program, CpuVariant::Nes2A03);
+        assert_eq!(cpu.reg_a, 0x2d);
+
+        let cpu = run_variant(&program, CpuVariant::Mos6502);
+        assert_eq!(cpu.reg_a, 0x27);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+    }
 
-            //program counterを進める
-            if program_counter_state == self.reg_pc {
-                self.reg_pc += (opcode.len - 1) as u16;
+    /// ADC/SBCのリファレンス(符号付き/符号なしの演算から直接フラグを求める).
+    /// (A, C, V, N, Z)を返す
+    fn reference_adc_sbc(a: u8, m: u8, carry: bool, sbc: bool) -> (u8, bool, bool, bool, bool) {
+        let (unsigned, signed) = if sbc {
+            let borrow = !carry as i16;
+            (
+                a as i16 - m as i16 - borrow,
+                a as i8 as i16 - m as i8 as i16 - borrow,
+            )
+        } else {
+            let carry = carry as i16;
+            (
+                a as i16 + m as i16 + carry,
+                a as i8 as i16 + m as i8 as i16 + carry,
+            )
+        };
+        let result = unsigned as u8;
+        let c = if sbc { unsigned >= 0 } else { unsigned > 0xff };
+        let v = !(-128..=127).contains(&signed);
+        (result, c, v, result & 0x80 != 0, result == 0)
+    }
+
+    /// オペランドmをメモリに配置し、A=a, X=1, Y=1, キャリーを設定して`instruction`を実行するプログラム
+    fn adc_sbc_program(a: u8, m: u8, carry: bool, instruction: &[u8]) -> Vec<u8> {
+        let mut program = vec![
+            0xa9, m, //          LDA #m
+            0x85, 0x10, //       STA $10
+            0x8d, 0x00, 0x02, // STA $0200
+            0xa9, 0x00, //       LDA #$00
+            0x85, 0x20, //       STA $20
+            0xa9, 0x02, //       LDA #$02
+            0x85, 0x21, //       STA $21
+            0xa9, 0xff, //       LDA #$FF
+            0x85, 0x22, //       STA $22
+            0xa9, 0x01, //       LDA #$01
+            0x85, 0x23, //       STA $23
+            0xa2, 0x01, //       LDX #$01
+            0xa0, 0x01, //       LDY #$01
+        ];
+        //SEC / CLC
+        program.push(if carry { 0x38 } else { 0x18 });
+        //LDA #a
+        program.extend_from_slice(&[0xa9, a]);
+        program.extend_from_slice(instruction);
+        program.push(0x00);
+        program
+    }
+
+    #[test]
+    fn adc_sbc_flags_all_addressing_modes() {
+        //(オペコード, オペランド). X=1, Y=1
+        //zp,X: $0F+X, abs,X/abs,Y: $01FF+X/Y, (ind,X): ($1F+X)=$0200, (ind),Y: ($22)+Y=$01FF+Y
+        let modes = |base: [u8; 8], m: u8| -> Vec<Vec<u8>> {
+            vec![
+                vec![base[0], m],
+                vec![base[1], 0x10],
+                vec![base[2], 0x0f],
+                vec![base[3], 0x00, 0x02],
+                vec![base[4], 0xff, 0x01],
+                vec![base[5], 0xff, 0x01],
+                vec![base[6], 0x1f],
+                vec![base[7], 0x22],
+            ]
+        };
+        const ADC: [u8; 8] = [0x69, 0x65, 0x75, 0x6d, 0x7d, 0x79, 0x61, 0x71];
+        const SBC: [u8; 8] = [0xe9, 0xe5, 0xf5, 0xed, 0xfd, 0xf9, 0xe1, 0xf1];
+        let pairs = [
+            (0x50, 0x10),
+            (0x50, 0x50),
+            (0x50, 0x90),
+            (0x50, 0xd0),
+            (0xd0, 0x10),
+            (0xd0, 0x50),
+            (0xd0, 0x90),
+            (0xd0, 0xd0),
+            (0x00, 0x00),
+            (0xff, 0x01),
+            (0x80, 0xff),
+            (0x7f, 0x00),
+        ];
+
+        for (sbc, opcodes) in [(false, ADC), (true, SBC)] {
+            for (a, m) in pairs {
+                for carry in [false, true] {
+                    let mut instructions = modes(opcodes, m);
+                    if sbc {
+                        //非公式のSBC #imm
+                        instructions.push(vec![0xeb, m]);
+                    }
+                    for instruction in instructions {
+                        let program = adc_sbc_program(a, m, carry, &instruction);
+                        let cpu = run_variant(&program, CpuVariant::Nes2A03);
+                        let expected = reference_adc_sbc(a, m, carry, sbc);
+                        let actual = (
+                            cpu.reg_a,
+                            cpu.status.contains(CpuFlags::CARRY),
+                            cpu.status.contains(CpuFlags::OVERFLOW),
+                            cpu.status.contains(CpuFlags::NEGATIV),
+                            cpu.status.contains(CpuFlags::ZERO),
+                        );
+                        assert_eq!(
+                            actual, expected,
+                            "{:02X?} A={:02X} M={:02X} C={}",
+                            instruction, a, m, carry
+                        );
+                    }
+                }
             }
+        }
+    }
 
-            callback(self);
+    #[test]
+    fn dcp_equal_sets_carry_and_zero() {
+        let mut cpu = run(&[
+            0xa9, 0x11, // LDA #$11
+            0x85, 0x10, // STA $10
+            0xa9, 0x10, // LDA #$10
+            0x18, //       CLC
+            0xc7, 0x10, // DCP $10
+            0x00,
+        ]);
+        assert_eq!(cpu.mem_read(0x10), 0x10);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+        assert!(!cpu.status.contains(CpuFlags::NEGATIV));
+    }
+
+    #[test]
+    fn dcp_greater_clears_carry_and_sets_negative() {
+        let mut cpu = run(&[
+            0xa9, 0x12, // LDA #$12
+            0x85, 0x10, // STA $10
+            0xa9, 0x10, // LDA #$10
+            0x38, //       SEC
+            0xc7, 0x10, // DCP $10
+            0x00,
+        ]);
+        // 0x10 - 0x11 = 0xFF
+        assert_eq!(cpu.mem_read(0x10), 0x11);
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+        assert!(!cpu.status.contains(CpuFlags::ZERO));
+        assert!(cpu.status.contains(CpuFlags::NEGATIV));
+    }
+
+    #[test]
+    fn dcp_wraps_memory_below_zero() {
+        let mut cpu = run(&[
+            0xa9, 0x90, // LDA #$90
+            0x38, //       SEC
+            0xc7, 0x10, // DCP $10 (0x00 -> 0xFF)
+            0x00,
+        ]);
+        // 0x90 - 0xFF = 0x91
+        assert_eq!(cpu.mem_read(0x10), 0xff);
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+        assert!(!cpu.status.contains(CpuFlags::ZERO));
+        assert!(cpu.status.contains(CpuFlags::NEGATIV));
+    }
+
+    #[test]
+    fn shx_without_page_cross() {
+        let mut cpu = run(&[
+            0xa2, 0xff, //       LDX #$FF
+            0xa0, 0x01, //       LDY #$01
+            0x9e, 0x00, 0x02, // SHX $0200,Y
+            0x00,
+        ]);
+        // X & (0x02 + 1)
+        assert_eq!(cpu.mem_read(0x0201), 0x03);
+    }
+
+    #[test]
+    fn shx_with_page_cross() {
+        let mut cpu = run(&[
+            0xa2, 0x05, //       LDX #$05
+            0xa0, 0x02, //       LDY #$02
+            0x9e, 0xff, 0x06, // SHX $06FF,Y
+            0x00,
+        ]);
+        // 値は X & (0x06 + 1) = 0x05 で、上位バイトもその値に置き換わる
+        assert_eq!(cpu.mem_read(0x0701), 0x00);
+        assert_eq!(cpu.mem_read(0x0501), 0x05);
+    }
+
+    #[test]
+    fn shy_without_page_cross() {
+        let mut cpu = run(&[
+            0xa0, 0xff, //       LDY #$FF
+            0xa2, 0x01, //       LDX #$01
+            0x9c, 0x00, 0x02, // SHY $0200,X
+            0x00,
+        ]);
+        assert_eq!(cpu.mem_read(0x0201), 0x03);
+    }
+
+    #[test]
+    fn shy_with_page_cross() {
+        let mut cpu = run(&[
+            0xa0, 0x05, //       LDY #$05
+            0xa2, 0x02, //       LDX #$02
+            0x9c, 0xff, 0x06, // SHY $06FF,X
+            0x00,
+        ]);
+        assert_eq!(cpu.mem_read(0x0701), 0x00);
+        assert_eq!(cpu.mem_read(0x0501), 0x05);
+    }
+
+    #[test]
+    fn tas_wraps_address_space() {
+        let mut cpu = run(&[
+            0xa9, 0x55, //       LDA #$55
+            0x85, 0x01, //       STA $01
+            0xa9, 0xff, //       LDA #$FF
+            0xa2, 0xff, //       LDX #$FF
+            0xa0, 0x02, //       LDY #$02
+            0x9b, 0xff, 0xff, // TAS $FFFF,Y
+            0x00,
+        ]);
+        // $FFFF + 2 は $0001 に折り返し、値は SP & (0xFF + 1) = 0
+        assert_eq!(cpu.reg_sp, 0xff);
+        assert_eq!(cpu.mem_read(0x0001), 0x00);
+    }
+
+    #[test]
+    fn disassemble_range_listing() {
+        let bus = Bus::new(
+            RamCartridge::new(
+                &[
+                    0xa9, 0x01, //       LDA #$01
+                    0x8d, 0x07, 0x20, // STA $2007
+                    0xb1, 0x10, //       LDA ($10),Y
+                    0x0a, //             ASL A
+                    0xd0, 0xf6, //       BNE $8000
+                    0x6c, 0x34, 0x12, // JMP ($1234)
+                    0xe8, //             INX
+                    0x02, //             *NOP (非公式命令)
+                ],
+                0x2000,
+            ),
+            |_| {},
+        );
+        let cpu = Cpu::new(bus);
+        let listing = cpu.disassemble_range(0x8000, 8);
+        let expected = [
+            (0x8000, "LDA #$01"),
+            (0x8002, "STA $2007"),
+            (0x8005, "LDA ($10),Y"),
+            (0x8007, "ASL A"),
+            (0x8008, "BNE $8000"),
+            (0x800a, "JMP ($1234)"),
+            (0x800d, "INX"),
+            (0x800e, "*NOP"),
+        ];
+        let expected: Vec<(u16, String)> = expected
+            .iter()
+            .map(|(addr, instruction)| (*addr, instruction.to_string()))
+            .collect();
+        assert_eq!(listing, expected);
+    }
+
+    #[test]
+    fn disassemble_window_around_pc() {
+        let program = assemble(
+            0x8000,
+            "
+                    LDA #$01
+                    STA $0200
+                    LDX #$02
+                    INX
+                    STX $0201
+                    JMP $8000
+            ",
+        );
+        let bus = Bus::new(RamCartridge::new(&program, 0x2000), |_| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        for _ in 0..3 {
+            cpu.step();
         }
+        assert_eq!(cpu.pc(), 0x8007);
+
+        let window = cpu.disassemble_window(2, 2);
+        let lines: Vec<(u16, &str, bool)> = window
+            .iter()
+            .map(|(addr, instruction, current)| (*addr, instruction.as_str(), *current))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (0x8002, "STA $0200", false),
+                (0x8005, "LDX #$02", false),
+                (0x8007, "INX", true),
+                (0x8008, "STX $0201", false),
+                (0x800B, "JMP $8000", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn disassemble_range_has_no_side_effects() {
+        // LDA $2002
+        let bus = Bus::new(RamCartridge::new(&[0xad, 0x02, 0x20], 0x2000), |_| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.bus.ppu_mut().status.set_vblank_status(true);
+        cpu.disassemble_range(0x2000, 4);
+        assert_eq!(cpu.bus.ppu().status.snapshot() & 0b1000_0000, 0b1000_0000);
+    }
+
+    #[test]
+    fn run_until_return_steps_out_of_subroutine() {
+        let bus = Bus::new(
+            RamCartridge::new(
+                &[
+                    0x20, 0x06, 0x80, // JSR $8006
+                    0xa2, 0x01, //       LDX #$01
+                    0x00, //             BRK
+                    0xa9, 0x05, //       $8006: LDA #$05
+                    0x20, 0x0c, 0x80, // JSR $800C
+                    0x60, //             RTS
+                    0xc8, //             $800C: INY
+                    0x60, //             RTS
+                ],
+                0x2000,
+            ),
+            |_| {},
+        );
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        let sp = cpu.reg_sp;
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x8006);
+
+        assert!(cpu.run_until_return());
+        assert_eq!(cpu.pc(), 0x8003);
+        assert_eq!(cpu.reg_sp, sp);
+        //ネストしたサブルーチンは実行済み
+        assert_eq!(cpu.reg_a, 0x05);
+        assert_eq!(cpu.reg_y, 0x01);
+        assert_eq!(cpu.reg_x, 0x00);
+    }
+
+    #[test]
+    fn run_until_return_ignores_interrupt_handler_rti() {
+        let mut program = vec![
+            0x20, 0x06, 0x80, // JSR $8006
+            0xa2, 0x01, //       LDX #$01
+            0x00, //             BRK
+            0xa9, 0x05, //       $8006: LDA #$05
+            0xea, //             NOP
+            0x60, //             RTS
+            0xc8, //             $800A: NMI: INY
+            0x40, //             RTI
+        ];
+        program.resize(0x7ffa, 0);
+        //NMIベクタ
+        program.extend_from_slice(&[0x0a, 0x80]);
+        let bus = Bus::new(RamCartridge::new(&program, 0x2000), |_| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.step();
+        cpu.bus.ppu_mut().nmi_interrupt = Some(1);
+
+        assert!(cpu.run_until_return());
+        assert_eq!(cpu.pc(), 0x8003);
+        assert_eq!(cpu.reg_y, 0x01);
+    }
+
+    /// 全てNOPのプログラムで、NMIを有効にしてCPUサイクル`start`からNOPを実行する
+    fn nmi_timing_cpu<'a>(start: usize) -> Cpu<'a> {
+        let mut program = vec![0xea; 0x7ffa];
+        //NMIベクタ
+        program.extend_from_slice(&[0x00, 0x90]);
+        let bus = Bus::new(RamCartridge::new(&program, 0x2000), |_| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.bus.mem_write(0x2000, 0x80);
+        while cpu.bus.cycles() < start {
+            cpu.bus.tick(1);
+        }
+        cpu
+    }
+
+    #[test]
+    fn nmi_recognized_before_last_cycle() {
+        //スキャンライン241のドット0(PPUサイクル 241*341)はCPUサイクル27394の中
+        const VBLANK_CYCLE: usize = 241 * 341 / 3 + 1;
+
+        //NMIがNOPの1サイクル目で発生: NOPの後に処理する
+        let mut cpu = nmi_timing_cpu(VBLANK_CYCLE - 1);
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x8001);
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x9001);
+
+        //NMIがNOPの最後のサイクルで発生: 次の命令の後に処理する
+        let mut cpu = nmi_timing_cpu(VBLANK_CYCLE - 2);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x8002);
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x9001);
     }
 }
@@ -0,0 +1,56 @@
+use super::bus::CpuBus;
+use super::cpu::Memory;
+
+/// FlatBus Struct
+/// 0x0000~0xFFFFの全域をRAMとして扱うテスト用のバス.
+/// PPU/APU/カートリッジを持たず、CPU単体のテスト(命令単位のテストスイート等)で使用する.
+///
+/// # Parameters
+/// * `ram` - 64KiBのRAM
+/// * `cycles` - 電源投入からのCPUサイクル数
+pub struct FlatBus {
+    ram: Vec<u8>,
+    cycles: usize,
+}
+
+impl FlatBus {
+    ///FlatBusコンストラクタ. RAMは0で初期化する.
+    pub fn new() -> Self {
+        FlatBus {
+            ram: vec![0; 0x10000],
+            cycles: 0,
+        }
+    }
+}
+
+impl Memory for FlatBus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.ram[addr as usize] = data;
+    }
+}
+
+impl CpuBus for FlatBus {
+    fn tick(&mut self, cycles: u8) {
+        self.cycles += cycles as usize;
+    }
+
+    fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    fn poll_nmi_status(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn poll_irq_status(&self) -> bool {
+        false
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+}
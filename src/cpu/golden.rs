@@ -0,0 +1,144 @@
+//! TomHarte/ProcessorTests (65x02) 形式の命令単位テスト.
+//!
+//! 各テストケースの初期状態を全域RAMのバス(`FlatBus`)に設定し、`Cpu::step` で1命令実行した後の
+//! レジスタ・メモリ・サイクル数を期待値と比較する.
+//!
+//! `tests/65x02` に同梱しているファイルは常に実行する. テストスイート全体
+//! (https://github.com/SingleStepTests/ProcessorTests の `nes6502/v1`) を実行する場合は
+//! ディレクトリを環境変数で指定する.
+//!
+//! ```text
+//! % NES_RS_65X02_TESTS=/path/to/nes6502/v1 cargo test --features golden golden
+//! ```
+use super::bus::CpuBus;
+use super::cpu::{Cpu, CpuFlags, Memory};
+use super::flat_bus::FlatBus;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+const VENDORED_DIR: &str = "tests/65x02";
+const TESTS_DIR_ENV: &str = "NES_RS_65X02_TESTS";
+
+fn field(state: &Value, name: &str) -> u64 {
+    state[name]
+        .as_u64()
+        .unwrap_or_else(|| panic!("missing field {}", name))
+}
+
+fn ram(state: &Value) -> Vec<(u16, u8)> {
+    state["ram"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|cell| {
+            (
+                cell[0].as_u64().unwrap() as u16,
+                cell[1].as_u64().unwrap() as u8,
+            )
+        })
+        .collect()
+}
+
+/// 1テストケースを実行する. 失敗した場合はその内容を返す.
+fn run_case(case: &Value) -> Result<(), String> {
+    let name = case["name"].as_str().unwrap_or("?");
+    let initial = &case["initial"];
+    let expected = &case["final"];
+
+    let mut cpu = Cpu::new(FlatBus::new());
+    //BRKは停止せずに割り込みとして実行する
+    cpu.set_halt_on_brk(false);
+    cpu.reg_pc = field(initial, "pc") as u16;
+    cpu.reg_sp = field(initial, "s") as u8;
+    cpu.reg_a = field(initial, "a") as u8;
+    cpu.reg_x = field(initial, "x") as u8;
+    cpu.reg_y = field(initial, "y") as u8;
+    cpu.status = CpuFlags::from_bits_truncate(field(initial, "p") as u8);
+    for (addr, data) in ram(initial) {
+        cpu.mem_write(addr, data);
+    }
+
+    let start_cycles = cpu.bus.cycles();
+    cpu.step();
+    let cycles = cpu.bus.cycles() - start_cycles;
+
+    let registers = [
+        ("pc", cpu.reg_pc as u64),
+        ("s", cpu.reg_sp as u64),
+        ("a", cpu.reg_a as u64),
+        ("x", cpu.reg_x as u64),
+        ("y", cpu.reg_y as u64),
+        ("p", cpu.status.bits() as u64),
+    ];
+    for (register, actual) in registers.iter() {
+        if field(expected, register) != *actual {
+            return Err(format!(
+                "{}: {} expected {:#x} but was {:#x}",
+                name,
+                register,
+                field(expected, register),
+                actual
+            ));
+        }
+    }
+    for (addr, data) in ram(expected) {
+        let actual = cpu.mem_read(addr);
+        if actual != data {
+            return Err(format!(
+                "{}: ram[{:#06x}] expected {:#04x} but was {:#04x}",
+                name, addr, data, actual
+            ));
+        }
+    }
+    let expected_cycles = case["cycles"].as_array().unwrap().len();
+    if cycles != expected_cycles {
+        return Err(format!(
+            "{}: cycles expected {} but was {}",
+            name, expected_cycles, cycles
+        ));
+    }
+    Ok(())
+}
+
+/// ディレクトリ内の全てのJSONファイルを実行し、失敗したケースを返す.
+fn run_dir(dir: &Path) -> Vec<String> {
+    let mut failures = Vec::new();
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let cases: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        for case in cases.as_array().unwrap() {
+            if let Err(failure) = run_case(case) {
+                failures.push(format!("{}: {}", path.display(), failure));
+            }
+        }
+    }
+    failures
+}
+
+#[test]
+fn golden_vendored() {
+    let failures = run_dir(Path::new(VENDORED_DIR));
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn golden_full_suite() {
+    let dir = match std::env::var(TESTS_DIR_ENV) {
+        Ok(dir) => dir,
+        Err(_) => return,
+    };
+    let failures = run_dir(Path::new(&dir));
+    assert!(
+        failures.is_empty(),
+        "{} failures\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
@@ -31,6 +31,31 @@ impl OpCode {
     }
 }
 
+/// インデックスの加算でページをまたいだ場合に1サイクル追加される命令.
+/// 読み出しのみの命令(Absolute_X/Absolute_Y/Indirect_Y)が該当する.
+/// 書き込み/リードモディファイライト命令は常にページをまたぐ場合のサイクル数がかかる.
+const PAGE_CROSS_PENALTY: [u8; 32] = [
+    0x7d, 0x79, 0x71, // ADC
+    0xfd, 0xf9, 0xf1, // SBC
+    0x3d, 0x39, 0x31, // AND
+    0x5d, 0x59, 0x51, // EOR
+    0x1d, 0x19, 0x11, // ORA
+    0xdd, 0xd9, 0xd1, // CMP
+    0xbd, 0xb9, 0xb1, // LDA
+    0xbe, // LDX
+    0xbc, // LDY
+    0xbf, 0xb3, // *LAX
+    0xbb, // *LAS
+    0x1c, 0x3c, 0x5c, 0x7c, 0xdc, 0xfc, // *NOP
+];
+
+impl OpCode {
+    /// ページをまたいだ場合に1サイクル追加されるかどうか
+    pub fn has_page_cross_penalty(&self) -> bool {
+        PAGE_CROSS_PENALTY.contains(&self.code)
+    }
+}
+
 lazy_static! {
     pub static ref CPU_OPS_CODES: Vec<OpCode> = vec![
         OpCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing),
@@ -293,12 +318,12 @@ lazy_static! {
         OpCode::new(0xd4, "*NOP", 2, 4, AddressingMode::ZeroPage_X),
         OpCode::new(0xf4, "*NOP", 2, 4, AddressingMode::ZeroPage_X),
         OpCode::new(0x0c, "*NOP", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x1c, "*NOP", 3, 4 /*or 5*/, AddressingMode::Absolute_X),
-        OpCode::new(0x3c, "*NOP", 3, 4 /*or 5*/, AddressingMode::Absolute_X),
-        OpCode::new(0x5c, "*NOP", 3, 4 /*or 5*/, AddressingMode::Absolute_X),
-        OpCode::new(0x7c, "*NOP", 3, 4 /*or 5*/, AddressingMode::Absolute_X),
-        OpCode::new(0xdc, "*NOP", 3, 4 /* or 5*/, AddressingMode::Absolute_X),
-        OpCode::new(0xfc, "*NOP", 3, 4 /* or 5*/, AddressingMode::Absolute_X),
+        OpCode::new(0x1c, "*NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X),
+        OpCode::new(0x3c, "*NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X),
+        OpCode::new(0x5c, "*NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X),
+        OpCode::new(0x7c, "*NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X),
+        OpCode::new(0xdc, "*NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X),
+        OpCode::new(0xfc, "*NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X),
 
         OpCode::new(0x67, "*RRA", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0x77, "*RRA", 2, 6, AddressingMode::ZeroPage_X),
@@ -338,22 +363,22 @@ lazy_static! {
         // OpCode::new(0xea, "NOP", 1,2, AddressingMode::NoneAddressing),
         OpCode::new(0xfa, "*NOP", 1,2, AddressingMode::NoneAddressing),
 
-        OpCode::new(0xab, "*LXA", 2, 3, AddressingMode::Immediate), //todo: highly unstable and not used
+        OpCode::new(0xab, "*LXA", 2, 2, AddressingMode::Immediate), //todo: highly unstable and not used
         //http://visual6502.org/wiki/index.php?title=6502_Opcode_8B_%28XAA,_ANE%29
-        OpCode::new(0x8b, "*XAA", 2, 3, AddressingMode::Immediate), //todo: highly unstable and not used
-        OpCode::new(0xbb, "*LAS", 3, 2, AddressingMode::Absolute_Y), //todo: highly unstable and not used
-        OpCode::new(0x9b, "*TAS", 3, 2, AddressingMode::Absolute_Y), //todo: highly unstable and not used
-        OpCode::new(0x93, "*AHX", 2, /* guess */ 8, AddressingMode::Indirect_Y), //todo: highly unstable and not used
-        OpCode::new(0x9f, "*AHX", 3, /* guess */ 4/* or 5*/, AddressingMode::Absolute_Y), //todo: highly unstable and not used
-        OpCode::new(0x9e, "*SHX", 3, /* guess */ 4/* or 5*/, AddressingMode::Absolute_Y), //todo: highly unstable and not used
-        OpCode::new(0x9c, "*SHY", 3, /* guess */ 4/* or 5*/, AddressingMode::Absolute_X), //todo: highly unstable and not used
+        OpCode::new(0x8b, "*XAA", 2, 2, AddressingMode::Immediate), //todo: highly unstable and not used
+        OpCode::new(0xbb, "*LAS", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y), //todo: highly unstable and not used
+        OpCode::new(0x9b, "*TAS", 3, 5, AddressingMode::Absolute_Y), //todo: highly unstable and not used
+        OpCode::new(0x93, "*AHX", 2, 6, AddressingMode::Indirect_Y), //todo: highly unstable and not used
+        OpCode::new(0x9f, "*AHX", 3, 5, AddressingMode::Absolute_Y), //todo: highly unstable and not used
+        OpCode::new(0x9e, "*SHX", 3, 5, AddressingMode::Absolute_Y), //todo: highly unstable and not used
+        OpCode::new(0x9c, "*SHY", 3, 5, AddressingMode::Absolute_X), //todo: highly unstable and not used
 
         OpCode::new(0xa7, "*LAX", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0xb7, "*LAX", 2, 4, AddressingMode::ZeroPage_Y),
         OpCode::new(0xaf, "*LAX", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xbf, "*LAX", 3, 4, AddressingMode::Absolute_Y),
+        OpCode::new(0xbf, "*LAX", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
         OpCode::new(0xa3, "*LAX", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0xb3, "*LAX", 2, 5, AddressingMode::Indirect_Y),
+        OpCode::new(0xb3, "*LAX", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
 
         OpCode::new(0x87, "*SAX", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0x97, "*SAX", 2, 4, AddressingMode::ZeroPage_Y),
@@ -371,4 +396,28 @@ lazy_static! {
         }
         map
     };
+
+    /// OpCodeをコードで引く表. 命令の実行毎に引くため、HashMapではなく配列にしている.
+    /// 定義されていないコードはNone.
+    pub static ref OPCODES_TABLE: [Option<&'static OpCode>; 256] = {
+        let mut table = [None; 256];
+        for cpuop in &*CPU_OPS_CODES {
+            table[cpuop.code as usize] = Some(cpuop);
+        }
+        table
+    };
+}
+
+#[cfg(test)]
+mod opcodes_test {
+    use super::*;
+
+    #[test]
+    fn table_matches_map() {
+        for code in 0..=255u8 {
+            let from_table = OPCODES_TABLE[code as usize].map(|op| op as *const OpCode);
+            let from_map = OPCODES_MAP.get(&code).map(|op| *op as *const OpCode);
+            assert_eq!(from_table, from_map, "opcode {:02x}", code);
+        }
+    }
 }
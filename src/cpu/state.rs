@@ -0,0 +1,271 @@
+use crate::apu::apu::Apu;
+use crate::cpu::cpu::CpuFlags;
+use crate::error::NesError;
+use crate::ppu::ppu::Ppu;
+
+/// CpuRegisters Struct
+/// CPUのレジスタの値
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CpuRegisters {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub status: CpuFlags,
+    pub pc: u16,
+}
+
+impl Default for CpuRegisters {
+    fn default() -> Self {
+        CpuRegisters {
+            a: 0,
+            x: 0,
+            y: 0,
+            sp: 0,
+            status: CpuFlags::empty(),
+            pc: 0,
+        }
+    }
+}
+
+/// CpuState Struct
+/// CPUだけの状態のスナップショット(デバッガのやり直し等、投機的な実行用).
+/// RAM/PPU/APUやサイクル数は含まないため、`MachineState`より軽い.
+///
+/// | field |  detail |
+/// |---------|---------|
+/// |registers | CPUのレジスタ|
+/// |irq_disable_delayed | CLI/SEI/PLPの直後に1命令遅れて反映されるIフラグ|
+/// |nmi_pending | 次の命令の前に処理するNMI|
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CpuState {
+    pub registers: CpuRegisters,
+    pub irq_disable_delayed: bool,
+    pub nmi_pending: bool,
+}
+
+/// MachineState Struct
+/// エミュレータの状態のスナップショット(セーブステート、テスト用).
+/// コールバックや入力機器等の複製できないものを除き、Cloneできる.
+///
+/// | field |  detail |
+/// |---------|---------|
+/// |registers | CPUのレジスタ|
+/// |ram | CPUのRAM(0x0000～0x07FF)|
+/// |ppu | PPU(パターンテーブルはカートリッジと共有する場合がある)|
+/// |apu | APU|
+/// |cycles | 電源投入からのCPUサイクル数|
+/// |cartridge | `Cartridge::write_state`で書き出したマッパーのレジスタやPRG-RAM|
+#[derive(Debug, Clone)]
+pub struct MachineState {
+    pub registers: CpuRegisters,
+    pub ram: [u8; 2048],
+    pub ppu: Ppu,
+    pub apu: Apu,
+    pub cycles: usize,
+    pub cartridge: Vec<u8>,
+}
+
+impl MachineState {
+    /// 状態をバイト列に書き出す. CHR-ROMは含まない(CHR-RAMは含む).
+    ///
+    /// # Parameters
+    /// * `w` - 書き出し先
+    pub fn write(&self, w: &mut StateWriter) {
+        let registers = &self.registers;
+        w.u8(registers.a);
+        w.u8(registers.x);
+        w.u8(registers.y);
+        w.u8(registers.sp);
+        w.u8(registers.status.bits());
+        w.u16(registers.pc);
+        w.bytes(&self.ram);
+        w.u64(self.cycles as u64);
+        self.ppu.write_state(w);
+        self.apu.write_state(w);
+        w.u64(self.cartridge.len() as u64);
+        w.bytes(&self.cartridge);
+    }
+
+    /// `write`で書き出したバイト列から状態を読み込む.
+    /// CHR-ROM等、バイト列に含まれないものは現在の値を保持する.
+    /// カートリッジの状態は`Cpu::load_state`で検証する.
+    ///
+    /// # Parameters
+    /// * `r` - 読み込み元
+    pub fn read(&mut self, r: &mut StateReader) -> Result<(), NesError> {
+        self.registers = CpuRegisters {
+            a: r.u8()?,
+            x: r.u8()?,
+            y: r.u8()?,
+            sp: r.u8()?,
+            status: CpuFlags::from_bits_truncate(r.u8()?),
+            pc: r.u16()?,
+        };
+        self.ram.copy_from_slice(r.bytes(2048)?);
+        self.cycles = r.u64()? as usize;
+        self.ppu.read_state(r)?;
+        self.apu.read_state(r)?;
+        let len = r.u64()? as usize;
+        self.cartridge = r.bytes(len)?.to_vec();
+        Ok(())
+    }
+}
+
+/// StateWriter Struct
+/// セーブステートのバイト列(リトルエンディアン)を組み立てる
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter::default()
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+    }
+
+    /// 書き出したバイト列
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// StateReader Struct
+/// `StateWriter`で書き出したバイト列を先頭から読む. 足りない場合は`NesError::InvalidState`
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data }
+    }
+
+    pub fn u8(&mut self) -> Result<u8, NesError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, NesError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(NesError::InvalidState("invalid flag")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, NesError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, NesError> {
+        let mut value = [0; 8];
+        value.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(value))
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], NesError> {
+        if self.data.len() < len {
+            return Err(NesError::InvalidState("truncated"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// 読み残したバイト数
+    pub fn remaining(&self) -> usize {
+        self.data.len()
+    }
+}
+
+#[cfg(test)]
+mod state_test {
+    use crate::cpu::cpu::Cpu;
+    use crate::rom::cartridge::RamCartridge;
+    use crate::{Bus, Memory};
+
+    #[test]
+    fn cpu_snapshot_restore() {
+        let program = [
+            0xa9, 0x10, // LDA #$10
+            0xa2, 0x20, // LDX #$20
+            0xc8, //       INY
+            0x48, //       PHA
+            0x38, //       SEC
+            0x00,
+        ];
+        let mut cpu = Cpu::new(Bus::new(RamCartridge::new(&program, 0x2000), |_| {}));
+        cpu.reset();
+        cpu.step();
+        let snapshot = cpu.snapshot();
+        let copy = snapshot;
+
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert_ne!(cpu.registers(), snapshot.registers);
+
+        cpu.restore(snapshot);
+        assert_eq!(cpu.snapshot(), copy);
+        assert_eq!(cpu.registers().a, 0x10);
+        assert_eq!(cpu.registers().x, 0x00);
+        assert_eq!(cpu.pc(), 0x8002);
+        //レジスタだけを戻し、RAM(スタック)はそのまま
+        assert_eq!(cpu.mem_read(0x01fd), 0x10);
+    }
+
+    #[test]
+    fn clone_is_independent_of_original() {
+        let program = [
+            0xee, 0x00, 0x02, // INC $0200
+            0xe8, //             INX
+            0x4c, 0x00, 0x80, // JMP $8000
+        ];
+        let mut cpu = Cpu::new(Bus::new(RamCartridge::new(&program, 0x2000), |_| {}));
+        cpu.reset();
+        for _ in 0..100 {
+            cpu.step();
+        }
+        let state = cpu.save_state();
+        let copy = state.clone();
+
+        for _ in 0..100 {
+            cpu.step();
+        }
+        let advanced = cpu.save_state();
+        assert_ne!(advanced.ram[0x200], copy.ram[0x200]);
+        assert_ne!(advanced.cycles, copy.cycles);
+        assert_eq!(copy.ram[0x200], state.ram[0x200]);
+        assert_eq!(copy.registers, state.registers);
+        assert_eq!(copy.ppu.dot(), state.ppu.dot());
+
+        //複製から再開すると同じ結果になる
+        cpu.load_state(&copy).unwrap();
+        for _ in 0..100 {
+            cpu.step();
+        }
+        assert_eq!(cpu.mem_read(0x200), advanced.ram[0x200]);
+        assert_eq!(cpu.save_state().registers, advanced.registers);
+    }
+}
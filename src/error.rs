@@ -0,0 +1,43 @@
+use crate::rom::rom::RomFormat;
+use std::fmt;
+use std::io;
+
+/// エミュレータのエラー
+///
+/// | variant |  detail |
+/// |---------|---------|
+/// |Io | ファイルの読み込みやヘッダの解析に失敗した|
+/// |UnsupportedFormat | 対応していない形式のファイル(FDS, UNIF)|
+/// |InvalidSession | セッションファイルが壊れている、またはROMが一致しない|
+/// |InvalidState | セーブステートが壊れている、またはROMが一致しない|
+/// |UnsupportedMapper | 対応していないマッパー番号のROM|
+#[derive(Debug)]
+pub enum NesError {
+    Io(io::Error),
+    UnsupportedFormat(RomFormat),
+    InvalidSession(&'static str),
+    InvalidState(&'static str),
+    UnsupportedMapper(u8),
+}
+
+impl fmt::Display for NesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NesError::Io(e) => write!(f, "{}", e),
+            NesError::UnsupportedFormat(format) => {
+                write!(f, "{} images are not supported", format)
+            }
+            NesError::InvalidSession(reason) => write!(f, "invalid session: {}", reason),
+            NesError::InvalidState(reason) => write!(f, "invalid save state: {}", reason),
+            NesError::UnsupportedMapper(mapper) => write!(f, "mapper {} is not supported", mapper),
+        }
+    }
+}
+
+impl std::error::Error for NesError {}
+
+impl From<io::Error> for NesError {
+    fn from(e: io::Error) -> Self {
+        NesError::Io(e)
+    }
+}
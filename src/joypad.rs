@@ -0,0 +1,6 @@
+pub mod controller;
+pub mod four_score;
+#[allow(clippy::module_inception)]
+pub mod joypad;
+pub mod turbo;
+pub mod zapper;
@@ -0,0 +1,25 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Controller Trait
+/// コントローラポート(0x4016/0x4017)に接続する入力機器を定義する.
+/// 標準コントローラ以外の機器(ザッパー、4人用アダプタ等)もこのTraitを実装する.
+pub trait Controller {
+    /// 0x4016/0x4017 の読み出し
+    fn read(&mut self) -> u8;
+
+    /// 0x4016 への書き込み. bit0がストローブ.
+    fn write(&mut self, data: u8);
+}
+
+/// フロントエンドとBusで入力機器を共有するための実装.
+/// フロントエンドはハンドルを保持してボタンの状態を更新する.
+impl<C: Controller> Controller for Rc<RefCell<C>> {
+    fn read(&mut self) -> u8 {
+        self.borrow_mut().read()
+    }
+
+    fn write(&mut self, data: u8) {
+        self.borrow_mut().write(data)
+    }
+}
@@ -0,0 +1,160 @@
+use super::controller::Controller;
+use super::joypad::JoypadButton;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// 0x4016 で読み出すシグネチャ(bit16から)
+const SIGNATURE_PORT1: u8 = 0b0000_1000;
+/// 0x4017 で読み出すシグネチャ(bit16から)
+const SIGNATURE_PORT2: u8 = 0b0000_0100;
+
+/// FourScore Struct
+/// 4人用アダプタ. ストローブが0になった後、読み出す毎に1bitずつ以下の順で返す.
+/// 24回を超えて読み出すと1を返す.
+///
+/// | read |  0x4016 | 0x4017 |
+/// |---------|---------|---------|
+/// |1～8 | プレイヤー1 | プレイヤー2|
+/// |9～16 | プレイヤー3 | プレイヤー4|
+/// |17～24 | シグネチャ 0,0,0,1,0,0,0,0 | シグネチャ 0,0,1,0,0,0,0,0|
+///
+/// 2つのポートにまたがるため、`ports`で取得したポートをそれぞれBusに接続する.
+///
+/// # Parameters
+/// * `strobe` - ストローブ(1の間は常にプレイヤー1,2のAボタンの状態を返す)
+/// * `read_index` - ポート毎の次に読み出すビット
+/// * `buttons` - プレイヤー毎のボタンの押下状態
+pub struct FourScore {
+    strobe: bool,
+    read_index: [u8; 2],
+    buttons: [JoypadButton; 4],
+}
+
+impl FourScore {
+    ///FourScoreコンストラクタ
+    pub fn new() -> Self {
+        FourScore {
+            strobe: false,
+            read_index: [0; 2],
+            buttons: [JoypadButton::empty(); 4],
+        }
+    }
+
+    /// ボタンの押下状態を設定する.
+    ///
+    /// # Parameters
+    /// * `player` - プレイヤー(0～3)
+    /// * `button` - ボタン
+    /// * `pressed` - 押されているかどうか
+    pub fn set_button_pressed(&mut self, player: usize, button: JoypadButton, pressed: bool) {
+        self.buttons[player].set(button, pressed);
+    }
+
+    /// 0x4016(ポート1)と0x4017(ポート2)に接続するポート
+    ///
+    /// # Parameters
+    /// * `four_score` - フロントエンドと共有するFourScore
+    pub fn ports(four_score: &Rc<RefCell<FourScore>>) -> [FourScorePort; 2] {
+        [0, 1].map(|port| FourScorePort {
+            four_score: four_score.clone(),
+            port,
+        })
+    }
+
+    fn read(&mut self, port: usize) -> u8 {
+        let index = if self.strobe {
+            0
+        } else {
+            self.read_index[port]
+        };
+        let data = match index {
+            0..=7 => self.buttons[port].bits() >> index,
+            8..=15 => self.buttons[port + 2].bits() >> (index - 8),
+            16..=23 => [SIGNATURE_PORT1, SIGNATURE_PORT2][port] >> (index - 16),
+            _ => 1,
+        };
+        if !self.strobe && index < 24 {
+            self.read_index[port] += 1;
+        }
+        data & 1
+    }
+
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.read_index = [0; 2];
+        }
+    }
+}
+
+impl Default for FourScore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// FourScorePort Struct
+/// FourScoreの片側のポート(0: 0x4016, 1: 0x4017)
+pub struct FourScorePort {
+    four_score: Rc<RefCell<FourScore>>,
+    port: usize,
+}
+
+impl Controller for FourScorePort {
+    fn read(&mut self) -> u8 {
+        self.four_score.borrow_mut().read(self.port)
+    }
+
+    fn write(&mut self, data: u8) {
+        self.four_score.borrow_mut().write(data)
+    }
+}
+
+#[cfg(test)]
+mod four_score_test {
+    use super::*;
+
+    fn bits(byte: u8) -> Vec<u8> {
+        (0..8).map(|i| (byte >> i) & 1).collect()
+    }
+
+    #[test]
+    fn read_full_sequence() {
+        let four_score = Rc::new(RefCell::new(FourScore::new()));
+        {
+            let mut four_score = four_score.borrow_mut();
+            four_score.set_button_pressed(0, JoypadButton::BUTTON_A, true);
+            four_score.set_button_pressed(1, JoypadButton::BUTTON_B, true);
+            four_score.set_button_pressed(2, JoypadButton::START, true);
+            four_score.set_button_pressed(3, JoypadButton::RIGHT, true);
+        }
+        let [mut port1, mut port2] = FourScore::ports(&four_score);
+        //0x4016への書き込みは両方のポートに届く
+        for port in [&mut port1, &mut port2] {
+            port.write(1);
+            port.write(0);
+        }
+
+        let read1: Vec<u8> = (0..24).map(|_| port1.read()).collect();
+        let read2: Vec<u8> = (0..24).map(|_| port2.read()).collect();
+        let expected1: Vec<u8> = [0b0000_0001, 0b0000_1000, 0b0000_1000]
+            .iter()
+            .flat_map(|&byte| bits(byte))
+            .collect();
+        let expected2: Vec<u8> = [0b0000_0010, 0b1000_0000, 0b0000_0100]
+            .iter()
+            .flat_map(|&byte| bits(byte))
+            .collect();
+        assert_eq!(read1, expected1);
+        assert_eq!(read2, expected2);
+        //シグネチャの後は1
+        assert_eq!(port1.read(), 1);
+        assert_eq!(port2.read(), 1);
+
+        //ストローブで最初から読み直せる
+        port1.write(1);
+        port1.write(0);
+        assert_eq!(port1.read(), 1);
+        assert_eq!(port2.read(), 0);
+    }
+}
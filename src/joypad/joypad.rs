@@ -0,0 +1,126 @@
+use super::controller::Controller;
+
+bitflags! {
+    /// # 標準コントローラのボタン
+    /// 0x4016/0x4017 から読み出される順番(bit0から)
+    ///
+    ///  7 6 5 4 3 2 1 0
+    ///  R L D U S S B A
+    ///  | | | | | | | +-- A
+    ///  | | | | | | +---- B
+    ///  | | | | | +------ Select
+    ///  | | | | +-------- Start
+    ///  | | | +---------- Up
+    ///  | | +------------ Down
+    ///  | +-------------- Left
+    ///  +---------------- Right
+    ///
+    pub struct JoypadButton: u8 {
+        const RIGHT             = 0b10000000;
+        const LEFT              = 0b01000000;
+        const DOWN              = 0b00100000;
+        const UP                = 0b00010000;
+        const START             = 0b00001000;
+        const SELECT            = 0b00000100;
+        const BUTTON_B          = 0b00000010;
+        const BUTTON_A          = 0b00000001;
+    }
+}
+
+/// Joypad Struct
+/// 標準コントローラ.
+/// ストローブが0になった後、読み出す毎にA,B,Select,Start,Up,Down,Left,Rightの順で
+/// ボタンの状態を1bitずつ返す. 8回を超えて読み出すと1を返す.
+///
+/// # Parameters
+/// * `strobe` - ストローブ(1の間は常にAボタンの状態を返す)
+/// * `button_index` - 次に読み出すボタン
+/// * `button_status` - ボタンの押下状態
+pub struct Joypad {
+    strobe: bool,
+    button_index: u8,
+    button_status: JoypadButton,
+}
+
+impl Joypad {
+    ///Joypadコンストラクタ
+    pub fn new() -> Self {
+        Joypad {
+            strobe: false,
+            button_index: 0,
+            button_status: JoypadButton::from_bits_truncate(0),
+        }
+    }
+
+    /// ボタンの押下状態を設定する.
+    ///
+    /// # Parameters
+    /// * `button` - ボタン
+    /// * `pressed` - 押されているかどうか
+    pub fn set_button_pressed_status(&mut self, button: JoypadButton, pressed: bool) {
+        self.button_status.set(button, pressed);
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Controller for Joypad {
+    fn read(&mut self) -> u8 {
+        if self.button_index > 7 {
+            return 1;
+        }
+        let response = (self.button_status.bits() & (1 << self.button_index)) >> self.button_index;
+        if !self.strobe {
+            self.button_index += 1;
+        }
+        response
+    }
+
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.button_index = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod joypad_test {
+    use super::*;
+
+    #[test]
+    fn read_buttons_then_ones() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_A, true);
+        joypad.set_button_pressed_status(JoypadButton::START, true);
+        joypad.set_button_pressed_status(JoypadButton::RIGHT, true);
+        joypad.write(1);
+        joypad.write(0);
+
+        let bits: Vec<u8> = (0..8).map(|_| joypad.read()).collect();
+        assert_eq!(bits, vec![1, 0, 0, 1, 0, 0, 0, 1]);
+        for _ in 0..4 {
+            assert_eq!(joypad.read(), 1);
+        }
+
+        //ストローブで最初から読み直せる
+        joypad.write(1);
+        joypad.write(0);
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.read(), 0);
+    }
+
+    #[test]
+    fn strobe_high_returns_button_a() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_A, true);
+        joypad.write(1);
+        for _ in 0..10 {
+            assert_eq!(joypad.read(), 1);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use super::joypad::{Joypad, JoypadButton};
+
+/// 連射速度のデフォルト(1秒あたりの押下回数)
+pub const DEFAULT_TURBO_RATE: u32 = 15;
+/// 1秒あたりのフレーム数(NTSC)
+const FRAMES_PER_SECOND: u32 = 60;
+
+/// Turbo Struct
+/// A/Bボタンの連射. 連射ボタンを押している間、Joypadのボタンを一定のフレーム毎に押す/離す.
+/// フロントエンドは1フレーム毎に`next_frame`を呼ぶ.
+///
+/// 通常のボタンと同じボタンの連射を同時に押している間は連射が優先される.
+///
+/// # Parameters
+/// * `held` - 押されている連射ボタン
+/// * `applied` - 前のフレームでJoypadに反映した連射ボタン
+/// * `half_period` - 押す/離す状態を保つフレーム数
+/// * `frame` - 連射ボタンを押してからのフレーム数
+pub struct Turbo {
+    held: JoypadButton,
+    applied: JoypadButton,
+    half_period: u32,
+    frame: u32,
+}
+
+impl Turbo {
+    ///Turboコンストラクタ. 連射速度は`DEFAULT_TURBO_RATE`
+    pub fn new() -> Self {
+        let mut turbo = Turbo {
+            held: JoypadButton::empty(),
+            applied: JoypadButton::empty(),
+            half_period: 1,
+            frame: 0,
+        };
+        turbo.set_rate(DEFAULT_TURBO_RATE);
+        turbo
+    }
+
+    /// 連射速度を設定する.
+    /// 押す/離すの切り替えはフレーム単位のため、実際の速度は 30/n 回/秒
+    /// (30, 15, 10, 7.5, 6, ...)のうち最も近いものになる. 30を超える値は30として扱う.
+    ///
+    /// # Parameters
+    /// * `rate` - 1秒あたりの押下回数
+    pub fn set_rate(&mut self, rate: u32) {
+        let rate = rate.clamp(1, FRAMES_PER_SECOND / 2);
+        //押す/離すのフレーム数 = 30 / rate を四捨五入
+        self.half_period = (FRAMES_PER_SECOND + rate) / (rate * 2);
+    }
+
+    /// 連射ボタンの押下状態を設定する. A/B以外のボタンは無視する.
+    ///
+    /// # Parameters
+    /// * `button` - 連射するボタン(BUTTON_A/BUTTON_B)
+    /// * `enabled` - 連射ボタンが押されているかどうか
+    pub fn set_turbo(&mut self, button: JoypadButton, enabled: bool) {
+        let button = button & (JoypadButton::BUTTON_A | JoypadButton::BUTTON_B);
+        if self.held.is_empty() && enabled {
+            //押した直後のフレームから押下状態にする
+            self.frame = 0;
+        }
+        self.held.set(button, enabled);
+    }
+
+    /// 1フレーム進め、連射ボタンの状態をJoypadに反映する.
+    /// 離した連射ボタンはJoypadでも離した状態にする.
+    ///
+    /// # Parameters
+    /// * `joypad` - 連射ボタンの状態を反映するJoypad
+    pub fn next_frame(&mut self, joypad: &mut Joypad) {
+        joypad.set_button_pressed_status(self.applied - self.held, false);
+        self.applied = self.held;
+
+        let pressed = (self.frame / self.half_period) & 1 == 0;
+        joypad.set_button_pressed_status(self.held, pressed);
+        self.frame = self.frame.wrapping_add(1);
+    }
+}
+
+impl Default for Turbo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod turbo_test {
+    use super::*;
+    use crate::joypad::controller::Controller;
+
+    /// Joypadから読み出したAボタンの状態
+    fn button_a(joypad: &mut Joypad) -> u8 {
+        joypad.write(1);
+        joypad.write(0);
+        joypad.read()
+    }
+
+    #[test]
+    fn turbo_a_alternates_at_rate() {
+        let mut joypad = Joypad::new();
+        let mut turbo = Turbo::new();
+        turbo.set_turbo(JoypadButton::BUTTON_A, true);
+        let frames: Vec<u8> = (0..10)
+            .map(|_| {
+                turbo.next_frame(&mut joypad);
+                button_a(&mut joypad)
+            })
+            .collect();
+        //15回/秒 = 2フレーム毎に押す/離す
+        assert_eq!(frames, vec![1, 1, 0, 0, 1, 1, 0, 0, 1, 1]);
+
+        turbo.set_rate(30);
+        let frames: Vec<u8> = (0..4)
+            .map(|_| {
+                turbo.next_frame(&mut joypad);
+                button_a(&mut joypad)
+            })
+            .collect();
+        assert_eq!(frames, vec![1, 0, 1, 0]);
+
+        //16回/秒は15回/秒(2フレーム毎)に丸める
+        turbo.set_rate(16);
+        assert_eq!(turbo.half_period, 2);
+        turbo.set_rate(21);
+        assert_eq!(turbo.half_period, 1);
+        turbo.set_rate(8);
+        assert_eq!(turbo.half_period, 4);
+        turbo.set_rate(u32::MAX);
+        assert_eq!(turbo.half_period, 1);
+        turbo.set_rate(30);
+
+        //離すと押していない状態に戻る
+        turbo.set_turbo(JoypadButton::BUTTON_A, false);
+        for _ in 0..4 {
+            turbo.next_frame(&mut joypad);
+            assert_eq!(button_a(&mut joypad), 0);
+        }
+    }
+}
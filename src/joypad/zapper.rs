@@ -0,0 +1,132 @@
+use super::controller::Controller;
+use crate::render::frame::Frame;
+
+/// 光を検出したとみなす明るさ(輝度)
+const BRIGHTNESS_THRESHOLD: u32 = 0xc0;
+
+/// Zapper Struct
+/// 光線銃. 読み出すとbit3に光センサ、bit4にトリガーの状態を返す.
+///
+/// | bit |  detail |
+/// |---------|---------|
+/// |3 | 0: 光を検出, 1: 検出しない|
+/// |4 | 0: トリガーを離している, 1: 引いている|
+///
+/// 光センサは`update`で渡されたフレームの照準位置の明るさから決まる.
+/// フロントエンドはフレームの描画毎に`update`を呼ぶ.
+///
+/// # Parameters
+/// * `x` - 照準のX座標
+/// * `y` - 照準のY座標
+/// * `trigger` - トリガーを引いているか
+/// * `light` - 照準位置が明るいか
+pub struct Zapper {
+    x: usize,
+    y: usize,
+    trigger: bool,
+    light: bool,
+}
+
+impl Zapper {
+    ///Zapperコンストラクタ
+    pub fn new() -> Self {
+        Zapper {
+            x: 0,
+            y: 0,
+            trigger: false,
+            light: false,
+        }
+    }
+
+    /// 照準を合わせる.
+    ///
+    /// # Parameters
+    /// * `x` - 画面上のX座標(0～255)
+    /// * `y` - 画面上のY座標(0～239)
+    pub fn aim(&mut self, x: usize, y: usize) {
+        self.x = x;
+        self.y = y;
+    }
+
+    /// トリガーの状態を設定する.
+    ///
+    /// # Parameters
+    /// * `pulled` - 引いているかどうか
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    /// 描画されたフレームから照準位置の光を検出する.
+    ///
+    /// # Parameters
+    /// * `frame` - 描画されたフレーム
+    pub fn update(&mut self, frame: &Frame) {
+        self.light = false;
+        if self.x >= Frame::WIDTH || self.y >= Frame::HIGHT {
+            return;
+        }
+        let base = (self.y * Frame::WIDTH + self.x) * 3;
+        let (r, g, b) = (
+            frame.data[base] as u32,
+            frame.data[base + 1] as u32,
+            frame.data[base + 2] as u32,
+        );
+        //ITU-R BT.601の輝度
+        let brightness = (299 * r + 587 * g + 114 * b) / 1000;
+        self.light = brightness >= BRIGHTNESS_THRESHOLD;
+    }
+}
+
+impl Default for Zapper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Controller for Zapper {
+    fn read(&mut self) -> u8 {
+        let mut data = 0;
+        if !self.light {
+            data |= 0b0000_1000;
+        }
+        if self.trigger {
+            data |= 0b0001_0000;
+        }
+        data
+    }
+
+    //ストローブは使用しない
+    fn write(&mut self, _data: u8) {}
+}
+
+#[cfg(test)]
+mod zapper_test {
+    use super::*;
+
+    #[test]
+    fn light_sense_on_bright_target() {
+        let mut frame = Frame::new();
+        for y in 100..116 {
+            for x in 120..136 {
+                frame.set_pixel(x, y, (0xff, 0xff, 0xff));
+            }
+        }
+        let mut zapper = Zapper::new();
+
+        zapper.aim(128, 108);
+        zapper.update(&frame);
+        assert_eq!(zapper.read() & 0b1000, 0);
+
+        zapper.aim(10, 10);
+        zapper.update(&frame);
+        assert_eq!(zapper.read() & 0b1000, 0b1000);
+    }
+
+    #[test]
+    fn trigger() {
+        let mut zapper = Zapper::new();
+        assert_eq!(zapper.read() & 0b1_0000, 0);
+        zapper.set_trigger(true);
+        assert_eq!(zapper.read() & 0b1_0000, 0b1_0000);
+    }
+}
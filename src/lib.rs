@@ -0,0 +1,17 @@
+#[macro_use]
+extern crate arrayref;
+#[macro_use]
+extern crate bitflags;
+
+pub mod apu;
+pub mod cpu;
+pub mod error;
+pub mod joypad;
+pub mod mapper;
+pub mod nes;
+pub mod ppu;
+pub mod render;
+pub mod rom;
+
+pub use cpu::bus::Bus;
+pub use cpu::cpu::{CpuFlags, Memory};
@@ -1,23 +1,61 @@
-#[macro_use]
-extern crate arrayref;
-#[macro_use]
-extern crate bitflags;
-
-mod cpu;
-mod nes;
-mod ppu;
-mod render;
-mod rom;
-
-use cpu::bus::Bus;
-use cpu::cpu::Memory;
 use sdl2::pixels::PixelFormatEnum;
 use std::env;
 
-use render::frame::Frame;
-use rom::rom::Rom;
+use nes_rs::joypad::turbo::DEFAULT_TURBO_RATE;
+use nes_rs::mapper;
+use nes_rs::nes::audio::{Audio, DEFAULT_AUDIO_BUFFER};
+use nes_rs::nes::key_config::KeyConfig;
+use nes_rs::nes::pacing::Pacer;
+use nes_rs::nes::{self, Nes, RunOptions};
+use nes_rs::render;
+use nes_rs::render::frame::Frame;
+use nes_rs::rom::header::TvSystem;
+use nes_rs::rom::rom::Rom;
 
 fn main() {
+    //ROM読み出し
+    let args: Vec<String> = env::args().collect();
+    let nes_file = &args[1];
+    let rom = Rom::load(nes_file).unwrap_or_else(|e| {
+        eprintln!("couldn't load {}: {}", nes_file, e);
+        std::process::exit(1);
+    });
+
+    //PALのタイミングは未対応のため、NTSCとして実行する
+    if rom.header.tv_system == TvSystem::Pal {
+        eprintln!("warning: PAL ROM is running with NTSC timing");
+    }
+    let cartridge = mapper::create(rom).unwrap_or_else(|e| {
+        eprintln!("couldn't load {}: {}", nes_file, e);
+        std::process::exit(1);
+    });
+
+    //フィルタ
+    let filter = option_value(&args, "--filter").map(|name| {
+        render::filter::from_name(name).unwrap_or_else(|| panic!("unknown filter: {}", name))
+    });
+
+    //スプライトの1スキャンライン8個の制限を無効にする(ちらつき防止)
+    let disable_sprite_limit = args.iter().any(|arg| arg == "--no-sprite-limit");
+
+    //サムネイル出力(SDLを使わずに指定フレーム数実行してPNGに保存)
+    if let Some(path) = option_value(&args, "--thumbnail") {
+        let frames = option_value(&args, "--frames")
+            .map(|frames| frames.parse().expect("--frames must be a number"))
+            .unwrap_or(60);
+        //内部解像度の倍率(スーパーサンプリング)
+        let supersample = option_value(&args, "--supersample")
+            .map(|factor| factor.parse().expect("--supersample must be a number"))
+            .unwrap_or(1);
+        let mut nes = Nes::new(cartridge);
+        nes.cpu.bus.ppu_mut().disable_sprite_limit = disable_sprite_limit;
+        nes.run_frames(frames);
+        nes.screenshot(supersample, filter.as_deref())
+            .save_png(path)
+            .unwrap();
+        return;
+    }
+
     //SDL初期化
     let sdl_context = sdl2::init().unwrap();
     // Videoサブシステム取得
@@ -44,11 +82,78 @@ fn main() {
     //Frame作成
     let frame = Frame::new();
 
-    //ROM読み出し
-    let args: Vec<String> = env::args().collect();
-    let nes_file = &args[1];
-    let rom = Rom::load(nes_file).unwrap();
+    //ポート2に光線銃(マウスで操作)を接続する
+    let zapper = args.iter().any(|arg| arg == "--zapper");
+
+    //ホストが遅れた場合に1回の描画までに進める最大フレーム数
+    let max_catch_up = option_value(&args, "--max-catch-up")
+        .map(|frames| frames.parse().expect("--max-catch-up must be a number"))
+        .unwrap_or(Pacer::DEFAULT_MAX_CATCH_UP);
+
+    //ウィンドウがフォーカスを失っている間は一時停止する
+    let pause_on_blur = !args.iter().any(|arg| arg == "--no-pause-on-blur");
+
+    //ROMファイルが更新されたら読み込み直す
+    let watch = args
+        .iter()
+        .any(|arg| arg == "--watch")
+        .then(|| nes_file.clone());
+
+    //キー割り当て. 読み込めない場合はデフォルトを使う
+    let keys = match option_value(&args, "--config") {
+        Some(path) => match KeyConfig::load(path) {
+            Ok((keys, warnings)) => {
+                for warning in warnings {
+                    eprintln!("warning: {}: {}", path, warning);
+                }
+                keys
+            }
+            Err(e) => {
+                eprintln!("warning: couldn't load {}: {}", path, e);
+                KeyConfig::default()
+            }
+        },
+        None => KeyConfig::default(),
+    };
+
+    //連射ボタンの1秒あたりの押下回数
+    let turbo_rate = option_value(&args, "--turbo-rate")
+        .map(|rate| rate.parse().expect("--turbo-rate must be a number"))
+        .unwrap_or(DEFAULT_TURBO_RATE);
+
+    //音声. 開けない場合は音声なしで実行する
+    let audio_buffer = option_value(&args, "--audio-buffer")
+        .map(|samples| samples.parse().expect("--audio-buffer must be a number"))
+        .unwrap_or(DEFAULT_AUDIO_BUFFER);
+    let audio = sdl_context
+        .audio()
+        .and_then(|audio| Audio::open(&audio, audio_buffer))
+        .map_err(|e| eprintln!("warning: couldn't open audio: {}", e))
+        .ok();
 
     //NESの実行
-    nes::run(rom, canvas, event_pump, texture, frame);
+    let options = RunOptions {
+        filter,
+        zapper,
+        max_catch_up,
+        pause_on_blur,
+        watch,
+        keys,
+        disable_sprite_limit,
+        turbo_rate,
+        audio,
+    };
+    nes::run(cartridge, canvas, event_pump, texture, frame, options);
+}
+
+/// `--name value` 形式のオプションの値を取得する.
+///
+/// # Parameters
+/// * `args` - コマンドライン引数
+/// * `name` - オプション名
+fn option_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .map(|value| value.as_str())
 }
@@ -0,0 +1,64 @@
+pub mod nrom;
+pub mod uxrom;
+
+use crate::error::NesError;
+use crate::rom::cartridge::Cartridge;
+use crate::rom::rom::Rom;
+use nrom::Nrom;
+use uxrom::Uxrom;
+
+/// 対応しているマッパー番号(`create`で作れるもの)
+///
+/// | mapper |  detail |
+/// |---------|---------|
+/// |0 | NROM|
+/// |2 | UxROM|
+const SUPPORTED_MAPPERS: [u8; 2] = [0, 2];
+
+/// 対応しているマッパー番号の一覧
+pub fn supported_mappers() -> &'static [u8] {
+    &SUPPORTED_MAPPERS
+}
+
+/// マッパー番号に対応しているかどうか. ROMを読み込む前の確認に使う.
+///
+/// # Parameters
+/// * `mapper` - マッパー番号
+pub fn is_mapper_supported(mapper: u8) -> bool {
+    SUPPORTED_MAPPERS.contains(&mapper)
+}
+
+/// ROMのマッパー番号に対応するカートリッジを作る.
+///
+/// # Parameters
+/// * `rom` - Rom
+pub fn create(rom: Rom) -> Result<Box<dyn Cartridge>, NesError> {
+    match rom.mapper {
+        0 => Ok(Box::new(Nrom::new(rom))),
+        2 => Ok(Box::new(Uxrom::new(rom))),
+        mapper => Err(NesError::UnsupportedMapper(mapper)),
+    }
+}
+
+#[cfg(test)]
+mod mapper_test {
+    use super::*;
+    use crate::rom::rom::Mirroring;
+
+    fn rom(mapper: u8) -> Rom {
+        Rom::test(vec![0; 0x8000]).with_mapper(mapper, Mirroring::HORIZONTAL)
+    }
+
+    #[test]
+    fn supported_mappers_match_factory() {
+        assert!(is_mapper_supported(0));
+        assert!(!is_mapper_supported(4));
+        for &mapper in supported_mappers() {
+            assert!(create(rom(mapper)).is_ok());
+        }
+        assert!(matches!(
+            create(rom(4)),
+            Err(NesError::UnsupportedMapper(4))
+        ));
+    }
+}
@@ -0,0 +1,240 @@
+use crate::cpu::state::{StateReader, StateWriter};
+use crate::error::NesError;
+use crate::rom::cartridge::{Cartridge, ChrMemory, ChrRam, CHR_RAM_SIZE};
+use crate::rom::rom::{Mirroring, Rom};
+
+/// PRG-RAMのサイズ
+const PRG_RAM_SIZE: usize = 0x2000;
+
+/// NROM (mapper 0)
+///
+/// | address |  purpose |
+/// |---------|---------|
+/// |0x8000～0xBFFF| PRG-ROMの先頭16KiB|
+/// |0xC000～0xFFFF| PRG-ROMの末尾16KiB (16KiBのROMでは0x8000～0xBFFFのミラー)|
+///
+/// PRG-ROMのサイズが32KiBに満たない場合は、0x8000からのオフセットをサイズで割った余りで読み出す.
+/// 16KiB以外の半端なサイズ(例えば24KiB)では、末尾を越えた領域はPRG-ROMの先頭から繰り返す.
+///
+/// ROMへの書き込みは無視する. デバッグ用のコールバックを設定すると書き込みを通知する.
+/// ヘッダでPRG-RAMがないと指定されていない限り、0x6000～0x7FFFに8KiBのPRG-RAMを持つ.
+/// キャラクターROMがない場合は8KiBのCHR-RAMを持つ.
+pub struct Nrom {
+    program_data: Vec<u8>,
+    prg_ram: Vec<u8>,
+    char_data: Vec<u8>,
+    chr_ram: bool,
+    screen_mirroring: Mirroring,
+    rom_write_callback: Option<Box<dyn FnMut(u16, u8)>>,
+    ignored_writes: u64,
+}
+
+impl Nrom {
+    ///Nromコンストラクタ
+    ///
+    /// # Parameters
+    /// * `rom` - Rom
+    pub fn new(rom: Rom) -> Self {
+        Nrom {
+            prg_ram: vec![
+                0;
+                if rom.header.has_prg_ram {
+                    PRG_RAM_SIZE
+                } else {
+                    0
+                }
+            ],
+            program_data: rom.program_data,
+            chr_ram: rom.char_data.is_empty(),
+            char_data: if rom.char_data.is_empty() {
+                vec![0; CHR_RAM_SIZE]
+            } else {
+                rom.char_data
+            },
+            screen_mirroring: rom.screen_mirroring,
+            rom_write_callback: None,
+            ignored_writes: 0,
+        }
+    }
+
+    /// ROM領域(0x8000～0xFFFF)への書き込みを通知するコールバックを設定する.
+    /// バグのあるゲームの調査用.
+    ///
+    /// # Parameters
+    /// * `callback` - 書き込み先のアドレスと値を引数にとるクロージャ
+    pub fn set_rom_write_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(u16, u8) + 'static,
+    {
+        self.rom_write_callback = Some(Box::new(callback));
+    }
+}
+
+impl Cartridge for Nrom {
+    fn read_prg(&self, addr: u16) -> u8 {
+        if self.program_data.is_empty() {
+            return 0;
+        }
+        let offset = (addr - 0x8000) as usize % self.program_data.len();
+        self.program_data[offset]
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        self.ignored_writes += 1;
+        if let Some(callback) = &mut self.rom_write_callback {
+            callback(addr, data);
+        }
+    }
+
+    fn read_prg_ram(&self, addr: u16) -> u8 {
+        self.prg_ram
+            .get((addr - 0x6000) as usize)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        if let Some(byte) = self.prg_ram.get_mut((addr - 0x6000) as usize) {
+            *byte = data;
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_rom(&self) -> Vec<u8> {
+        self.program_data.clone()
+    }
+
+    fn char_data(&self) -> Vec<u8> {
+        self.char_data.clone()
+    }
+
+    fn chr_memory(&self) -> Box<dyn ChrMemory> {
+        if self.chr_ram {
+            Box::new(ChrRam::new(self.char_data.clone()))
+        } else {
+            Box::new(self.char_data.clone())
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.screen_mirroring.clone()
+    }
+
+    fn ignored_writes(&self) -> u64 {
+        self.ignored_writes
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.prg_ram);
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), NesError> {
+        let prg_ram = r.bytes(self.prg_ram.len())?;
+        self.prg_ram.copy_from_slice(prg_ram);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod nrom_test {
+    use super::*;
+    use crate::cpu::bus::Bus;
+    use crate::ppu::ppu::{Ppu, TPpu};
+    use crate::Memory;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn chr_ram_without_char_rom() {
+        let mut ppu = Ppu::from_cartridge(&Nrom::new(Rom::test(vec![0; 0x4000])));
+        ppu.write_to_ppu_addr(0x00);
+        ppu.write_to_ppu_addr(0x10);
+        ppu.write_to_data(0x42);
+        assert_eq!(ppu.read_chr(0x0010), 0x00);
+
+        let no_chr = Rom::test(vec![0; 0x4000]).with_char_data(Vec::new());
+        let mut ppu = Ppu::from_cartridge(&Nrom::new(no_chr));
+        ppu.write_to_ppu_addr(0x00);
+        ppu.write_to_ppu_addr(0x10);
+        ppu.write_to_data(0x42);
+        assert_eq!(ppu.read_chr(0x0010), 0x42);
+    }
+
+    #[test]
+    fn prg_ram_follows_header() {
+        let mut nrom = Nrom::new(Rom::test(vec![0; 0x4000]));
+        nrom.write_prg_ram(0x6000, 0x42);
+        nrom.write_prg_ram(0x7fff, 0x43);
+        assert_eq!(nrom.read_prg_ram(0x6000), 0x42);
+        assert_eq!(nrom.read_prg_ram(0x7fff), 0x43);
+
+        let mut no_ram = Rom::test(vec![0; 0x4000]);
+        no_ram.header.has_prg_ram = false;
+        let mut nrom = Nrom::new(no_ram);
+        nrom.write_prg_ram(0x6000, 0x42);
+        assert_eq!(nrom.read_prg_ram(0x6000), 0);
+    }
+
+    #[test]
+    fn prg_16kib_is_mirrored() {
+        let mut program_data = vec![0; 0x4000];
+        program_data[0x0000] = 0x11;
+        program_data[0x3fff] = 0x22;
+        let nrom = Nrom::new(Rom::test(program_data));
+
+        assert_eq!(nrom.read_prg(0x8000), 0x11);
+        assert_eq!(nrom.read_prg(0xc000), 0x11);
+        assert_eq!(nrom.read_prg(0xbfff), 0x22);
+        assert_eq!(nrom.read_prg(0xffff), 0x22);
+    }
+
+    #[test]
+    fn prg_32kib_has_distinct_banks() {
+        let mut program_data = vec![0; 0x8000];
+        program_data[0x0000] = 0x11;
+        program_data[0x4000] = 0x33;
+        let nrom = Nrom::new(Rom::test(program_data));
+
+        assert_eq!(nrom.read_prg(0x8000), 0x11);
+        assert_eq!(nrom.read_prg(0xc000), 0x33);
+    }
+
+    #[test]
+    fn prg_24kib_wraps() {
+        let program_data: Vec<u8> = (0..0x6000).map(|i| (i / 0x1000) as u8).collect();
+        let nrom = Nrom::new(Rom::test(program_data));
+
+        for addr in 0x8000..=0xffff {
+            nrom.read_prg(addr);
+        }
+        assert_eq!(nrom.read_prg(0x8000), 0);
+        assert_eq!(nrom.read_prg(0xdfff), 5);
+        //0xE000以降は先頭から繰り返す
+        assert_eq!(nrom.read_prg(0xe000), 0);
+        assert_eq!(nrom.read_prg(0xffff), 1);
+    }
+
+    #[test]
+    fn rom_write_is_ignored() {
+        let mut nrom = Nrom::new(Rom::test(vec![0x11; 0x4000]));
+        nrom.write_prg(0x8000, 0x22);
+        assert_eq!(nrom.read_prg(0x8000), 0x11);
+    }
+
+    #[test]
+    fn rom_write_callback() {
+        let writes = Rc::new(RefCell::new(vec![]));
+        let sink = writes.clone();
+        let mut nrom = Nrom::new(Rom::test(vec![0x11; 0x4000]));
+        nrom.set_rom_write_callback(move |addr, data| sink.borrow_mut().push((addr, data)));
+
+        let mut bus = Bus::new(nrom, |_| {});
+        bus.mem_write(0x8000, 0x22);
+
+        assert_eq!(*writes.borrow(), vec![(0x8000, 0x22)]);
+        assert_eq!(bus.mem_read(0x8000), 0x11);
+    }
+}
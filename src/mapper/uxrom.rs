@@ -0,0 +1,190 @@
+use crate::cpu::state::{StateReader, StateWriter};
+use crate::error::NesError;
+use crate::rom::cartridge::{Cartridge, ChrMemory, ChrRam, CHR_RAM_SIZE};
+use crate::rom::rom::{Mirroring, Rom};
+
+/// PRGバンクのサイズ
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/// UxROM (mapper 2)
+///
+/// | address |  purpose |
+/// |---------|---------|
+/// |0x8000～0xBFFF| 切り替え可能な16KiBのPRGバンク(電源投入時はバンク0)|
+/// |0xC000～0xFFFF| 最後の16KiBのPRGバンクに固定|
+///
+/// 0x8000～0xFFFFへの書き込みで0x8000～0xBFFFのバンクを選択する.
+/// リセットベクタは固定バンクにあるため、電源投入時のバンクに関わらず起動できる.
+/// (バスコンフリクトは再現しない)
+///
+/// PRG-ROMが16KiBの倍数でない場合は、半端なバンクも1バンクとして数え、
+/// 末尾を越えた領域はNROMと同様にPRG-ROMの先頭から繰り返す.
+///
+/// キャラクターROMがない場合は8KiBのCHR-RAMを持つ.
+pub struct Uxrom {
+    program_data: Vec<u8>,
+    char_data: Vec<u8>,
+    chr_ram: bool,
+    screen_mirroring: Mirroring,
+    bank: usize,
+}
+
+impl Uxrom {
+    ///Uxromコンストラクタ. 0x8000～0xBFFFにバンク0、0xC000～0xFFFFに最後のバンクを配置する.
+    ///
+    /// # Parameters
+    /// * `rom` - Rom
+    pub fn new(rom: Rom) -> Self {
+        Uxrom {
+            program_data: rom.program_data,
+            chr_ram: rom.char_data.is_empty(),
+            char_data: if rom.char_data.is_empty() {
+                vec![0; CHR_RAM_SIZE]
+            } else {
+                rom.char_data
+            },
+            screen_mirroring: rom.screen_mirroring,
+            bank: 0,
+        }
+    }
+
+    fn bank_count(&self) -> usize {
+        self.program_data.len().div_ceil(PRG_BANK_SIZE).max(1)
+    }
+}
+
+impl Cartridge for Uxrom {
+    fn read_prg(&self, addr: u16) -> u8 {
+        if self.program_data.is_empty() {
+            return 0;
+        }
+        let (bank, offset) = match addr {
+            0x8000..=0xbfff => (self.bank, addr - 0x8000),
+            _ => (self.bank_count() - 1, addr - 0xc000),
+        };
+        self.program_data[(bank * PRG_BANK_SIZE + offset as usize) % self.program_data.len()]
+    }
+
+    fn write_prg(&mut self, _addr: u16, data: u8) {
+        self.bank = data as usize % self.bank_count();
+    }
+
+    fn prg_rom(&self) -> Vec<u8> {
+        self.program_data.clone()
+    }
+
+    fn char_data(&self) -> Vec<u8> {
+        self.char_data.clone()
+    }
+
+    fn chr_memory(&self) -> Box<dyn ChrMemory> {
+        if self.chr_ram {
+            Box::new(ChrRam::new(self.char_data.clone()))
+        } else {
+            Box::new(self.char_data.clone())
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.screen_mirroring.clone()
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        w.u8(self.bank as u8);
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), NesError> {
+        let bank = r.u8()? as usize;
+        if bank >= self.bank_count() {
+            return Err(NesError::InvalidState("invalid PRG bank"));
+        }
+        self.bank = bank;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod uxrom_test {
+    use super::*;
+    use crate::cpu::bus::Bus;
+    use crate::cpu::cpu::{Cpu, Memory};
+
+    /// 4バンク(64KiB)のROM. 各バンクの先頭にバンク番号、
+    /// 末尾(0x3FFC)にリセットベクタとして 0x8000 + バンク番号 を書き込む
+    fn rom() -> Rom {
+        let mut program_data = vec![0; PRG_BANK_SIZE * 4];
+        for bank in 0..4 {
+            let base = bank * PRG_BANK_SIZE;
+            program_data[base] = bank as u8;
+            program_data[base + 0x3ffc] = bank as u8;
+            program_data[base + 0x3ffd] = 0x80;
+        }
+        Rom::test(program_data).with_mapper(2, Mirroring::VERTICAL)
+    }
+
+    #[test]
+    fn reset_vector_from_fixed_bank() {
+        let mut cpu = Cpu::new(Bus::new(Uxrom::new(rom()), |_| {}));
+        cpu.reset();
+        assert_eq!(cpu.pc(), 0x8003);
+    }
+
+    #[test]
+    fn switch_bank() {
+        let mut uxrom = Uxrom::new(rom());
+        assert_eq!(uxrom.read_prg(0x8000), 0);
+        assert_eq!(uxrom.read_prg(0xc000), 3);
+
+        uxrom.write_prg(0x8000, 2);
+        assert_eq!(uxrom.read_prg(0x8000), 2);
+        assert_eq!(uxrom.read_prg(0xc000), 3);
+        assert_eq!(uxrom.read_prg(0xfffc), 0x03);
+    }
+
+    #[test]
+    fn odd_sized_prg_wraps() {
+        //8KiB: 両方の窓で同じ8KiBを繰り返す
+        let mut program_data = vec![0; 0x2000];
+        program_data[0x1fff] = 0x11;
+        let mut uxrom = Uxrom::new(Rom::test(program_data).with_mapper(2, Mirroring::VERTICAL));
+        assert_eq!(uxrom.read_prg(0x9fff), 0x11);
+        assert_eq!(uxrom.read_prg(0xbfff), 0x11);
+        assert_eq!(uxrom.read_prg(0xffff), 0x11);
+        uxrom.write_prg(0x8000, 3);
+        assert_eq!(uxrom.read_prg(0xbfff), 0x11);
+
+        //24KiB: 半端なバンク1が最後のバンクになり、末尾を越えると先頭に戻る
+        let mut program_data = vec![0; 0x6000];
+        program_data[0x0000] = 0x22;
+        program_data[0x4000] = 0x33;
+        let mut uxrom = Uxrom::new(Rom::test(program_data).with_mapper(2, Mirroring::VERTICAL));
+        assert_eq!(uxrom.read_prg(0xc000), 0x33);
+        assert_eq!(uxrom.read_prg(0xe000), 0x22);
+        uxrom.write_prg(0x8000, 1);
+        assert_eq!(uxrom.read_prg(0x8000), 0x33);
+        uxrom.write_prg(0x8000, 2);
+        assert_eq!(uxrom.read_prg(0x8000), 0x22);
+    }
+
+    #[test]
+    fn save_state_restores_bank() {
+        let mut cpu = Cpu::new(Bus::new(Uxrom::new(rom()), |_| {}));
+        cpu.reset();
+        cpu.mem_write(0x8000, 1);
+        let state = cpu.save_state();
+
+        cpu.mem_write(0x8000, 2);
+        assert_eq!(cpu.mem_read(0x8000), 2);
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.mem_read(0x8000), 1);
+
+        //存在しないバンクは読み込まない
+        let mut broken = state.clone();
+        broken.cartridge = vec![4];
+        assert!(matches!(
+            cpu.load_state(&broken),
+            Err(NesError::InvalidState(_))
+        ));
+        assert_eq!(cpu.mem_read(0x8000), 1);
+    }
+}
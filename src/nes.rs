@@ -1,29 +1,161 @@
+pub mod audio;
+pub mod diagnostics;
+pub mod key_config;
+pub mod pacing;
+pub mod pause;
+pub mod save_state;
+pub mod session;
+pub mod watch;
+
 use crate::cpu::bus::Bus;
 use crate::cpu::cpu::Cpu;
-use crate::ppu::ppu::Ppu;
+use crate::cpu::state::MachineState;
+use crate::error::NesError;
+use crate::joypad::joypad::{Joypad, JoypadButton};
+use crate::joypad::turbo::{Turbo, DEFAULT_TURBO_RATE};
+use crate::joypad::zapper::Zapper;
+use crate::mapper;
+use crate::nes::audio::Audio;
+use crate::nes::key_config::KeyConfig;
+use crate::nes::pacing::Pacer;
+use crate::nes::pause::Pause;
+use crate::nes::save_state::SaveState;
+use crate::nes::watch::RomWatcher;
+use crate::ppu::ppu::{Ppu, PpuMode};
 use crate::render;
+use crate::render::filter::FrameFilter;
 use crate::render::frame::Frame;
-use crate::rom::rom::Rom;
+use crate::rom::cartridge::Cartridge;
+use crate::rom::rom::{Mirroring, Rom};
 
 use sdl2::event::Event;
+use sdl2::event::WindowEvent;
 use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
 
 use sdl2::render::Canvas;
 use sdl2::render::Texture;
 use sdl2::video::Window;
 use sdl2::EventPump;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// SDLフロントエンドの設定
+///
+/// # Parameters
+/// * `filter` - 画面に適用するフィルタ
+/// * `zapper` - ポート2に光線銃を接続する
+/// * `max_catch_up` - ホストが遅れた場合に1回の描画までに進める最大フレーム数
+/// * `pause_on_blur` - ウィンドウがフォーカスを失っている間は一時停止する
+/// * `watch` - ROMファイルのパス. 更新されたら読み込み直してリセットする(開発用)
+/// * `keys` - キー割り当て. プレイヤー2に割り当てがあり光線銃がなければポート2にパッドを接続する
+/// * `disable_sprite_limit` - スプライトの1スキャンライン8個の制限を無効にする
+/// * `turbo_rate` - 連射ボタンの1秒あたりの押下回数
+/// * `audio` - 音声の出力先. Noneの場合は音声を生成しない
+pub struct RunOptions {
+    pub filter: Option<Box<dyn FrameFilter>>,
+    pub zapper: bool,
+    pub max_catch_up: u32,
+    pub pause_on_blur: bool,
+    pub watch: Option<String>,
+    pub keys: KeyConfig,
+    pub disable_sprite_limit: bool,
+    pub turbo_rate: u32,
+    pub audio: Option<Audio>,
+}
+
+/// SDLフロントエンドでオーディオスレッドへサンプルを渡す間隔(サンプル数, 約1.5ms)
+const AUDIO_CHUNK: usize = 64;
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            filter: None,
+            zapper: false,
+            max_catch_up: Pacer::DEFAULT_MAX_CATCH_UP,
+            pause_on_blur: true,
+            watch: None,
+            keys: KeyConfig::default(),
+            disable_sprite_limit: false,
+            turbo_rate: DEFAULT_TURBO_RATE,
+            audio: None,
+        }
+    }
+}
 
 pub fn run<'a>(
-    rom: Rom,
+    cartridge: Box<dyn Cartridge>,
     mut canvas: Canvas<Window>,
     mut event_pump: EventPump,
     mut texture: Texture<'a>,
     mut frame: Frame,
+    options: RunOptions,
 ) {
+    let RunOptions {
+        filter,
+        zapper,
+        max_catch_up,
+        pause_on_blur,
+        watch,
+        keys,
+        disable_sprite_limit,
+        turbo_rate,
+        audio,
+    } = options;
+
+    //ウィンドウの拡大率(マウス座標を画面座標に変換する)
+    let (scale_x, scale_y) = canvas.scale();
+
+    //キー割り当て
+    let [key_map, key_map2] = keys.players;
+    let [turbo_map, turbo_map2] = keys.turbo;
+    let mut turbo = [Turbo::new(), Turbo::new()];
+    for turbo in turbo.iter_mut() {
+        turbo.set_rate(turbo_rate);
+    }
+    let joypad2_connected = !zapper && !key_map2.is_empty();
+
+    let joypad = Rc::new(RefCell::new(Joypad::new()));
+    let input = joypad.clone();
+    let joypad2 = Rc::new(RefCell::new(Joypad::new()));
+    let input2 = joypad2.clone();
+    let gun = Rc::new(RefCell::new(Zapper::new()));
+    let gun_input = gun.clone();
+
+    //実時間に合わせる. 遅れている間は描画せずにフレームを進める
+    let mut pacer = Pacer::new(pacing::NTSC_FRAME, max_catch_up);
+    let mut last = Instant::now();
+    let mut skip = 0;
+    let mut pause = Pause::new(pause_on_blur);
+
+    //ROMファイルが更新されたら、次の命令の前に差し替える
+    let mut watcher = watch.map(RomWatcher::new);
+    let reloaded = Rc::new(RefCell::new(None));
+    let reload = reloaded.clone();
+
+    //音声. 一時停止中はデバイスも止める
+    let audio = audio.map(|audio| Rc::new(RefCell::new(audio)));
+    let audio_pause = audio.clone();
+    let mut reported_underruns = 0;
+
     //BusとLoop処理の実装
-    let bus = Bus::new(rom, move |ppu: &Ppu| {
+    let mut bus = Bus::new(cartridge, move |ppu: &Ppu| {
+        if skip > 0 {
+            skip -= 1;
+            return;
+        }
         render::render(ppu, &mut frame);
-        texture.update(None, &frame.data, 256 * 3).unwrap();
+        gun_input.borrow_mut().update(&frame);
+        match &filter {
+            Some(filter) => texture
+                .update(None, &filter.apply(&frame).data, 256 * 3)
+                .unwrap(),
+            None => texture.update(None, &frame.data, 256 * 3).unwrap(),
+        }
 
         //画面を描画
         canvas.copy(&texture, None, None).unwrap();
@@ -38,13 +170,976 @@ pub fn run<'a>(
                     keycode: Some(Keycode::Escape),
                     ..
                 } => std::process::exit(0),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(button) = key_map.get(&keycode) {
+                        input.borrow_mut().set_button_pressed_status(*button, true);
+                    }
+                    if let Some(button) = key_map2.get(&keycode) {
+                        input2.borrow_mut().set_button_pressed_status(*button, true);
+                    }
+                    if let Some(button) = turbo_map.get(&keycode) {
+                        turbo[0].set_turbo(*button, true);
+                    }
+                    if let Some(button) = turbo_map2.get(&keycode) {
+                        turbo[1].set_turbo(*button, true);
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(button) = key_map.get(&keycode) {
+                        input.borrow_mut().set_button_pressed_status(*button, false);
+                    }
+                    if let Some(button) = key_map2.get(&keycode) {
+                        input2
+                            .borrow_mut()
+                            .set_button_pressed_status(*button, false);
+                    }
+                    if let Some(button) = turbo_map.get(&keycode) {
+                        turbo[0].set_turbo(*button, false);
+                    }
+                    if let Some(button) = turbo_map2.get(&keycode) {
+                        turbo[1].set_turbo(*button, false);
+                    }
+                }
+                Event::MouseMotion { x, y, .. } => gun_input
+                    .borrow_mut()
+                    .aim((x as f32 / scale_x) as usize, (y as f32 / scale_y) as usize),
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } => gun_input.borrow_mut().set_trigger(true),
+                Event::MouseButtonUp {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } => gun_input.borrow_mut().set_trigger(false),
+                Event::Window {
+                    win_event: WindowEvent::FocusLost,
+                    ..
+                } => pause.focus_lost(),
+                _ => {}
+            }
+        }
+
+        //連射ボタンの状態を反映する
+        turbo[0].next_frame(&mut input.borrow_mut());
+        turbo[1].next_frame(&mut input2.borrow_mut());
+
+        //一時停止中はフォーカスが戻るまでイベントを待つ
+        if let (true, Some(audio)) = (pause.is_paused(), &audio_pause) {
+            audio.borrow_mut().pause();
+        }
+        while pause.is_paused() {
+            match event_pump.wait_event() {
+                Event::Quit { .. } => std::process::exit(0),
+                Event::Window {
+                    win_event: WindowEvent::FocusGained,
+                    ..
+                } => pause.focus_gained(),
                 _ => {}
             }
+            //停止していた時間は取り戻さない
+            last = Instant::now();
         }
+
+        if let Some(rom) = watcher.as_mut().and_then(|watcher| watcher.poll()) {
+            match mapper::create(rom) {
+                Ok(cartridge) => *reloaded.borrow_mut() = Some(cartridge),
+                Err(e) => eprintln!("warning: couldn't reload ROM: {}", e),
+            }
+        }
+
+        //次のフレームの時間まで待つ
+        let frames = loop {
+            let now = Instant::now();
+            let frames = pacer.frames_to_run(now - last);
+            last = now;
+            if frames > 0 {
+                break frames;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        skip = frames - 1;
     });
+    bus.ppu_mut().disable_sprite_limit = disable_sprite_limit;
+    if audio.is_some() {
+        bus.enable_audio();
+    }
+    bus.set_controller(0, joypad);
+    if zapper {
+        bus.set_controller(1, gun);
+    } else if joypad2_connected {
+        bus.set_controller(1, joypad2);
+    }
 
     //CPUエミュレート
     let mut cpu = Cpu::new(bus);
     cpu.reset();
-    cpu.run();
+    cpu.run_with_callback(|cpu| {
+        if let Some(cartridge) = reload.borrow_mut().take() {
+            cpu.bus.load_cartridge(cartridge);
+            cpu.reset();
+        }
+        if let Some(audio) = &audio {
+            if cpu.bus.queued_audio() >= AUDIO_CHUNK {
+                let underruns = audio.borrow_mut().push(&cpu.bus.take_audio());
+                if underruns > reported_underruns {
+                    eprintln!(
+                        "warning: audio buffer underrun ({} samples in total)",
+                        underruns
+                    );
+                    reported_underruns = underruns;
+                }
+            }
+        }
+    });
+}
+
+/// 前のゲームのPRG-RAMを受け取るコールバック(`Nes::set_save_ram_callback`)
+type SaveRamCallback<'a> = Box<dyn FnMut(&[u8]) + 'a>;
+
+/// Nes Struct
+/// SDLを使わずにエミュレーションを進めるためのヘッドレスな実行環境
+///
+/// パッド1の入力は`set_buttons`で設定し、`step_frame`毎に記録する.
+/// 記録した入力は`export_session`でバグ報告用に書き出せる.
+pub struct Nes<'a> {
+    pub cpu: Cpu<'a>,
+    frame: Frame,
+    ///パッド1
+    joypad: Rc<RefCell<Joypad>>,
+    ///次のフレームで使用するパッド1のボタン状態
+    buttons: JoypadButton,
+    ///フレーム毎のパッド1の入力記録
+    input_log: Vec<u8>,
+    ///再生中のセッションの残りの入力
+    replay: VecDeque<u8>,
+    ///ROMのハッシュ
+    rom_hash: u64,
+    ///実行したフレーム数
+    frame_count: u64,
+    ///画面を描画するフレームの間隔
+    render_every: u64,
+    ///画面の描画を省略する残りのフレーム数
+    skip_frames: u64,
+    ///画面を描画したフレーム数
+    rendered_frames: u64,
+    ///マッパーの診断を行うフレーム
+    mapper_check_frame: Option<u64>,
+    ///現在のフレームの入力を反映済みか
+    in_frame: bool,
+    ///ステップ実行でマスタークロックがエミュレーションより進んでいるPPUドット数
+    ///(負の場合はエミュレーションが先行している)
+    clock_ahead: i64,
+    ///ROMの差し替え前に前のゲームのPRG-RAMを受け取るコールバック
+    save_ram_callback: Option<SaveRamCallback<'a>>,
+}
+
+impl<'a> Nes<'a> {
+    ///Nesコンストラクタ
+    ///
+    /// # Parameters
+    /// * `cartridge` - カートリッジ(Rom等)
+    pub fn new<C>(cartridge: C) -> Self
+    where
+        C: Cartridge + 'a,
+    {
+        Nes::new_with_ppu_mode(cartridge, PpuMode::Fast)
+    }
+
+    ///PPUの描画方式を指定するNesコンストラクタ
+    ///
+    /// # Parameters
+    /// * `cartridge` - カートリッジ(Rom等)
+    /// * `mode` - PPUの描画方式
+    pub fn new_with_ppu_mode<C>(cartridge: C, mode: PpuMode) -> Self
+    where
+        C: Cartridge + 'a,
+    {
+        let rom_hash = session::rom_hash(&cartridge);
+        let joypad = Rc::new(RefCell::new(Joypad::new()));
+        let mut bus = Bus::new(cartridge, |_| {});
+        bus.ppu_mut().set_mode(mode);
+        bus.set_controller(0, joypad.clone());
+        bus.enable_audio();
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        Nes {
+            cpu,
+            frame: Frame::new(),
+            joypad,
+            buttons: JoypadButton::empty(),
+            input_log: Vec::new(),
+            replay: VecDeque::new(),
+            rom_hash,
+            frame_count: 0,
+            render_every: 1,
+            skip_frames: 0,
+            rendered_frames: 0,
+            mapper_check_frame: None,
+            in_frame: false,
+            clock_ahead: 0,
+            save_ram_callback: None,
+        }
+    }
+
+    /// バグ報告のセッションを再現する.
+    /// ROMと設定を復元し、記録された入力を次のフレームから順に再生するNesを返す.
+    ///
+    /// # Parameters
+    /// * `path` - `export_session`で書き出したファイル
+    /// * `rom` - 報告されたゲームのROM
+    pub fn replay_session<P: AsRef<Path>>(path: P, rom: Rom) -> Result<Self, NesError> {
+        let session = session::Session::parse(&fs::read_to_string(path)?)?;
+        let cartridge = mapper::create(rom)?;
+        if session::rom_hash(cartridge.as_ref()) != session.rom_hash {
+            return Err(NesError::InvalidSession("ROM does not match"));
+        }
+        let mut nes = Nes::new_with_ppu_mode(cartridge, session.ppu_mode);
+        let ppu = nes.cpu.bus.ppu_mut();
+        ppu.disable_sprite_limit = session.disable_sprite_limit;
+        ppu.vblank_read_suppression = session.vblank_read_suppression;
+        nes.cpu
+            .bus
+            .set_dmc_controller_glitch(session.dmc_controller_glitch);
+        if session.entry_point.is_some() {
+            nes.set_entry_point(session.entry_point);
+            nes.cpu.reset();
+        }
+        nes.replay = session.inputs.into();
+        Ok(nes)
+    }
+
+    /// ROMを読み込み、リセットから実行する代わりにセーブステートの状態から開始する.
+    /// `frame`はセーブステートを保存した時点のフレームになる.
+    ///
+    /// # Parameters
+    /// * `rom` - セーブステートを保存したゲームのROM
+    /// * `state` - `save_state_bytes`で書き出したバイト列
+    pub fn boot_with_state(rom: Rom, state: &[u8]) -> Result<Self, NesError> {
+        let save_state = SaveState::parse(state)?;
+        let mut nes = Nes::new(mapper::create(rom)?);
+        if save_state.rom_hash != nes.rom_hash {
+            return Err(NesError::InvalidState("ROM does not match"));
+        }
+        let mut machine = nes.save_state();
+        save_state.restore(&mut machine)?;
+        nes.load_state(&machine)?;
+        nes.frame = save_state.frame;
+        Ok(nes)
+    }
+
+    /// ROMのハッシュ、最後に描画したフレームとCPU/RAM/PPU/APUとカートリッジの状態をバイト列に書き出す.
+    /// `boot_with_state`で読み込める.
+    pub fn save_state_bytes(&self) -> Vec<u8> {
+        SaveState::to_bytes(self.rom_hash, &self.save_state(), &self.frame)
+    }
+
+    /// ROMのハッシュ、設定、これまでの入力記録をファイルに書き出す.
+    ///
+    /// # Parameters
+    /// * `path` - 書き出すファイル
+    pub fn export_session<P: AsRef<Path>>(&self, path: P) -> Result<(), NesError> {
+        let ppu = self.cpu.bus.ppu();
+        let session = session::Session {
+            rom_hash: self.rom_hash,
+            ppu_mode: ppu.mode(),
+            disable_sprite_limit: ppu.disable_sprite_limit,
+            vblank_read_suppression: ppu.vblank_read_suppression,
+            dmc_controller_glitch: self.cpu.bus.dmc_controller_glitch(),
+            entry_point: self.cpu.entry_point(),
+            inputs: self.input_log.clone(),
+        };
+        fs::write(path, session.to_text())?;
+        Ok(())
+    }
+
+    /// 指定フレーム数実行した時点で一度だけ`check_mapper_behavior`を行う(開発用).
+    ///
+    /// # Parameters
+    /// * `frames` - 診断を行うまでのフレーム数
+    pub fn enable_mapper_diagnostics(&mut self, frames: u64) {
+        self.mapper_check_frame = Some(self.frame_count + frames);
+    }
+
+    /// マッパー/ミラーリングが正しくない兆候があれば警告をログに出力し、trueを返す.
+    /// 画面が崩れるROMの原因調査用で、判定はヒューリスティック.
+    pub fn check_mapper_behavior(&self) -> bool {
+        let warnings = diagnostics::mapper_warnings(&self.cpu.bus);
+        for warning in warnings.iter() {
+            log::warn!("possible unsupported mapper behavior detected: {}", warning);
+        }
+        !warnings.is_empty()
+    }
+
+    /// 画面を描画するフレームの間隔を設定する(遅いホスト向け).
+    /// CPU/PPUは全フレームをエミュレートし、RGBへの描画だけをNフレーム毎に行う.
+    /// 描画しないフレームでは`frame`は前回描画した画面のまま.
+    ///
+    /// # Parameters
+    /// * `n` - 描画するフレームの間隔. 1(デフォルト)は全フレームを描画する
+    pub fn set_render_every_n_frames(&mut self, n: u32) {
+        self.render_every = n.max(1) as u64;
+        self.skip_frames = 0;
+    }
+
+    /// 画面を描画したフレーム数
+    pub fn rendered_frames(&self) -> u64 {
+        self.rendered_frames
+    }
+
+    /// 次のフレームからのパッド1のボタン状態を設定する.
+    ///
+    /// # Parameters
+    /// * `buttons` - 押されているボタン
+    pub fn set_buttons(&mut self, buttons: JoypadButton) {
+        self.buttons = buttons;
+    }
+
+    /// `load_rom`でROMを差し替える前に、前のゲームのPRG-RAM(セーブRAM)を受け取るコールバックを設定する.
+    ///
+    /// # Parameters
+    /// * `callback` - PRG-RAMの内容を引数にとるクロージャ. PRG-RAMを持たないカートリッジでは呼ばれない
+    pub fn set_save_ram_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(&[u8]) + 'a,
+    {
+        self.save_ram_callback = Some(Box::new(callback));
+    }
+
+    /// ROMを差し替えてCPUをリセットする. Nes自体は作り直さない.
+    /// 差し替える前に、前のゲームのPRG-RAMを`set_save_ram_callback`のコールバックに渡す.
+    ///
+    /// 対応していないマッパーの場合は`NesError::UnsupportedMapper`を返し、前のゲームをそのまま続ける.
+    ///
+    /// # Parameters
+    /// * `rom` - 新しいROM
+    pub fn load_rom(&mut self, rom: Rom) -> Result<(), NesError> {
+        let cartridge = mapper::create(rom)?;
+        let prg_ram = self.cpu.bus.prg_ram();
+        if let Some(callback) = &mut self.save_ram_callback {
+            if !prg_ram.is_empty() {
+                callback(prg_ram);
+            }
+        }
+        self.rom_hash = session::rom_hash(cartridge.as_ref());
+        self.input_log.clear();
+        self.replay.clear();
+        self.cpu.bus.load_cartridge(cartridge);
+        self.cpu.reset();
+        self.frame = Frame::new();
+        self.skip_frames = 0;
+        self.in_frame = false;
+        self.clock_ahead = 0;
+        Ok(())
+    }
+
+    /// CPU/RAM/PPU/APUとカートリッジ(マッパーのレジスタやPRG-RAM)の状態を複製する(セーブステート).
+    pub fn save_state(&self) -> MachineState {
+        self.cpu.save_state()
+    }
+
+    /// `save_state`で複製した状態に戻す.
+    ///
+    /// # Parameters
+    /// * `state` - 戻す状態
+    pub fn load_state(&mut self, state: &MachineState) -> Result<(), NesError> {
+        self.cpu.load_state(state)
+    }
+
+    /// ネームテーブルのミラーリングを強制する(マッパーのミラーリングのデバッグ用).
+    /// ROMを差し替えると解除される.
+    ///
+    /// # Parameters
+    /// * `mirroring` - 使用するミラーリング. Noneの場合はカートリッジのミラーリングに戻す
+    pub fn set_mirroring(&mut self, mirroring: Option<Mirroring>) {
+        self.cpu.bus.force_mirroring(mirroring);
+    }
+
+    /// リセット時にリセットベクタの代わりに実行を開始するアドレスを設定する.
+    /// 次の`reset`から有効になる.
+    ///
+    /// # Parameters
+    /// * `entry_point` - 開始アドレス. Noneの場合はリセットベクタに戻す
+    pub fn set_entry_point(&mut self, entry_point: Option<u16>) {
+        self.cpu.set_entry_point(entry_point);
+    }
+
+    /// リセットボタンによるリセット.
+    /// PPU/APUをリセットしてからCPUがリセットベクタを読み直す.
+    pub fn reset(&mut self) {
+        self.cpu.bus.reset();
+        self.cpu.soft_reset();
+    }
+
+    /// 1フレーム分エミュレーションを進め、画面を描画する.
+    /// フレームの開始時にパッド1の入力を反映して記録する.
+    pub fn step_frame(&mut self) {
+        loop {
+            self.begin_frame();
+            if !self.cpu.step() || self.cpu.bus.take_frame_complete() {
+                break;
+            }
+        }
+        self.end_frame();
+    }
+
+    /// ステップ実行: 1命令実行し、マスタークロックを命令のサイクル数分進める.
+    /// フレームが完了した場合は画面を描画してtrueを返す.
+    ///
+    /// `tick_ppu_dot`と合わせて呼び出し側がマスタークロックを進める.
+    /// 実時間やSDLには依存しないため、同じROMと入力からは常に同じフレームの列が得られる.
+    pub fn tick_cpu_instruction(&mut self) -> bool {
+        self.begin_frame();
+        self.cpu.step();
+        if self.cpu.bus.take_frame_complete() {
+            self.end_frame();
+            return true;
+        }
+        false
+    }
+
+    /// ステップ実行: マスタークロックを1PPUドット進める.
+    /// 命令は途中で止められないため、エミュレーションがクロックに遅れた時点で次の1命令を実行し、
+    /// 以降はその命令のサイクル数分のドットが経過するまで何もしない.
+    /// フレームが完了した場合は画面を描画してtrueを返す.
+    pub fn tick_ppu_dot(&mut self) -> bool {
+        self.clock_ahead += 1;
+        if self.clock_ahead <= 0 {
+            return false;
+        }
+        let cycles = self.cpu.bus.cycles();
+        let frame_complete = self.tick_cpu_instruction();
+        self.clock_ahead -= (self.cpu.bus.cycles() - cycles) as i64 * 3;
+        frame_complete
+    }
+
+    /// フレームの開始時にパッド1の入力を反映して記録する(フレーム中は何もしない)
+    fn begin_frame(&mut self) {
+        if self.in_frame {
+            return;
+        }
+        self.in_frame = true;
+        if let Some(buttons) = self.replay.pop_front() {
+            self.buttons = JoypadButton::from_bits_truncate(buttons);
+        }
+        self.input_log.push(self.buttons.bits());
+        let mut joypad = self.joypad.borrow_mut();
+        joypad.set_button_pressed_status(JoypadButton::all(), false);
+        joypad.set_button_pressed_status(self.buttons, true);
+    }
+
+    /// フレームの完了時に画面を描画する(`set_render_every_n_frames`の間隔毎)
+    fn end_frame(&mut self) {
+        self.in_frame = false;
+        if self.skip_frames == 0 {
+            render::render(self.cpu.bus.ppu(), &mut self.frame);
+            self.rendered_frames += 1;
+            self.skip_frames = self.render_every;
+        }
+        self.skip_frames -= 1;
+
+        self.frame_count += 1;
+        if self.mapper_check_frame == Some(self.frame_count) {
+            self.check_mapper_behavior();
+        }
+    }
+
+    /// PPUが1スキャンラインを完了するまでエミュレーションを進め、
+    /// 到達したスキャンライン(0～261)を返す.
+    /// `step_frame`と同様にフレームの開始時に入力を反映して記録し、
+    /// フレームが完了した場合は画面を描画する(`set_render_every_n_frames`の間隔毎).
+    pub fn step_scanline(&mut self) -> u16 {
+        let scanline = self.cpu.bus.ppu().scanline();
+        while self.cpu.bus.ppu().scanline() == scanline {
+            self.begin_frame();
+            if !self.cpu.step() {
+                break;
+            }
+        }
+        if self.cpu.bus.take_frame_complete() {
+            self.end_frame();
+        }
+        self.cpu.bus.ppu().scanline()
+    }
+
+    /// 指定フレーム数エミュレーションを進める.
+    ///
+    /// # Parameters
+    /// * `frames` - フレーム数
+    pub fn run_frames(&mut self, frames: usize) {
+        for _ in 0..frames {
+            self.step_frame();
+        }
+    }
+
+    /// 前回の呼び出し以降に生成した音声(44100Hz, モノラル).
+    /// サンプルはCPUクロックから生成するため、1フレームあたり約734サンプルとなり映像とずれない.
+    /// 呼び出さない場合は直近0.5秒分のみ保持し、古いサンプルは破棄する.
+    pub fn take_audio(&mut self) -> Vec<f32> {
+        self.cpu.bus.take_audio()
+    }
+
+    /// 最後に描画したフレーム
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    /// 現在のPPUの状態から、内部解像度をfactor倍にして描画したスクリーンショットを返す.
+    /// (`render::render_supersampled`)
+    ///
+    /// # Parameters
+    /// * `factor` - 内部解像度の倍率. 1の場合は通常の描画と同じ
+    /// * `filter` - 拡大した解像度で適用するフィルタ
+    pub fn screenshot(&self, factor: usize, filter: Option<&dyn FrameFilter>) -> Frame {
+        render::render_supersampled(self.cpu.bus.ppu(), factor, filter)
+    }
+
+    /// 指定フレーム数実行した後の画面をサムネイルとして返す.
+    ///
+    /// # Parameters
+    /// * `frames` - フレーム数
+    pub fn thumbnail(&mut self, frames: usize) -> Frame {
+        self.run_frames(frames);
+        self.frame().clone()
+    }
+}
+
+#[cfg(test)]
+mod nes_test {
+    use super::*;
+    use crate::cpu::cpu::{CpuFlags, Memory};
+    use crate::mapper::nrom::Nrom;
+    use crate::rom::cartridge::RamCartridge;
+
+    #[test]
+    fn reset_mid_frame() {
+        let program = [
+            0xa9, 0x1e, //       LDA #$1E
+            0x8d, 0x01, 0x20, // STA $2001
+            0xee, 0x00, 0x02, // INC $0200
+            0x4c, 0x05, 0x80, // JMP $8005
+        ];
+        let mut nes = Nes::new(RamCartridge::new(&program, 0x2000));
+        nes.cpu.bus.ppu_mut().vram[0x10] = 0x42;
+        for _ in 0..500 {
+            nes.cpu.step();
+        }
+        let sp = nes.cpu.reg_sp;
+
+        nes.reset();
+
+        assert_eq!(nes.cpu.reg_pc, 0x8000);
+        assert_eq!(nes.cpu.reg_sp, sp.wrapping_sub(3));
+        assert!(nes.cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+        assert_eq!(nes.cpu.bus.ppu().ctrl.bits(), 0);
+        assert_eq!(nes.cpu.bus.ppu().mask.bits(), 0);
+        assert_eq!(nes.cpu.bus.ppu().vram[0x10], 0x42);
+
+        let counter = nes.cpu.mem_read(0x0200);
+        nes.run_frames(2);
+        assert_ne!(nes.cpu.mem_read(0x0200), counter);
+        assert_eq!(nes.cpu.bus.ppu().mask.bits(), 0x1e);
+    }
+
+    fn rom(program: &[u8], chr_value: u8) -> Rom {
+        let mut program_data = vec![0; 0x4000];
+        program_data[..program.len()].copy_from_slice(program);
+        //リセットベクタ 0x8000
+        program_data[0x3ffd] = 0x80;
+        Rom::test(program_data).with_char_data(vec![chr_value; 0x2000])
+    }
+
+    #[test]
+    fn forced_mirroring_survives_mapper_writes() {
+        let mut nes = Nes::new(Nrom::new(rom(&[0x4c, 0x00, 0x80], 0)));
+        nes.set_mirroring(Some(Mirroring::VERTICAL));
+        assert_eq!(nes.cpu.bus.ppu().mirroring, Mirroring::VERTICAL);
+        nes.cpu.mem_write(0x8000, 0x00);
+        assert_eq!(nes.cpu.bus.ppu().mirroring, Mirroring::VERTICAL);
+
+        nes.set_mirroring(None);
+        assert_eq!(nes.cpu.bus.ppu().mirroring, Mirroring::HORIZONTAL);
+    }
+
+    #[test]
+    fn entry_point_override() {
+        let program = [
+            0xa9, 0x01, //       LDA #$01
+            0x8d, 0x00, 0x02, // STA $0200
+            0x4c, 0x05, 0x80, // JMP $8005
+            0xa9, 0x02, //       $8008: LDA #$02
+            0x8d, 0x01, 0x02, // STA $0201
+            0x4c, 0x0d, 0x80, // JMP $800D
+        ];
+        let mut nes = Nes::new(Nrom::new(rom(&program, 0)));
+        nes.set_entry_point(Some(0x8008));
+        nes.reset();
+        assert_eq!(nes.cpu.pc(), 0x8008);
+
+        nes.run_frames(1);
+        assert_eq!(nes.cpu.mem_read(0x0200), 0x00);
+        assert_eq!(nes.cpu.mem_read(0x0201), 0x02);
+
+        nes.set_entry_point(None);
+        nes.reset();
+        assert_eq!(nes.cpu.pc(), 0x8000);
+
+        //セッションは開始アドレスを記録して再現する
+        let path = std::env::temp_dir().join("nes_rs_entry_point_session_test.txt");
+        let mut nes = Nes::new(Nrom::new(rom(&program, 0)));
+        nes.set_entry_point(Some(0x8008));
+        nes.reset();
+        nes.export_session(&path).unwrap();
+        let replay = Nes::replay_session(&path, rom(&program, 0));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(replay.unwrap().cpu.pc(), 0x8008);
+    }
+
+    #[test]
+    fn audio_samples_per_frame() {
+        // JMP $8000
+        let mut nes = Nes::new(RamCartridge::new(&[0x4c, 0x00, 0x80], 0x2000));
+        nes.take_audio();
+        //44100 / 60.0988 ≒ 733.8
+        let mut total = 0;
+        for _ in 0..120 {
+            nes.step_frame();
+            let samples = nes.take_audio().len();
+            assert!((733..=735).contains(&samples), "samples: {}", samples);
+            total += samples;
+        }
+        assert!((88_050..=88_070).contains(&total), "total: {}", total);
+
+        //取り出さなくても溜まり続けない
+        for _ in 0..120 {
+            nes.step_frame();
+        }
+        assert_eq!(nes.take_audio().len(), 22_050);
+    }
+
+    #[test]
+    fn load_rom() {
+        let first = [
+            0xa9, 0x01, //       LDA #$01
+            0x8d, 0x00, 0x02, // STA $0200
+            0x4c, 0x05, 0x80, // JMP $8005
+        ];
+        let second = [
+            0xa9, 0x02, //       LDA #$02
+            0x8d, 0x01, 0x02, // STA $0201
+            0x4c, 0x05, 0x80, // JMP $8005
+        ];
+        let mut nes = Nes::new(Nrom::new(rom(&first, 0x11)));
+        nes.run_frames(1);
+        assert_eq!(nes.cpu.mem_read(0x0200), 0x01);
+
+        nes.load_rom(rom(&second, 0x22)).unwrap();
+        assert_eq!(nes.cpu.pc(), 0x8000);
+        assert_eq!(nes.cpu.mem_read(0x0200), 0x00);
+
+        nes.run_frames(1);
+        assert_eq!(nes.cpu.mem_read(0x0200), 0x00);
+        assert_eq!(nes.cpu.mem_read(0x0201), 0x02);
+        assert_eq!(nes.cpu.bus.ppu().read_chr(0), 0x22);
+    }
+
+    #[test]
+    fn load_rom_flushes_save_ram() {
+        let program = [
+            0xa9, 0x5a, //       LDA #$5A
+            0x8d, 0x00, 0x60, // STA $6000
+            0x4c, 0x05, 0x80, // JMP $8005
+        ];
+        let saved = Rc::new(RefCell::new(Vec::new()));
+        let mut nes = Nes::new(Nrom::new(rom(&program, 0x11)));
+        let sink = saved.clone();
+        nes.set_save_ram_callback(move |prg_ram| sink.borrow_mut().push(prg_ram.to_vec()));
+        nes.run_frames(1);
+
+        nes.load_rom(rom(&[0x4c, 0x00, 0x80], 0x22)).unwrap();
+        let saved = saved.borrow();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].len(), 0x2000);
+        assert_eq!(saved[0][0], 0x5a);
+        //新しいゲームのPRG-RAMは空
+        assert_eq!(nes.cpu.mem_read(0x6000), 0x00);
+    }
+
+    #[test]
+    fn load_rom_unsupported_mapper() {
+        let program = [
+            0xee, 0x00, 0x02, // INC $0200
+            0x4c, 0x00, 0x80, // JMP $8000
+        ];
+        let flushed = Rc::new(RefCell::new(false));
+        let mut nes = Nes::new(Nrom::new(rom(&program, 0x11)));
+        let sink = flushed.clone();
+        nes.set_save_ram_callback(move |_| *sink.borrow_mut() = true);
+        nes.run_frames(1);
+        let pc = nes.cpu.pc();
+        let counter = nes.cpu.mem_read(0x0200);
+
+        //エラーを返し、ROMの差し替えもセーブRAMの書き出しも行わない
+        let unsupported = rom(&[0x4c, 0x00, 0x80], 0x33).with_mapper(4, Mirroring::HORIZONTAL);
+        assert!(matches!(
+            nes.load_rom(unsupported),
+            Err(NesError::UnsupportedMapper(4))
+        ));
+        assert!(!*flushed.borrow());
+        assert_eq!(nes.cpu.bus.ppu().read_chr(0), 0x11);
+        assert_eq!(nes.cpu.pc(), pc);
+
+        //前のゲームをそのまま続ける
+        nes.run_frames(1);
+        assert_ne!(nes.cpu.mem_read(0x0200), counter);
+    }
+
+    #[test]
+    fn step_scanline() {
+        // JMP $8000
+        let mut nes = Nes::new(RamCartridge::new(&[0x4c, 0x00, 0x80], 0x2000));
+        assert_eq!(nes.cpu.bus.ppu().scanline(), 0);
+
+        let scanlines: Vec<u16> = (0..262).map(|_| nes.step_scanline()).collect();
+        let expected: Vec<u16> = (1..262).chain(0..1).collect();
+        assert_eq!(scanlines, expected);
+
+        //1フレーム(262 * 341 / 3 ≒ 29781 CPUサイクル)
+        let cycles = nes.cpu.bus.cycles();
+        assert!((29781..29781 + 7).contains(&cycles), "cycles: {}", cycles);
+    }
+
+    #[test]
+    fn step_scanline_counts_frames() {
+        // JMP $8000
+        let mut nes = Nes::new(RamCartridge::new(&[0x4c, 0x00, 0x80], 0x2000));
+        nes.set_render_every_n_frames(2);
+        nes.set_buttons(JoypadButton::BUTTON_A);
+
+        //2フレーム分をスキャンライン毎に進める
+        for _ in 0..262 * 2 {
+            nes.step_scanline();
+        }
+        assert_eq!(nes.frame_count, 2);
+        assert_eq!(nes.rendered_frames(), 1);
+        assert_eq!(nes.input_log, vec![JoypadButton::BUTTON_A.bits(); 2]);
+        //ボタンの状態がパッドに反映されている
+        nes.cpu.mem_write(0x4016, 1);
+        nes.cpu.mem_write(0x4016, 0);
+        assert_eq!(nes.cpu.mem_read(0x4016) & 1, 1);
+    }
+
+    #[test]
+    fn thumbnail() {
+        // JMP $8000
+        let mut nes = Nes::new(RamCartridge::new(&[0x4c, 0x00, 0x80], 0x2000));
+        let frame = nes.thumbnail(3);
+        assert_eq!(frame.data.len(), 256 * 240 * 3);
+
+        let path = std::env::temp_dir().join("nes_rs_thumbnail_test.png");
+        let path = path.to_str().unwrap();
+        frame.save_png(path).unwrap();
+        assert_eq!(image::image_dimensions(path).unwrap(), (256, 240));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Aボタンの状態で背景色を切り替えるプログラム
+    const BUTTON_COLOR_PROGRAM: [u8; 43] = [
+        0xa9, 0x08, //       LDA #$08
+        0x8d, 0x01, 0x20, // STA $2001
+        0xa9, 0x01, //       LDA #$01
+        0x8d, 0x16, 0x40, // STA $4016
+        0xa9, 0x00, //       LDA #$00
+        0x8d, 0x16, 0x40, // STA $4016
+        0xad, 0x16, 0x40, // LDA $4016
+        0x29, 0x01, //       AND #$01
+        0x0a, 0x0a, 0x0a, 0x0a, // ASL A x4
+        0x09, 0x01, //       ORA #$01
+        0xaa, //             TAX
+        0xa9, 0x3f, //       LDA #$3F
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa9, 0x00, //       LDA #$00
+        0x8d, 0x06, 0x20, // STA $2006
+        0x8e, 0x07, 0x20, // STX $2007
+        0x4c, 0x05, 0x80, // JMP $8005
+    ];
+
+    fn frame_hash(nes: &Nes) -> u64 {
+        session::fnv1a(nes.frame().data.iter().copied())
+    }
+
+    #[test]
+    fn export_and_replay_session() {
+        let mut nes = Nes::new(Nrom::new(rom(&BUTTON_COLOR_PROGRAM, 0)));
+        nes.cpu.bus.ppu_mut().disable_sprite_limit = true;
+        nes.cpu.bus.set_dmc_controller_glitch(true);
+        let inputs = [0, 0, 1, 1, 0, 1, 0, 0];
+        let mut hashes = Vec::new();
+        for &input in inputs.iter() {
+            nes.set_buttons(JoypadButton::from_bits_truncate(input));
+            nes.step_frame();
+            hashes.push(frame_hash(&nes));
+        }
+        assert_ne!(hashes[1], hashes[2]);
+
+        let path = std::env::temp_dir().join("nes_rs_session_test.txt");
+        nes.export_session(&path).unwrap();
+
+        let mut replay = Nes::replay_session(&path, rom(&BUTTON_COLOR_PROGRAM, 0)).unwrap();
+        assert!(replay.cpu.bus.ppu().disable_sprite_limit);
+        assert!(replay.cpu.bus.dmc_controller_glitch());
+        let replayed: Vec<u64> = inputs
+            .iter()
+            .map(|_| {
+                replay.step_frame();
+                frame_hash(&replay)
+            })
+            .collect();
+        assert_eq!(replayed, hashes);
+
+        //別のROMでは再生できない
+        let other = Nes::replay_session(&path, rom(&BUTTON_COLOR_PROGRAM, 1));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(other, Err(NesError::InvalidSession(_))));
+    }
+
+    #[test]
+    fn boot_with_state() {
+        let mut nes = Nes::new(Nrom::new(rom(&BUTTON_COLOR_PROGRAM, 0)));
+        nes.set_buttons(JoypadButton::BUTTON_A);
+        nes.run_frames(3);
+        let state = nes.save_state_bytes();
+
+        let mut booted = Nes::boot_with_state(rom(&BUTTON_COLOR_PROGRAM, 0), &state).unwrap();
+        assert_eq!(booted.cpu.pc(), nes.cpu.pc());
+        assert_eq!(frame_hash(&booted), frame_hash(&nes));
+
+        //保存した時点から同じフレームを描画する
+        for buttons in [JoypadButton::BUTTON_A, JoypadButton::empty()] {
+            nes.set_buttons(buttons);
+            booted.set_buttons(buttons);
+            nes.step_frame();
+            booted.step_frame();
+            assert_eq!(frame_hash(&booted), frame_hash(&nes));
+        }
+
+        //別のROMでは開始できない
+        let other = Nes::boot_with_state(rom(&BUTTON_COLOR_PROGRAM, 1), &state);
+        assert!(matches!(
+            other,
+            Err(NesError::InvalidState("ROM does not match"))
+        ));
+    }
+
+    #[test]
+    fn render_every_n_frames() {
+        let mut nes = Nes::new(Nrom::new(rom(&BUTTON_COLOR_PROGRAM, 0)));
+        let mut skipping = Nes::new(Nrom::new(rom(&BUTTON_COLOR_PROGRAM, 0)));
+        skipping.set_render_every_n_frames(2);
+        nes.run_frames(10);
+        skipping.run_frames(10);
+
+        assert_eq!(nes.rendered_frames(), 10);
+        assert_eq!(skipping.rendered_frames(), 5);
+        //エミュレーションは同じだけ進む
+        assert_eq!(skipping.cpu.bus.cycles(), nes.cpu.bus.cycles());
+        assert_eq!(frame_hash(&skipping), frame_hash(&nes));
+
+        //描画しないフレームでは前回の画面を保持する
+        skipping.set_buttons(JoypadButton::BUTTON_A);
+        skipping.step_frame();
+        let pressed = frame_hash(&skipping);
+        assert_ne!(pressed, frame_hash(&nes));
+        skipping.set_buttons(JoypadButton::empty());
+        skipping.step_frame();
+        assert_eq!(frame_hash(&skipping), pressed);
+        assert_eq!(skipping.rendered_frames(), 6);
+    }
+
+    /// ステップ実行でフレームが完了する毎のハッシュ. 3フレーム目からAボタンを押す
+    fn stepped_frame_hashes(tick_dots: bool) -> Vec<u64> {
+        let mut nes = Nes::new(Nrom::new(rom(&BUTTON_COLOR_PROGRAM, 0)));
+        let mut hashes = Vec::new();
+        while hashes.len() < 6 {
+            let frame_complete = if tick_dots {
+                nes.tick_ppu_dot()
+            } else {
+                nes.tick_cpu_instruction()
+            };
+            if frame_complete {
+                hashes.push(frame_hash(&nes));
+                nes.set_buttons(if hashes.len() >= 2 {
+                    JoypadButton::BUTTON_A
+                } else {
+                    JoypadButton::empty()
+                });
+            }
+        }
+        hashes
+    }
+
+    #[test]
+    fn stepped_clock_is_reproducible() {
+        let hashes = stepped_frame_hashes(true);
+        assert_eq!(stepped_frame_hashes(true), hashes);
+        assert_ne!(hashes[1], hashes[2]);
+        //ドット単位でも命令単位でも同じフレームになる
+        assert_eq!(stepped_frame_hashes(false), hashes);
+
+        //1フレームは89342ドット(命令の途中で終わった分は次のフレームに持ち越す)
+        let mut nes = Nes::new(Nrom::new(rom(&BUTTON_COLOR_PROGRAM, 0)));
+        let frames = (0..89342 * 3).filter(|_| nes.tick_ppu_dot()).count();
+        assert_eq!(frames, 3);
+    }
+
+    /// テスト用のロガー. 出力されたメッセージを保存する
+    struct TestLogger;
+
+    static LOGGER: TestLogger = TestLogger;
+    static LOGGED: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+    impl log::Log for TestLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            LOGGED.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn mapper_diagnostics_warns_on_ignored_register_write() {
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Warn);
+
+        let program = [
+            0xa9, 0x02, //       LDA #$02
+            0x8d, 0x00, 0x80, // STA $8000 (UxROMのバンク切り替え)
+            0x4c, 0x05, 0x80, // JMP $8005
+        ];
+        let mut nes = Nes::new(Nrom::new(rom(&program, 0)));
+        nes.enable_mapper_diagnostics(2);
+        nes.run_frames(2);
+
+        assert!(nes.check_mapper_behavior());
+        let logged = LOGGED.lock().unwrap();
+        assert!(logged.iter().any(|message| message
+            == "possible unsupported mapper behavior detected: \
+                1 write to PRG-ROM was ignored (the game may use an unsupported mapper)"));
+    }
+
+    #[test]
+    fn mapper_diagnostics_quiet_for_well_behaved_game() {
+        let mut nes = Nes::new(Nrom::new(rom(&BUTTON_COLOR_PROGRAM, 0)));
+        nes.run_frames(2);
+        assert!(!nes.check_mapper_behavior());
+    }
 }
@@ -0,0 +1,84 @@
+use crate::apu::apu::SAMPLE_RATE;
+use crate::apu::buffer::SampleBuffer;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::AudioSubsystem;
+use std::sync::{Arc, Mutex};
+
+/// `--audio-buffer`のデフォルト値(サンプル数, 約23ms)
+pub const DEFAULT_AUDIO_BUFFER: u16 = 1024;
+
+/// SDLのオーディオスレッドでSampleBufferからサンプルを取り出す
+struct AudioOutput {
+    buffer: Arc<Mutex<SampleBuffer>>,
+}
+
+impl AudioCallback for AudioOutput {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.buffer.lock().unwrap().fill(out);
+    }
+}
+
+/// Audio Struct
+/// APUのサンプルをSDLのオーディオデバイスへ出力する.
+///
+/// # Parameters
+/// * `device` - SDLのオーディオデバイス(44100Hz, モノラル)
+/// * `buffer` - エミュレータとオーディオスレッドの間のキュー. デバイスのバッファ4つ分
+/// * `playing` - 再生中かどうか. 最初のサンプルを渡すまでは停止しておく
+pub struct Audio {
+    device: AudioDevice<AudioOutput>,
+    buffer: Arc<Mutex<SampleBuffer>>,
+    playing: bool,
+}
+
+impl Audio {
+    /// オーディオデバイスを開く. 最初の`push`で再生を始める.
+    ///
+    /// # Parameters
+    /// * `audio` - SDLのオーディオサブシステム
+    /// * `samples` - デバイスのバッファサイズ(サンプル数). 小さいほど遅延が減りアンダーランが増える
+    pub fn open(audio: &AudioSubsystem, samples: u16) -> Result<Self, String> {
+        let buffer = Arc::new(Mutex::new(SampleBuffer::new(samples as usize * 4)));
+        let spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE as i32),
+            channels: Some(1),
+            samples: Some(samples),
+        };
+        let output = AudioOutput {
+            buffer: buffer.clone(),
+        };
+        let device = audio.open_playback(None, &spec, |_| output)?;
+        Ok(Audio {
+            device,
+            buffer,
+            playing: false,
+        })
+    }
+
+    /// サンプルをキューに追加し、これまでのアンダーランの回数を返す.
+    ///
+    /// # Parameters
+    /// * `samples` - APUが生成したサンプル
+    pub fn push(&mut self, samples: &[f32]) -> usize {
+        let underruns = {
+            let mut buffer = self.buffer.lock().unwrap();
+            for &sample in samples {
+                buffer.push(sample);
+            }
+            buffer.underruns()
+        };
+        if !self.playing {
+            self.device.resume();
+            self.playing = true;
+        }
+        underruns
+    }
+
+    /// 再生を止める(一時停止中にアンダーランとして数えないため). 次の`push`で再開する.
+    pub fn pause(&mut self) {
+        self.device.pause();
+        self.playing = false;
+    }
+}
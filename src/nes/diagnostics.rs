@@ -0,0 +1,67 @@
+use crate::cpu::bus::Bus;
+
+/// ネームテーブルがノイズのように見えると判断するエントロピー(bit/byte).
+/// 通常のゲームの画面は同じタイルの繰り返しが多く、これよりずっと小さい.
+const NOISY_NAMETABLE_ENTROPY: f64 = 7.0;
+
+/// マッパー/ミラーリングの設定が間違っている可能性を示す兆候を返す.
+///
+/// | 兆候 |  detail |
+/// |---------|---------|
+/// |マッパーが無視した書き込み | PRG-ROMへの書き込み. 未対応のマッパーレジスタ(バンク切り替え等)の可能性がある|
+/// |ネームテーブルのエントロピー | 画面がノイズのようなデータになっている|
+///
+/// # Parameters
+/// * `bus` - Bus
+pub fn mapper_warnings(bus: &Bus) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let ignored = bus.ignored_cartridge_writes();
+    if ignored > 0 {
+        //意図的にROMへ書き込むゲームもあるため、マッパーレジスタと断定しない
+        warnings.push(if ignored == 1 {
+            "1 write to PRG-ROM was ignored (the game may use an unsupported mapper)".to_string()
+        } else {
+            format!(
+                "{} writes to PRG-ROM were ignored (the game may use an unsupported mapper)",
+                ignored
+            )
+        });
+    }
+    let entropy = entropy(&bus.ppu().nametable(0));
+    if entropy > NOISY_NAMETABLE_ENTROPY {
+        warnings.push(format!(
+            "nametable 0 looks like noise (entropy {:.2})",
+            entropy
+        ));
+    }
+    warnings
+}
+
+/// バイト列のシャノンエントロピー(bit/byte)
+fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &byte in data {
+        counts[byte as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod diagnostics_test {
+    use super::*;
+
+    #[test]
+    fn entropy_of_repeated_and_random_data() {
+        assert_eq!(entropy(&[0x24; 1024]), 0.0);
+        let all: Vec<u8> = (0..1024).map(|i| i as u8).collect();
+        assert!((entropy(&all) - 8.0).abs() < 1e-9);
+    }
+}
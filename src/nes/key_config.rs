@@ -0,0 +1,239 @@
+use crate::error::NesError;
+use crate::joypad::joypad::JoypadButton;
+
+use sdl2::keyboard::Keycode;
+use std::collections::HashMap;
+use std::fs;
+
+/// KeyConfig Struct
+/// キーボードのキーとパッドのボタンの割り当て
+///
+/// 設定ファイルはTOMLのサブセットで、プレイヤー毎のセクションに`キー = "ボタン"`を書く.
+/// ファイルにあるセクションはそのプレイヤーのデフォルトの割り当てを置き換える.
+///
+/// ```toml
+/// [player1]
+/// Up = "up"
+/// Z = "b"
+/// X = "a"
+/// A = "turbo_a"
+///
+/// [player2]
+/// I = "up"
+/// ```
+///
+/// | 項目 |  detail |
+/// |---------|---------|
+/// |キー | A～Z, 0～9, Up, Down, Left, Right, Return, Space, Tab, Backspace, LShift, RShift, LCtrl, RCtrl|
+/// |ボタン | a, b, select, start, up, down, left, right, turbo_a, turbo_b(連射)|
+///
+/// # Parameters
+/// * `players` - プレイヤー1,2のキー割り当て
+/// * `turbo` - プレイヤー1,2の連射ボタンのキー割り当て
+#[derive(Debug, PartialEq)]
+pub struct KeyConfig {
+    pub players: [HashMap<Keycode, JoypadButton>; 2],
+    pub turbo: [HashMap<Keycode, JoypadButton>; 2],
+}
+
+impl Default for KeyConfig {
+    /// プレイヤー1は矢印キー/Space(SELECT)/Return(START)/A/S、連射はQ(A)/W(B).
+    /// プレイヤー2は割り当てなし
+    fn default() -> Self {
+        let player1 = HashMap::from([
+            (Keycode::Down, JoypadButton::DOWN),
+            (Keycode::Up, JoypadButton::UP),
+            (Keycode::Right, JoypadButton::RIGHT),
+            (Keycode::Left, JoypadButton::LEFT),
+            (Keycode::Space, JoypadButton::SELECT),
+            (Keycode::Return, JoypadButton::START),
+            (Keycode::A, JoypadButton::BUTTON_A),
+            (Keycode::S, JoypadButton::BUTTON_B),
+        ]);
+        let turbo1 = HashMap::from([
+            (Keycode::Q, JoypadButton::BUTTON_A),
+            (Keycode::W, JoypadButton::BUTTON_B),
+        ]);
+        KeyConfig {
+            players: [player1, HashMap::new()],
+            turbo: [turbo1, HashMap::new()],
+        }
+    }
+}
+
+impl KeyConfig {
+    /// 設定ファイルを読み込む. 不正な行は無視し、警告として返す.
+    ///
+    /// # Parameters
+    /// * `path` - 設定ファイルのパス
+    pub fn load(path: &str) -> Result<(Self, Vec<String>), NesError> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// 設定ファイルの内容を解析する. 不正な行は無視し、"line N: ..."の警告として返す.
+    ///
+    /// # Parameters
+    /// * `text` - 設定ファイルの内容
+    pub fn parse(text: &str) -> (Self, Vec<String>) {
+        let mut config = KeyConfig::default();
+        let mut warnings = Vec::new();
+        let mut player = None;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut warn = |message: String| warnings.push(format!("line {}: {}", i + 1, message));
+
+            if let Some(section) = line.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                player = match section.trim() {
+                    "player1" => Some(0),
+                    "player2" => Some(1),
+                    section => {
+                        warn(format!("unknown section [{}]", section));
+                        None
+                    }
+                };
+                //ファイルにあるセクションはデフォルトを置き換える
+                if let Some(player) = player {
+                    config.players[player].clear();
+                    config.turbo[player].clear();
+                }
+                continue;
+            }
+
+            let player = match player {
+                Some(player) => player,
+                None => {
+                    warn("binding outside of [player1] or [player2]".to_string());
+                    continue;
+                }
+            };
+            let (key, button) = match line.split_once('=') {
+                Some((key, button)) => (key.trim(), button.trim().trim_matches('"')),
+                None => {
+                    warn(format!("expected `key = \"button\"`, got `{}`", line));
+                    continue;
+                }
+            };
+            match (keycode(key), button_from_name(button)) {
+                (Some(key), Some((button, false))) => {
+                    config.players[player].insert(key, button);
+                }
+                (Some(key), Some((button, true))) => {
+                    config.turbo[player].insert(key, button);
+                }
+                (None, _) => warn(format!("unknown key `{}`", key)),
+                (_, None) => warn(format!("unknown button `{}`", button)),
+            }
+        }
+        (config, warnings)
+    }
+}
+
+/// キー名からKeycodeを求める(大文字小文字は区別しない)
+fn keycode(name: &str) -> Option<Keycode> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        //英数字のKeycodeは小文字のASCIIコード
+        if c.is_ascii_alphanumeric() {
+            return Keycode::from_i32(c.to_ascii_lowercase() as i32);
+        }
+    }
+    match name.to_ascii_lowercase().as_str() {
+        "up" => Some(Keycode::Up),
+        "down" => Some(Keycode::Down),
+        "left" => Some(Keycode::Left),
+        "right" => Some(Keycode::Right),
+        "return" | "enter" => Some(Keycode::Return),
+        "space" => Some(Keycode::Space),
+        "tab" => Some(Keycode::Tab),
+        "backspace" => Some(Keycode::Backspace),
+        "lshift" => Some(Keycode::LShift),
+        "rshift" => Some(Keycode::RShift),
+        "lctrl" => Some(Keycode::LCtrl),
+        "rctrl" => Some(Keycode::RCtrl),
+        _ => None,
+    }
+}
+
+/// ボタン名からJoypadButtonと連射ボタンかどうかを求める
+fn button_from_name(name: &str) -> Option<(JoypadButton, bool)> {
+    match name.to_ascii_lowercase().as_str() {
+        "a" => Some((JoypadButton::BUTTON_A, false)),
+        "b" => Some((JoypadButton::BUTTON_B, false)),
+        "select" => Some((JoypadButton::SELECT, false)),
+        "start" => Some((JoypadButton::START, false)),
+        "up" => Some((JoypadButton::UP, false)),
+        "down" => Some((JoypadButton::DOWN, false)),
+        "left" => Some((JoypadButton::LEFT, false)),
+        "right" => Some((JoypadButton::RIGHT, false)),
+        "turbo_a" => Some((JoypadButton::BUTTON_A, true)),
+        "turbo_b" => Some((JoypadButton::BUTTON_B, true)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod key_config_test {
+    use super::*;
+
+    #[test]
+    fn parse_sample_file() {
+        let text = "\
+# custom keys
+[player1]
+Up = \"up\"
+Down = \"down\"
+z = \"b\"
+X = \"a\"
+RShift = \"select\"
+Return = \"start\"
+F13 = \"a\"
+V = \"turbo_a\"
+
+[player2]
+I = \"up\"
+K = \"turbo\"
+";
+        let (config, warnings) = KeyConfig::parse(text);
+        assert_eq!(
+            config.players[0],
+            HashMap::from([
+                (Keycode::Up, JoypadButton::UP),
+                (Keycode::Down, JoypadButton::DOWN),
+                (Keycode::Z, JoypadButton::BUTTON_B),
+                (Keycode::X, JoypadButton::BUTTON_A),
+                (Keycode::RShift, JoypadButton::SELECT),
+                (Keycode::Return, JoypadButton::START),
+            ])
+        );
+        assert_eq!(
+            config.turbo[0],
+            HashMap::from([(Keycode::V, JoypadButton::BUTTON_A)])
+        );
+        assert_eq!(
+            config.players[1],
+            HashMap::from([(Keycode::I, JoypadButton::UP)])
+        );
+        assert_eq!(
+            warnings,
+            vec![
+                "line 9: unknown key `F13`".to_string(),
+                "line 14: unknown button `turbo`".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_section_keeps_defaults() {
+        let (config, warnings) = KeyConfig::parse("[player2]\nW = \"up\"\n");
+        assert!(warnings.is_empty());
+        assert_eq!(config.players[0], KeyConfig::default().players[0]);
+        assert_eq!(config.turbo[0], KeyConfig::default().turbo[0]);
+        assert_eq!(
+            config.players[1],
+            HashMap::from([(Keycode::W, JoypadButton::UP)])
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+/// NTSCの1フレームの時間(約60.0988fps)
+pub const NTSC_FRAME: Duration = Duration::from_nanos(16_639_267);
+
+/// Pacer Struct
+/// 実時間に合わせて、1回の描画までに進めるエミュレーションのフレーム数を決める.
+///
+/// ホストが止まった後に遅れを全て取り戻そうとすると、追いつくための処理で
+/// さらに遅れる(spiral of death)ため、1回で進めるフレーム数に上限を設け、
+/// 上限を超えた分の遅れは捨てる.
+///
+/// # Parameters
+/// * `frame_duration` - 1フレームの時間
+/// * `max_catch_up` - 1回で進める最大フレーム数
+/// * `behind` - まだ進めていない時間
+#[derive(Debug)]
+pub struct Pacer {
+    frame_duration: Duration,
+    max_catch_up: u32,
+    behind: Duration,
+}
+
+impl Pacer {
+    /// 1回で進める最大フレーム数のデフォルト
+    pub const DEFAULT_MAX_CATCH_UP: u32 = 4;
+
+    ///Pacerコンストラクタ
+    ///
+    /// # Parameters
+    /// * `frame_duration` - 1フレームの時間
+    /// * `max_catch_up` - 1回で進める最大フレーム数(0は1として扱う)
+    pub fn new(frame_duration: Duration, max_catch_up: u32) -> Self {
+        Pacer {
+            frame_duration,
+            max_catch_up: max_catch_up.max(1),
+            behind: Duration::ZERO,
+        }
+    }
+
+    /// 前回からの経過時間を加え、進めるフレーム数を返す.
+    /// 上限に達した場合は残りの遅れを捨てる.
+    ///
+    /// # Parameters
+    /// * `elapsed` - 前回の呼び出しからの経過時間
+    pub fn frames_to_run(&mut self, elapsed: Duration) -> u32 {
+        self.behind += elapsed;
+        let frames = self.behind.as_nanos() / self.frame_duration.as_nanos();
+        if frames >= self.max_catch_up as u128 {
+            self.behind = Duration::ZERO;
+            return self.max_catch_up;
+        }
+        let frames = frames as u32;
+        self.behind -= self.frame_duration * frames;
+        frames
+    }
+}
+
+#[cfg(test)]
+mod pacing_test {
+    use super::*;
+
+    #[test]
+    fn runs_elapsed_frames() {
+        let mut pacer = Pacer::new(NTSC_FRAME, 4);
+        let half = NTSC_FRAME / 2;
+        assert_eq!(pacer.frames_to_run(half), 0);
+        assert_eq!(pacer.frames_to_run(NTSC_FRAME - half), 1);
+        assert_eq!(pacer.frames_to_run(NTSC_FRAME * 3), 3);
+    }
+
+    #[test]
+    fn caps_catch_up_after_stall() {
+        let mut pacer = Pacer::new(NTSC_FRAME, 4);
+        assert_eq!(pacer.frames_to_run(Duration::from_secs(10)), 4);
+        //捨てた遅れは取り戻さない
+        assert_eq!(pacer.frames_to_run(Duration::ZERO), 0);
+        assert_eq!(pacer.frames_to_run(NTSC_FRAME), 1);
+
+        let mut pacer = Pacer::new(NTSC_FRAME, 2);
+        for _ in 0..10 {
+            assert!(pacer.frames_to_run(Duration::from_secs(3600)) <= 2);
+        }
+    }
+}
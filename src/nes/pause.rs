@@ -0,0 +1,64 @@
+/// Pause Struct
+/// ウィンドウのフォーカスによる一時停止の状態.
+/// フォーカスを失うと一時停止し、フォーカスが戻ると再開する.
+///
+/// # Parameters
+/// * `pause_on_blur` - フォーカスを失った時に一時停止するかどうか
+/// * `focused` - ウィンドウがフォーカスを持っているかどうか
+#[derive(Debug)]
+pub struct Pause {
+    pause_on_blur: bool,
+    focused: bool,
+}
+
+impl Pause {
+    ///Pauseコンストラクタ. フォーカスを持った状態で開始する.
+    ///
+    /// # Parameters
+    /// * `pause_on_blur` - フォーカスを失った時に一時停止するかどうか
+    pub fn new(pause_on_blur: bool) -> Self {
+        Pause {
+            pause_on_blur,
+            focused: true,
+        }
+    }
+
+    /// ウィンドウがフォーカスを失った
+    pub fn focus_lost(&mut self) {
+        self.focused = false;
+    }
+
+    /// ウィンドウがフォーカスを得た
+    pub fn focus_gained(&mut self) {
+        self.focused = true;
+    }
+
+    /// 一時停止中かどうか
+    pub fn is_paused(&self) -> bool {
+        self.pause_on_blur && !self.focused
+    }
+}
+
+#[cfg(test)]
+mod pause_test {
+    use super::*;
+
+    #[test]
+    fn pauses_while_unfocused() {
+        let mut pause = Pause::new(true);
+        assert!(!pause.is_paused());
+        pause.focus_lost();
+        assert!(pause.is_paused());
+        pause.focus_lost();
+        assert!(pause.is_paused());
+        pause.focus_gained();
+        assert!(!pause.is_paused());
+    }
+
+    #[test]
+    fn disabled_pause_on_blur() {
+        let mut pause = Pause::new(false);
+        pause.focus_lost();
+        assert!(!pause.is_paused());
+    }
+}
@@ -0,0 +1,134 @@
+use crate::cpu::state::{MachineState, StateReader, StateWriter};
+use crate::error::NesError;
+use crate::render::frame::Frame;
+
+/// ファイルの先頭
+const HEADER: &[u8] = b"nes-rs state 2\n";
+
+/// SaveState Struct
+/// ファイルに書き出すセーブステート(バイナリ, リトルエンディアン)
+///
+/// | field |  detail |
+/// |---------|---------|
+/// |rom_hash | ROMのハッシュ(`session::rom_hash`)|
+/// |frame | 保存時点で最後に描画したフレーム|
+/// |machine | `MachineState::write`で書き出したCPU/RAM/PPU/APUとカートリッジの状態|
+pub struct SaveState<'a> {
+    pub rom_hash: u64,
+    pub frame: Frame,
+    machine: &'a [u8],
+}
+
+impl<'a> SaveState<'a> {
+    /// セーブステートをバイト列にする.
+    ///
+    /// # Parameters
+    /// * `rom_hash` - ROMのハッシュ
+    /// * `state` - CPU/RAM/PPU/APUとカートリッジの状態
+    /// * `frame` - 最後に描画したフレーム
+    pub fn to_bytes(rom_hash: u64, state: &MachineState, frame: &Frame) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.bytes(HEADER);
+        w.u64(rom_hash);
+        w.bytes(&frame.data);
+        state.write(&mut w);
+        w.into_bytes()
+    }
+
+    /// バイト列からROMのハッシュとフレームを読み込む. 状態は`restore`で読み込む.
+    ///
+    /// # Parameters
+    /// * `data` - `to_bytes`で書き出したバイト列
+    pub fn parse(data: &'a [u8]) -> Result<Self, NesError> {
+        let mut r = StateReader::new(data);
+        if r.bytes(HEADER.len())? != HEADER {
+            return Err(NesError::InvalidState("missing header"));
+        }
+        let rom_hash = r.u64()?;
+        let mut frame = Frame::new();
+        frame.data.copy_from_slice(r.bytes(Frame::SIZE)?);
+        let machine = r.bytes(r.remaining())?;
+        Ok(SaveState {
+            rom_hash,
+            frame,
+            machine,
+        })
+    }
+
+    /// CPU/RAM/PPU/APUとカートリッジの状態を読み込む.
+    ///
+    /// # Parameters
+    /// * `state` - 読み込み先. CHR-ROM等、セーブステートに含まれないものは保持する
+    pub fn restore(&self, state: &mut MachineState) -> Result<(), NesError> {
+        let mut r = StateReader::new(self.machine);
+        state.read(&mut r)?;
+        if r.remaining() != 0 {
+            return Err(NesError::InvalidState("trailing data"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod save_state_test {
+    use super::*;
+    use crate::cpu::bus::Bus;
+    use crate::cpu::cpu::Cpu;
+    use crate::ppu::ppu::PpuMode;
+    use crate::rom::cartridge::RamCartridge;
+
+    fn state(mode: PpuMode) -> MachineState {
+        // INC $0200; JMP $8000
+        let program = [0xee, 0x00, 0x02, 0x4c, 0x00, 0x80];
+        let mut bus = Bus::new(RamCartridge::new(&program, 0x2000), |_| {});
+        bus.ppu_mut().set_mode(mode);
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        for _ in 0..5000 {
+            cpu.step();
+        }
+        cpu.save_state()
+    }
+
+    #[test]
+    fn round_trip() {
+        for mode in [PpuMode::Fast, PpuMode::Accurate] {
+            let saved = state(mode);
+            let mut frame = Frame::new();
+            frame.set_pixel(1, 2, (3, 4, 5));
+            let bytes = SaveState::to_bytes(0x0123_4567_89ab_cdef, &saved, &frame);
+
+            let parsed = SaveState::parse(&bytes).unwrap();
+            assert_eq!(parsed.rom_hash, 0x0123_4567_89ab_cdef);
+            assert_eq!(parsed.frame.data, frame.data);
+            let mut restored = state(PpuMode::Fast);
+            restored.ram = [0; 2048];
+            parsed.restore(&mut restored).unwrap();
+            assert_eq!(restored.registers, saved.registers);
+            assert_eq!(restored.ram[..], saved.ram[..]);
+            assert_eq!(restored.cycles, saved.cycles);
+            assert_eq!(restored.ppu.dot(), saved.ppu.dot());
+            assert_eq!(restored.ppu.mode(), mode);
+
+            //同じ状態は同じバイト列になる
+            assert_eq!(
+                SaveState::to_bytes(0x0123_4567_89ab_cdef, &restored, &frame),
+                bytes
+            );
+        }
+    }
+
+    #[test]
+    fn broken_state() {
+        let bytes = SaveState::to_bytes(0, &state(PpuMode::Fast), &Frame::new());
+        assert!(matches!(
+            SaveState::parse(b"nes-rs session 1\n"),
+            Err(NesError::InvalidState(_))
+        ));
+        let truncated = SaveState::parse(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(matches!(
+            truncated.restore(&mut state(PpuMode::Fast)),
+            Err(NesError::InvalidState("truncated"))
+        ));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::error::NesError;
+use crate::ppu::ppu::PpuMode;
+use crate::rom::cartridge::Cartridge;
+
+/// セッションファイルの1行目
+const MAGIC: &str = "nes-rs session 2";
+
+/// Session Struct
+/// バグ報告を再現するためのセッション(ROM、設定、入力記録)
+///
+/// テキスト形式で1行に1項目を保存する.
+///
+/// | key |  detail |
+/// |---------|---------|
+/// |rom_hash | ROMのハッシュ(FNV-1a 64bit, 16進数)|
+/// |ppu_mode | fast / accurate|
+/// |disable_sprite_limit | 0 / 1|
+/// |vblank_read_suppression | 0 / 1|
+/// |dmc_controller_glitch | 0 / 1|
+/// |entry_point | 開始アドレス(16進数4桁). リセットベクタから開始した場合は none|
+/// |inputs | フレーム毎のパッド1のボタン状態(16進数2桁の連続)|
+#[derive(Debug, PartialEq)]
+pub struct Session {
+    pub rom_hash: u64,
+    pub ppu_mode: PpuMode,
+    pub disable_sprite_limit: bool,
+    pub vblank_read_suppression: bool,
+    pub dmc_controller_glitch: bool,
+    pub entry_point: Option<u16>,
+    pub inputs: Vec<u8>,
+}
+
+impl Session {
+    /// テキスト形式に変換する
+    pub fn to_text(&self) -> String {
+        let ppu_mode = match self.ppu_mode {
+            PpuMode::Fast => "fast",
+            PpuMode::Accurate => "accurate",
+        };
+        let entry_point = match self.entry_point {
+            Some(addr) => format!("{:04x}", addr),
+            None => "none".to_string(),
+        };
+        let inputs: String = self.inputs.iter().map(|b| format!("{:02x}", b)).collect();
+        format!(
+            "{}\nrom_hash {:016x}\nppu_mode {}\ndisable_sprite_limit {}\nvblank_read_suppression {}\ndmc_controller_glitch {}\nentry_point {}\ninputs {}\n",
+            MAGIC,
+            self.rom_hash,
+            ppu_mode,
+            self.disable_sprite_limit as u8,
+            self.vblank_read_suppression as u8,
+            self.dmc_controller_glitch as u8,
+            entry_point,
+            inputs
+        )
+    }
+
+    /// テキスト形式から読み込む
+    ///
+    /// # Parameters
+    /// * `text` - `to_text`で出力したテキスト
+    pub fn parse(text: &str) -> Result<Self, NesError> {
+        let mut lines = text.lines();
+        if lines.next() != Some(MAGIC) {
+            return Err(NesError::InvalidSession("missing header"));
+        }
+        let mut value = |key: &str| -> Result<&str, NesError> {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(key))
+                .and_then(|line| line.strip_prefix(' ').or(Some("")))
+                .ok_or(NesError::InvalidSession("missing field"))
+        };
+        let flag = |value: &str| match value {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            _ => Err(NesError::InvalidSession("invalid flag")),
+        };
+
+        let rom_hash = u64::from_str_radix(value("rom_hash")?, 16)
+            .map_err(|_| NesError::InvalidSession("invalid rom hash"))?;
+        let ppu_mode = match value("ppu_mode")? {
+            "fast" => PpuMode::Fast,
+            "accurate" => PpuMode::Accurate,
+            _ => return Err(NesError::InvalidSession("invalid ppu mode")),
+        };
+        let disable_sprite_limit = flag(value("disable_sprite_limit")?)?;
+        let vblank_read_suppression = flag(value("vblank_read_suppression")?)?;
+        let dmc_controller_glitch = flag(value("dmc_controller_glitch")?)?;
+        let entry_point = match value("entry_point")? {
+            "none" => None,
+            addr => Some(
+                u16::from_str_radix(addr, 16)
+                    .map_err(|_| NesError::InvalidSession("invalid entry point"))?,
+            ),
+        };
+        let inputs = value("inputs")?;
+        //16進数以外(非ASCII文字を含む)は文字境界で切れないため先に弾く
+        if inputs.len() % 2 != 0 || !inputs.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(NesError::InvalidSession("invalid inputs"));
+        }
+        let inputs = (0..inputs.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&inputs[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| NesError::InvalidSession("invalid inputs"))?;
+
+        Ok(Session {
+            rom_hash,
+            ppu_mode,
+            disable_sprite_limit,
+            vblank_read_suppression,
+            dmc_controller_glitch,
+            entry_point,
+            inputs,
+        })
+    }
+}
+
+/// FNV-1a(64bit)
+pub fn fnv1a(data: impl IntoIterator<Item = u8>) -> u64 {
+    data.into_iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// PRG-ROM全体とキャラクターデータのハッシュ.
+/// バンク切り替えを行うカートリッジでも、電源投入時に見えていないバンクを含める.
+pub fn rom_hash(cartridge: &dyn Cartridge) -> u64 {
+    fnv1a(cartridge.prg_rom().into_iter().chain(cartridge.char_data()))
+}
+
+#[cfg(test)]
+mod session_test {
+    use super::*;
+
+    #[test]
+    fn text_round_trip() {
+        let session = Session {
+            rom_hash: 0x0123_4567_89ab_cdef,
+            ppu_mode: PpuMode::Accurate,
+            disable_sprite_limit: false,
+            vblank_read_suppression: true,
+            dmc_controller_glitch: true,
+            entry_point: Some(0xc123),
+            inputs: vec![0x00, 0x81, 0xff],
+        };
+        assert_eq!(Session::parse(&session.to_text()).unwrap(), session);
+    }
+
+    #[test]
+    fn parse_rejects_broken_file() {
+        assert!(Session::parse("").is_err());
+        assert!(Session::parse("nes-rs session 2\nrom_hash xyz\n").is_err());
+        //非ASCII文字は文字境界で切らずにエラーにする
+        let text = Session {
+            rom_hash: 0,
+            ppu_mode: PpuMode::Fast,
+            disable_sprite_limit: false,
+            vblank_read_suppression: true,
+            dmc_controller_glitch: false,
+            entry_point: None,
+            inputs: vec![],
+        }
+        .to_text()
+        .replace("inputs \n", "inputs 0あ\n");
+        assert!(matches!(
+            Session::parse(&text),
+            Err(NesError::InvalidSession("invalid inputs"))
+        ));
+    }
+
+    #[test]
+    fn rom_hash_covers_all_prg_banks() {
+        use crate::mapper::uxrom::Uxrom;
+        use crate::rom::rom::{Mirroring, Rom};
+
+        //電源投入時に見えていないバンク1だけが異なるUxROM
+        let rom = |byte: u8| {
+            let mut program_data = vec![0; 0x4000 * 4];
+            program_data[0x4000] = byte;
+            Rom::test(program_data).with_mapper(2, Mirroring::VERTICAL)
+        };
+        assert_ne!(rom_hash(&Uxrom::new(rom(1))), rom_hash(&Uxrom::new(rom(2))));
+    }
+}
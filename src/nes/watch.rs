@@ -0,0 +1,117 @@
+use crate::rom::rom::Rom;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// RomWatcher Struct
+/// ROMファイルの更新日時を監視し、変更されたら読み込み直す(開発用).
+///
+/// # Parameters
+/// * `path` - ROMファイルのパス
+/// * `modified` - 最後に読み込んだ時点の更新日時
+#[derive(Debug)]
+pub struct RomWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl RomWatcher {
+    ///RomWatcherコンストラクタ. 現在のファイルを読み込み済みとして扱う.
+    ///
+    /// # Parameters
+    /// * `path` - ROMファイルのパス
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let mut watcher = RomWatcher {
+            path: path.into(),
+            modified: None,
+        };
+        watcher.modified = watcher.modified_time();
+        watcher
+    }
+
+    /// ファイルが更新されていれば読み込んだROMを返す.
+    /// ビルド途中でファイルが読めない、または壊れている場合は、次の呼び出しで読み込み直す.
+    pub fn poll(&mut self) -> Option<Rom> {
+        let modified = self.modified_time()?;
+        if self.modified == Some(modified) {
+            return None;
+        }
+        let rom = Rom::from_bytes(&fs::read(&self.path).ok()?).ok()?;
+        self.modified = Some(modified);
+        Some(rom)
+    }
+
+    fn modified_time(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path).and_then(|m| m.modified()).ok()
+    }
+}
+
+#[cfg(test)]
+mod watch_test {
+    use super::*;
+    use crate::nes::Nes;
+    use crate::rom::cartridge::RamCartridge;
+    use std::fs::File;
+    use std::time::Duration;
+
+    /// PRG 16KiB、先頭が`first`のiNESファイル
+    fn ines(first: u8) -> Vec<u8> {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, 0, 0];
+        data.resize(16, 0);
+        let mut prg = vec![0; 0x4000];
+        prg[0] = first;
+        data.extend_from_slice(&prg);
+        data.extend_from_slice(&[0; 0x2000]);
+        data
+    }
+
+    #[test]
+    fn reload_on_change() {
+        let path = std::env::temp_dir().join("nes_rs_watch_test.nes");
+        fs::write(&path, ines(0x11)).unwrap();
+        let mut watcher = RomWatcher::new(&path);
+        assert!(watcher.poll().is_none());
+
+        let mut nes = Nes::new(RamCartridge::new(&[], 0x2000));
+        fs::write(&path, ines(0x22)).unwrap();
+        //更新日時の精度に依存しないように進める
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        nes.load_rom(watcher.poll().unwrap()).unwrap();
+        assert_eq!(nes.cpu.bus.peek(0x8000), 0x22);
+        assert!(watcher.poll().is_none());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncated_file_is_retried() {
+        let path = std::env::temp_dir().join("nes_rs_watch_truncated_test.nes");
+        fs::write(&path, ines(0x11)).unwrap();
+        let mut watcher = RomWatcher::new(&path);
+
+        //書き込み途中のファイル
+        let set_modified = |secs| {
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(SystemTime::now() + Duration::from_secs(secs))
+                .unwrap()
+        };
+        fs::write(&path, &ines(0x22)[..0x1010]).unwrap();
+        set_modified(10);
+        assert!(watcher.poll().is_none());
+
+        //書き込みが終わったら読み込む
+        fs::write(&path, ines(0x22)).unwrap();
+        set_modified(10);
+        assert_eq!(watcher.poll().unwrap().program_data[0], 0x22);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
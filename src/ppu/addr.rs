@@ -1,15 +1,14 @@
-/// Address Register Struct
-#[derive(Debug)]
+/// Address Register Struct (0x2006)
+/// 書き込みの順番(1回目: 上位バイト, 2回目: 下位バイト)は0x2005と共有するPPUの`w`ラッチで決まる.
+#[derive(Debug, Clone)]
 pub struct AddrRegister {
     value: (u8, u8),
-    hi_ptr: bool,
 }
 
 impl AddrRegister {
     pub fn new() -> Self {
         AddrRegister {
             value: (0, 0), // high byte first, lo byte second
-            hi_ptr: true,
         }
     }
     fn set(&mut self, data: u16) {
@@ -17,8 +16,11 @@ impl AddrRegister {
         self.value.1 = (data & 0xff) as u8;
     }
 
-    pub fn update(&mut self, data: u8) {
-        if self.hi_ptr {
+    /// # Parameters
+    /// * `data` - 書き込む値
+    /// * `second_write` - `w`ラッチが2回目の書き込みを示しているかどうか
+    pub fn update(&mut self, data: u8, second_write: bool) {
+        if !second_write {
             self.value.0 = data;
         } else {
             self.value.1 = data;
@@ -28,7 +30,6 @@ impl AddrRegister {
             //mirror down addr above 0x3fff
             self.set(self.get() & 0b11111111111111);
         }
-        self.hi_ptr = !self.hi_ptr;
     }
 
     pub fn increment(&mut self, inc: u8) {
@@ -42,11 +43,13 @@ impl AddrRegister {
         }
     }
 
-    pub fn reset_latch(&mut self) {
-        self.hi_ptr = true;
-    }
-
     pub fn get(&self) -> u16 {
         ((self.value.0 as u16) << 8) | (self.value.1 as u16)
     }
 }
+
+impl Default for AddrRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
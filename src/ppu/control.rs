@@ -66,13 +66,13 @@ impl ControlRegister {
         }
     }
 
-    // pub fn sprite_size(&self) -> u8 {
-    //     if !self.contains(ControlRegister::SPRITE_SIZE) {
-    //         8
-    //     } else {
-    //         16
-    //     }
-    // }
+    pub fn sprite_size(&self) -> u8 {
+        if !self.contains(ControlRegister::SPRITE_SIZE) {
+            8
+        } else {
+            16
+        }
+    }
 
     // pub fn master_slave_select(&self) -> u8 {
     //     if !self.contains(ControlRegister::SPRITE_SIZE) {
@@ -90,3 +90,9 @@ impl ControlRegister {
         self.bits = data;
     }
 }
+
+impl Default for ControlRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -54,6 +54,11 @@ impl MaskRegister {
     //     self.contains(MaskRegister::SHOW_SPRITES)
     // }
 
+    /// 背景かスプライトの描画が有効かどうか
+    pub fn is_rendering_enabled(&self) -> bool {
+        self.intersects(MaskRegister::SHOW_BACKGROUND | MaskRegister::SHOW_SPRITES)
+    }
+
     // pub fn emphasise(&self) -> Vec<Color> {
     //     let mut result = Vec::<Color>::new();
     //     if self.contains(MaskRegister::EMPHASISE_RED) {
@@ -73,3 +78,9 @@ impl MaskRegister {
         self.bits = data;
     }
 }
+
+impl Default for MaskRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,10 +1,87 @@
+use crate::cpu::state::{StateReader, StateWriter};
+use crate::error::NesError;
 use crate::ppu::addr::AddrRegister;
 use crate::ppu::control::ControlRegister;
 use crate::ppu::mask::MaskRegister;
 use crate::ppu::scroll::ScrollRegister;
 use crate::ppu::status::StatusRegister;
+use crate::render;
+use crate::render::frame::Frame;
+use crate::rom::cartridge::{Cartridge, ChrMemory, CHR_RAM_SIZE};
 use crate::rom::rom::Mirroring;
 
+/// TileInfo Struct
+/// ネームテーブルの1マス(8x8ドット)の背景タイルの情報
+///
+/// # Parameters
+/// * `tile_index` - タイル番号(ネームテーブルの値)
+/// * `palette` - 属性テーブルから求めたパレット番号(0～3)
+/// * `pattern_addr` - パターンテーブル上のタイルのアドレス
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct TileInfo {
+    pub tile_index: u8,
+    pub palette: u8,
+    pub pattern_addr: u16,
+}
+
+/// SpriteEntry Struct
+/// OAMのスプライト1個(4バイト)をデコードした情報
+///
+/// | byte |  detail |
+/// |---------|---------|
+/// |0 | Y座標(表示されるのは次のスキャンラインから)|
+/// |1 | タイル番号|
+/// |2 | 属性(bit0-1: パレット, bit5: 背景の後ろ, bit6: 左右反転, bit7: 上下反転)|
+/// |3 | X座標|
+///
+/// # Parameters
+/// * `index` - OAMのスロット(0～63)
+/// * `y` - Y座標(OAMの値そのまま)
+/// * `tile` - タイル番号
+/// * `attributes` - 属性(OAMの値そのまま)
+/// * `x` - X座標
+/// * `palette` - スプライトパレット番号(0～3)
+/// * `behind_background` - 背景の後ろに表示する
+/// * `flip_horizontal` - 左右反転
+/// * `flip_vertical` - 上下反転
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SpriteEntry {
+    pub index: u8,
+    pub y: u8,
+    pub tile: u8,
+    pub attributes: u8,
+    pub x: u8,
+    pub palette: u8,
+    pub behind_background: bool,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+/// スプライト属性の実装されているビット(bit2～4は常に0)
+const OAM_ATTRIBUTE_MASK: u8 = 0b1110_0011;
+
+/// PPUの描画方式
+///
+/// | mode |  detail |
+/// |---------|---------|
+/// |Fast | フレーム完了時にその時点のPPUの状態から1フレームをまとめて描画する(デフォルト)|
+/// |Accurate | PPUサイクル毎に、そのサイクルで出力されるドットを描画する|
+///
+/// どちらも`render::render_pixels`で描画するため、静止した画面の描画結果は同じになる.
+/// フレームの途中(スキャンラインの途中を含む)でPPUの状態を書き換える以下の効果は
+/// Accurateでのみ再現される.
+///
+/// * パレットの書き換え(背景色のグラデーション等)
+/// * PPUCTRLによるパターンテーブルの切り替え
+/// * PPUMASKによるグレースケール/カラーエンファシスの切り替え
+/// * VRAM/OAMの書き換え
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum PpuMode {
+    #[default]
+    Fast,
+    Accurate,
+}
+
 /// PPU struct
 /// PPUのレジスタはCPUから見て0x2000~0x2007
 ///
@@ -30,14 +107,15 @@ use crate::rom::rom::Mirroring;
 /// |0x2005| PPUSCROLL| W| 背景スクロールオフセット| 背景スクロール値|
 /// |0x2006| PPUADDR| W| PPUメモリアドレス| 書き込むPPUメモリ領域のアドレス|
 /// |0x2007| PPUDATA| RW| PPUメモリデータ| PPUメモリ領域のデータ|
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Ppu {
-    ///ROMに保存されているゲームのビジュアル
-    pub char_data: Vec<u8>,
+    ///ROMに保存されているゲームのビジュアル(パターンテーブル)
+    chr: Box<dyn ChrMemory>,
     ///画面で使用されるパレットテーブルを保持するための内部メモリ
     pub palette_table: [u8; 32],
-    ///背景情報を保持するための2KiBのスペースバンク
-    pub vram: [u8; 2048],
+    ///背景情報を保持するための4KiBのスペースバンク.
+    ///本体のVRAMは2KiBで、後半の2KiBは4画面ミラーリングのカートリッジ上のVRAMとして使う
+    pub vram: [u8; 4096],
     ///スプライトの状態を保持するための内部メモリ
     pub oam_data: [u8; 256],
     ///ミラーリング
@@ -53,8 +131,12 @@ pub struct Ppu {
     pub status: StatusRegister,
     /// Scroll Register
     pub scroll: ScrollRegister,
+    ///0x2005/0x2006で共有する書き込みラッチ(false: 1回目, true: 2回目). 0x2002の読み出しでクリアされる
+    w: bool,
 
     pub oam_addr: u8,
+    ///0x2007の読み出しバッファ. 電源投入時とリセットで0になる.
+    ///0x2006でアドレスを変えても、0x2002を読んでも保持され、次の0x2007の読み出しで返る.
     internal_data_buf: u8,
 
     ///ライン
@@ -63,6 +145,29 @@ pub struct Ppu {
     cycles: usize,
     ///NMI
     pub nmi_interrupt: Option<u8>,
+    ///スプライトの1スキャンライン8個の制限を無効にする(ちらつき防止).
+    ///オーバーフローフラグは制限の有無に関わらず実機通りに設定される.
+    pub disable_sprite_limit: bool,
+    ///VBLANKフラグが立つのと同時に0x2002を読んだ場合の競合を再現する(デフォルトtrue).
+    ///
+    /// * `true` - 実機と同様に読み出し値のVBLANKフラグは0になり、
+    ///   そのフレームのVBLANKフラグとNMIは発生しない
+    /// * `false` - 競合を無視し、VBLANKフラグが立った値を返す
+    ///   (競合に依存してうまく動かないゲーム向けの互換設定)
+    pub vblank_read_suppression: bool,
+    ///描画方式
+    mode: PpuMode,
+    ///Accurateモードでドット毎に描画するフレーム
+    frame: Option<Frame>,
+    ///現在のスキャンラインで描画済みのドット数
+    rendered_x: usize,
+    ///VBLANKが始まった(スキャンライン241に入った)ことを通知するフラグ
+    vblank_started: bool,
+    ///ネームテーブル選択(loopyの`t`のbit10～11). 0x2000と0x2006の1回目の書き込みで更新される
+    t_nametable: u8,
+    ///描画に使うネームテーブル選択(loopyの`v`のbit10～11).
+    ///描画中はbit0(水平)がドット257で、bit1(垂直)がプリレンダーラインのドット280～304で`t`からコピーされる
+    v_nametable: u8,
 }
 
 pub trait TPpu {
@@ -77,6 +182,7 @@ pub trait TPpu {
     fn write_to_data(&mut self, value: u8);
     fn read_data(&mut self) -> u8;
     fn write_oam_dma(&mut self, value: &[u8; 256]);
+    fn write_oam_dma_byte(&mut self, value: u8);
 }
 
 impl Ppu {
@@ -86,8 +192,33 @@ impl Ppu {
     /// * `char_data` - キャラクターデータ
     /// * `mirroring` - ミラーリング
     pub fn new_ppu(char_data: Vec<u8>, mirroring: Mirroring) -> Self {
+        Ppu::new_with_chr(Box::new(char_data), mirroring)
+    }
+
+    ///描画方式を指定するPPUコンストラクタ
+    ///
+    /// # Parameters
+    /// * `char_data` - キャラクターデータ
+    /// * `mirroring` - ミラーリング
+    /// * `mode` - 描画方式
+    pub fn new_ppu_with_mode(char_data: Vec<u8>, mirroring: Mirroring, mode: PpuMode) -> Self {
+        let mut ppu = Ppu::new_ppu(char_data, mirroring);
+        ppu.set_mode(mode);
+        ppu
+    }
+
+    ///カートリッジのパターンテーブルを参照するPPUコンストラクタ.
+    ///CHRのバンク切り替えやCHR-RAMはカートリッジ側の状態がそのまま反映される.
+    ///
+    /// # Parameters
+    /// * `cartridge` - カートリッジ
+    pub fn from_cartridge(cartridge: &dyn Cartridge) -> Self {
+        Ppu::new_with_chr(cartridge.chr_memory(), cartridge.mirroring())
+    }
+
+    fn new_with_chr(chr: Box<dyn ChrMemory>, mirroring: Mirroring) -> Self {
         Ppu {
-            char_data,
+            chr,
             mirroring,
             ctrl: ControlRegister::new(),
             mask: MaskRegister::new(),
@@ -95,14 +226,276 @@ impl Ppu {
             oam_addr: 0,
             scroll: ScrollRegister::new(),
             addr: AddrRegister::new(),
-            vram: [0; 2048],
+            w: false,
+            vram: [0; 4096],
             oam_data: [0; 64 * 4],
             palette_table: [0; 32],
             internal_data_buf: 0,
             cycles: 0,
             scanline: 0,
             nmi_interrupt: None,
+            disable_sprite_limit: false,
+            vblank_read_suppression: true,
+            mode: PpuMode::Fast,
+            frame: None,
+            rendered_x: 0,
+            vblank_started: false,
+            t_nametable: 0,
+            v_nametable: 0,
+        }
+    }
+
+    /// 描画方式
+    pub fn mode(&self) -> PpuMode {
+        self.mode
+    }
+
+    /// 描画方式を切り替える. Accurateに切り替えた場合は次のドットから描画する.
+    ///
+    /// # Parameters
+    /// * `mode` - 描画方式
+    pub fn set_mode(&mut self, mode: PpuMode) {
+        self.mode = mode;
+        self.frame = match mode {
+            PpuMode::Fast => None,
+            PpuMode::Accurate => self.frame.take().or_else(|| Some(Frame::new())),
+        };
+    }
+
+    /// 前回の呼び出し以降にVBLANKが始まったかどうかを返し、通知をクリアする.
+    pub fn take_vblank_started(&mut self) -> bool {
+        std::mem::take(&mut self.vblank_started)
+    }
+
+    /// Accurateモードでスキャンライン毎に描画したフレーム. Fastモードでは`None`
+    pub fn rendered_frame(&self) -> Option<&Frame> {
+        self.frame.as_ref()
+    }
+
+    /// リセットボタンによるリセット.
+    /// VRAM、パレット、OAMとスキャンラインのタイミングは保持し、
+    /// レジスタと内部ラッチのみ初期化する.
+    pub fn reset(&mut self) {
+        self.ctrl = ControlRegister::new();
+        self.mask = MaskRegister::new();
+        self.scroll = ScrollRegister::new();
+        self.w = false;
+        self.t_nametable = 0;
+        self.internal_data_buf = 0;
+        self.nmi_interrupt = None;
+    }
+
+    /// セーブステートに書き出す.
+    /// パターンテーブルはCHR-RAMの場合のみ含む. ミラーリングはカートリッジの状態のため含まない.
+    pub fn write_state(&self, w: &mut StateWriter) {
+        w.bool(self.chr.is_ram());
+        if self.chr.is_ram() {
+            for addr in 0..CHR_RAM_SIZE as u16 {
+                w.u8(self.chr.read_chr(addr));
+            }
+        }
+        w.bytes(&self.palette_table);
+        w.bytes(&self.vram);
+        w.bytes(&self.oam_data);
+        w.u16(self.addr.get());
+        w.u8(self.ctrl.bits());
+        w.u8(self.mask.bits());
+        w.u8(self.status.bits());
+        w.u8(self.scroll.scroll_x);
+        w.u8(self.scroll.scroll_y);
+        w.bool(self.w);
+        w.u8(self.oam_addr);
+        w.u8(self.internal_data_buf);
+        w.u16(self.scanline);
+        w.u64(self.cycles as u64);
+        match self.nmi_interrupt {
+            Some(nmi) => w.bytes(&[1, nmi]),
+            None => w.bytes(&[0, 0]),
+        }
+        w.bool(self.disable_sprite_limit);
+        w.bool(self.vblank_read_suppression);
+        w.u64(self.rendered_x as u64);
+        w.bool(self.vblank_started);
+        w.u8(self.t_nametable);
+        w.u8(self.v_nametable);
+        //Accurateモードは描画途中のフレームも書き出す
+        match &self.frame {
+            Some(frame) => {
+                w.u8(1);
+                w.bytes(&frame.data);
+            }
+            None => w.u8(0),
+        }
+    }
+
+    /// `write_state`で書き出した状態を読み込む.
+    /// CHR-ROMとミラーリングは現在の値を保持する.
+    pub fn read_state(&mut self, r: &mut StateReader) -> Result<(), NesError> {
+        if r.bool()? {
+            if !self.chr.is_ram() {
+                return Err(NesError::InvalidState("cartridge has no CHR-RAM"));
+            }
+            for (addr, &data) in r.bytes(CHR_RAM_SIZE)?.iter().enumerate() {
+                self.chr.write_chr(addr as u16, data);
+            }
+        }
+        self.palette_table.copy_from_slice(r.bytes(32)?);
+        self.vram.copy_from_slice(r.bytes(4096)?);
+        self.oam_data.copy_from_slice(r.bytes(256)?);
+        let [hi, lo] = r.u16()?.to_be_bytes();
+        self.addr.update(hi, false);
+        self.addr.update(lo, true);
+        self.ctrl = ControlRegister::from_bits_truncate(r.u8()?);
+        self.mask = MaskRegister::from_bits_truncate(r.u8()?);
+        self.status = StatusRegister::from_bits_truncate(r.u8()?);
+        self.scroll.scroll_x = r.u8()?;
+        self.scroll.scroll_y = r.u8()?;
+        self.w = r.bool()?;
+        self.oam_addr = r.u8()?;
+        self.internal_data_buf = r.u8()?;
+        self.scanline = r.u16()?;
+        self.cycles = r.u64()? as usize;
+        let nmi_pending = r.bool()?;
+        let nmi = r.u8()?;
+        self.nmi_interrupt = nmi_pending.then_some(nmi);
+        self.disable_sprite_limit = r.bool()?;
+        self.vblank_read_suppression = r.bool()?;
+        self.rendered_x = r.u64()? as usize;
+        self.vblank_started = r.bool()?;
+        self.t_nametable = r.u8()?;
+        self.v_nametable = r.u8()?;
+        if r.bool()? {
+            let mut frame = Frame::new();
+            frame.data.copy_from_slice(r.bytes(Frame::SIZE)?);
+            self.mode = PpuMode::Accurate;
+            self.frame = Some(frame);
+        } else {
+            self.set_mode(PpuMode::Fast);
+        }
+        Ok(())
+    }
+
+    /// 現在のスキャンライン
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    /// 描画に使うネームテーブル番号(0～3).
+    /// 0x2000への書き込みは、描画中は次のスキャンライン(垂直方向は次のフレーム)から反映される.
+    pub fn nametable_select(&self) -> u8 {
+        self.v_nametable
+    }
+
+    /// ネームテーブル(0～3)の先頭のVRAM上の位置. ミラーリングを解決する.
+    ///
+    /// # Parameters
+    /// * `index` - ネームテーブル番号(0～3)
+    pub fn nametable_base(&self, index: u8) -> usize {
+        self.mirror_vram_addr(0x2000 + (index as u16 & 0b11) * 0x400) as usize
+    }
+
+    /// 現在のスキャンライン上のPPUサイクル(ドット, 0～340)
+    pub fn dot(&self) -> usize {
+        self.cycles
+    }
+
+    /// ネームテーブルのマスの背景タイルの情報を返す.
+    ///
+    /// # Parameters
+    /// * `nametable` - ネームテーブル番号(0～3)
+    /// * `tile_x` - マスのX座標(0～31)
+    /// * `tile_y` - マスのY座標(0～29)
+    pub fn background_tile_at(&self, nametable: u8, tile_x: u8, tile_y: u8) -> TileInfo {
+        let nametable = self.nametable(nametable);
+        let (tile_x, tile_y) = (tile_x as usize, tile_y as usize);
+        let tile_index = nametable[tile_y * 32 + tile_x];
+
+        //属性テーブル(0x3c0～)の1byteが4x4マスを表し、2x2マス毎に2bitずつ割り当てられる
+        let attr_byte = nametable[0x3c0 + tile_y / 4 * 8 + tile_x / 4];
+        let shift = (tile_y % 4 / 2) * 4 + (tile_x % 4 / 2) * 2;
+        let palette = (attr_byte >> shift) & 0b11;
+
+        TileInfo {
+            tile_index,
+            palette,
+            pattern_addr: self.ctrl.bknd_pattern_addr() + tile_index as u16 * 16,
+        }
+    }
+
+    /// OAM(256バイト)
+    pub fn oam(&self) -> &[u8; 256] {
+        &self.oam_data
+    }
+
+    /// OAMの64個のスプライトをデコードして返す(デバッグ用)
+    pub fn sprites(&self) -> Vec<SpriteEntry> {
+        self.oam_data
+            .chunks_exact(4)
+            .enumerate()
+            .map(|(index, entry)| SpriteEntry {
+                index: index as u8,
+                y: entry[0],
+                tile: entry[1],
+                attributes: entry[2],
+                x: entry[3],
+                palette: entry[2] & 0b11,
+                behind_background: entry[2] >> 5 & 1 == 1,
+                flip_horizontal: entry[2] >> 6 & 1 == 1,
+                flip_vertical: entry[2] >> 7 & 1 == 1,
+            })
+            .collect()
+    }
+
+    /// OAMのスプライト1個を描画したフレームを返す(スプライトビューア用).
+    /// 左上に8x8(8x16モードでは8x16)で描画する.
+    ///
+    /// # Parameters
+    /// * `index` - スプライト番号(0～63)
+    pub fn render_sprite(&self, index: u8) -> Frame {
+        render::render_sprite(self, index)
+    }
+
+    /// ミラーリングを変更する. マッパーがミラーリングを切り替えた時に呼ばれ、
+    /// 以降のネームテーブルへのアクセスに即座に反映される.
+    ///
+    /// # Parameters
+    /// * `mirroring` - ミラーリング
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.mirroring = mirroring;
+    }
+
+    /// パターンテーブルの読み出し
+    ///
+    /// # Parameters
+    /// * `addr` - アドレス(0x0000～0x1fff)
+    pub fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.read_chr(addr)
+    }
+
+    /// パターンテーブルのタイル(16byte)
+    ///
+    /// # Parameters
+    /// * `addr` - タイルの先頭アドレス
+    pub fn pattern_tile(&self, addr: u16) -> [u8; 16] {
+        let mut tile = [0; 16];
+        for (i, value) in tile.iter_mut().enumerate() {
+            *value = self.read_chr(addr + i as u16);
         }
+        tile
+    }
+
+    /// パレットRAM(0x3f00～0x3f1f)の内容
+    pub fn palette_ram(&self) -> [u8; 32] {
+        self.palette_table
+    }
+
+    /// ネームテーブル(1KiB)の内容. ミラーリングを解決したVRAMを返す.
+    ///
+    /// # Parameters
+    /// * `index` - ネームテーブル番号(0～3)
+    pub fn nametable(&self, index: u8) -> Vec<u8> {
+        let start = self.nametable_base(index);
+        self.vram[start..start + 0x400].to_vec()
     }
 
     fn increment_vram_addr(&mut self) {
@@ -119,14 +512,27 @@ impl Ppu {
         //内部的には 341*262.
         //1 PPU サイクルで 1 dot 処理される.
         //341*262 = 89342 PPU サイクルが 1 フレーム
+        let start = self.cycles;
         self.cycles += cycles as usize;
+        self.render_dots();
+        self.copy_nametable_select(start);
         if self.cycles >= 341 {
             self.cycles -= 341;
+            self.rendered_x = 0;
             self.scanline += 1;
 
+            //スプライトの評価は描画が有効な場合のみ行われる
+            if self.scanline < 240
+                && self.mask.is_rendering_enabled()
+                && self.sprites_on_scanline(self.scanline) > 8
+            {
+                self.status.set_sprite_overflow(true);
+            }
+
             //line 241でVBLANKフラグ=trueになり
             //NMI 割り込みが発生
             if self.scanline == 241 {
+                self.vblank_started = true;
                 self.status.set_vblank_status(true);
                 self.status.set_sprite_zero_hit(false);
                 if self.ctrl.generate_vblank_nmi() {
@@ -139,6 +545,7 @@ impl Ppu {
                 self.scanline = 0;
                 self.nmi_interrupt = None;
                 self.status.set_sprite_zero_hit(false);
+                self.status.set_sprite_overflow(false);
                 self.status.reset_vblank_status();
                 return true;
             }
@@ -146,6 +553,66 @@ impl Ppu {
         false
     }
 
+    /// Accurateモードで、可視スキャンラインの現在のドットまでを描画する.
+    /// ドット1～256がX座標0～255に対応する(ドット0はアイドル).
+    fn render_dots(&mut self) {
+        if self.scanline >= 240 {
+            return;
+        }
+        //処理済みのドットは0～cycles-1. 最後のドットcycles-1がX座標cycles-2
+        let end = self.cycles.saturating_sub(1).min(Frame::WIDTH);
+        if end <= self.rendered_x {
+            return;
+        }
+        if let Some(mut frame) = self.frame.take() {
+            let line = self.scanline as usize;
+            render::render_pixels(self, &mut frame, line, self.rendered_x..end);
+            self.frame = Some(frame);
+        }
+        self.rendered_x = end;
+    }
+
+    /// 描画中に`t`のネームテーブル選択を`v`にコピーする.
+    ///
+    /// # Parameters
+    /// * `start` - このtickを始めたドット
+    fn copy_nametable_select(&mut self, start: usize) {
+        if !self.is_rendering() {
+            return;
+        }
+        //水平方向: ドット257
+        if start < 257 && 257 <= self.cycles {
+            self.v_nametable = (self.v_nametable & 0b10) | (self.t_nametable & 0b01);
+        }
+        //垂直方向: プリレンダーラインのドット280～304
+        if self.scanline == 261 && start <= 304 && 280 <= self.cycles {
+            self.v_nametable = (self.v_nametable & 0b01) | (self.t_nametable & 0b10);
+        }
+    }
+
+    /// 描画中(描画が有効で、可視スキャンラインかプリレンダーライン)かどうか.
+    /// OAMへの書き込みの無視等、描画中にだけ起きる動作の判定に使う.
+    pub fn is_rendering(&self) -> bool {
+        self.mask.is_rendering_enabled() && (self.scanline < 240 || self.scanline == 261)
+    }
+
+    /// VBLANKフラグが立ったのと同じCPUサイクル(3ドット以内)かどうか
+    fn is_vblank_start(&self) -> bool {
+        self.scanline == 241 && self.cycles < 3
+    }
+
+    /// スキャンライン上にあるスプライトの数(8x16モードでは16ライン分の高さで判定する)
+    fn sprites_on_scanline(&self, scanline: u16) -> usize {
+        let height = self.ctrl.sprite_size() as u16;
+        self.oam_data
+            .chunks(4)
+            .filter(|sprite| {
+                let y = sprite[0] as u16;
+                y <= scanline && scanline < y + height
+            })
+            .count()
+    }
+
     // fn poll_nmi_interrupt(&mut self) -> Option<u8> {
     //     self.nmi_interrupt.take()
     // }
@@ -157,6 +624,10 @@ impl Ppu {
     // Vertical:
     //   [ A ] [ B ]
     //   [ a ] [ b ]
+
+    // Four screen:
+    //   [ A ] [ B ]
+    //   [ C ] [ D ]
     pub fn mirror_vram_addr(&self, addr: u16) -> u16 {
         let mirrored_vram = addr & 0b10111111111111; // mirror down 0x3000-0x3eff to 0x2000 - 0x2eff
         let vram_index = mirrored_vram - 0x2000; // to vram vector
@@ -171,10 +642,22 @@ impl Ppu {
     }
 }
 
+/// パレットのアドレスからpalette_tableのインデックスを求める.
+/// 0x3f20~0x3fffは0x3f00~0x3f1fのミラー.
+fn palette_index(addr: u16) -> usize {
+    let index = (addr & 0x1f) as usize;
+    match index {
+        //Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
+        0x10 | 0x14 | 0x18 | 0x1c => index - 0x10,
+        _ => index,
+    }
+}
+
 impl TPpu for Ppu {
     fn write_to_ctrl(&mut self, value: u8) {
         let _before_nmi_status = self.ctrl.generate_vblank_nmi();
         self.ctrl.update(value);
+        self.t_nametable = value & 0b11;
     }
 
     fn write_to_mask(&mut self, value: u8) {
@@ -182,10 +665,13 @@ impl TPpu for Ppu {
     }
 
     fn read_status(&mut self) -> u8 {
+        if self.vblank_read_suppression && self.is_vblank_start() {
+            self.status.reset_vblank_status();
+            self.nmi_interrupt = None;
+        }
         let data = self.status.snapshot();
         self.status.reset_vblank_status();
-        self.addr.reset_latch();
-        self.scroll.reset_latch();
+        self.w = false;
         data
     }
 
@@ -193,39 +679,60 @@ impl TPpu for Ppu {
         self.oam_addr = value;
     }
 
+    /// OAMDATA(0x2004)への書き込み.
+    /// 描画中(可視スキャンラインとプリレンダーライン)は書き込まれず、OAMADDRが4進む.
     fn write_to_oam_data(&mut self, value: u8) {
+        if self.is_rendering() {
+            self.oam_addr = self.oam_addr.wrapping_add(4);
+            return;
+        }
         self.oam_data[self.oam_addr as usize] = value;
         self.oam_addr = self.oam_addr.wrapping_add(1);
     }
 
+    /// OAMDATA(0x2004)の読み出し.
+    /// スプライト属性(各スプライトの3byte目)のbit2～4は実装されていないため0として読める.
     fn read_oam_data(&self) -> u8 {
-        self.oam_data[self.oam_addr as usize]
+        let data = self.oam_data[self.oam_addr as usize];
+        if self.oam_addr % 4 == 2 {
+            data & OAM_ATTRIBUTE_MASK
+        } else {
+            data
+        }
     }
 
     fn write_to_scroll(&mut self, value: u8) {
-        self.scroll.write(value);
+        self.scroll.write(value, self.w);
+        self.w = !self.w;
     }
 
     fn write_to_ppu_addr(&mut self, value: u8) {
-        self.addr.update(value);
+        self.addr.update(value, self.w);
+        if self.w {
+            //2回目の書き込みで`t`が`v`にコピーされる
+            self.v_nametable = self.t_nametable;
+        } else {
+            self.t_nametable = (value >> 2) & 0b11;
+        }
+        self.w = !self.w;
     }
 
     fn write_to_data(&mut self, value: u8) {
         let addr = self.addr.get();
         match addr {
-            0..=0x1fff => println!("attempt to write to chr rom space {}", addr),
-            0x2000..=0x2fff => {
-                self.vram[self.mirror_vram_addr(addr) as usize] = value;
+            0..=0x1fff => {
+                if self.chr.is_ram() {
+                    self.chr.write_chr(addr, value);
+                } else {
+                    log::trace!("ignored write to CHR-ROM ${:04X}", addr);
+                }
             }
-            0x3000..=0x3eff => unimplemented!("addr {} shouldn't be used in reallity", addr),
-
-            //Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                let add_mirror = addr - 0x10;
-                self.palette_table[(add_mirror - 0x3f00) as usize] = value;
+            //0x3000~0x3effは0x2000~0x2effのミラー
+            0x2000..=0x3eff => {
+                self.vram[self.mirror_vram_addr(addr) as usize] = value;
             }
             0x3f00..=0x3fff => {
-                self.palette_table[(addr - 0x3f00) as usize] = value;
+                self.palette_table[palette_index(addr)] = value;
             }
             _ => panic!("unexpected access to mirrored space {}", addr),
         }
@@ -240,31 +747,621 @@ impl TPpu for Ppu {
         match addr {
             0..=0x1fff => {
                 let result = self.internal_data_buf;
-                self.internal_data_buf = self.char_data[addr as usize];
+                self.internal_data_buf = self.read_chr(addr);
                 result
             }
-            0x2000..=0x2fff => {
+            //0x3000~0x3effは0x2000~0x2effのミラー
+            0x2000..=0x3eff => {
                 let result = self.internal_data_buf;
                 self.internal_data_buf = self.vram[self.mirror_vram_addr(addr) as usize];
                 result
             }
-            0x3000..=0x3eff => unimplemented!("addr {} shouldn't be used in reallity", addr),
 
-            //Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                let add_mirror = addr - 0x10;
-                self.palette_table[(add_mirror - 0x3f00) as usize]
+            //パレットは即座に返すが、バッファにはパレットの下に隠れた
+            //ネームテーブル(0x2f00~0x2fff のミラー)のデータが読み込まれる
+            0x3f00..=0x3fff => {
+                self.internal_data_buf = self.vram[self.mirror_vram_addr(addr) as usize];
+                self.palette_table[palette_index(addr)]
             }
-
-            0x3f00..=0x3fff => self.palette_table[(addr - 0x3f00) as usize],
             _ => panic!("unexpected access to mirrored space {}", addr),
         }
     }
 
+    /// OAM DMA(0x4014). 実機と同様にOAMADDRから書き込みを始め、
+    /// 256バイトのOAMの末尾で先頭に折り返す. 256バイト書き込むので終了後のOAMADDRは元の値に戻る.
     fn write_oam_dma(&mut self, data: &[u8; 256]) {
         for x in data.iter() {
-            self.oam_data[self.oam_addr as usize] = *x;
-            self.oam_addr = self.oam_addr.wrapping_add(1);
+            self.write_oam_dma_byte(*x);
         }
     }
+
+    /// OAM DMAで転送された1byteをOAMADDRの位置に書き込む
+    fn write_oam_dma_byte(&mut self, value: u8) {
+        self.oam_data[self.oam_addr as usize] = value;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+mod ppu_test {
+    use super::*;
+    use crate::rom::cartridge::RamCartridge;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn read_palette_fills_buffer_with_nametable() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+
+        //0x2f05 (パレット0x3f05の下のネームテーブル)
+        ppu.write_to_ppu_addr(0x2f);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.write_to_data(0x66);
+
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.write_to_data(0x12);
+
+        //パレットは即座に返る
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x05);
+        assert_eq!(ppu.read_data(), 0x12);
+
+        //次の読み出しはバッファに入ったネームテーブルの値
+        ppu.write_to_ppu_addr(0x20);
+        ppu.write_to_ppu_addr(0x00);
+        assert_eq!(ppu.read_data(), 0x66);
+    }
+
+    fn write_vram(ppu: &mut Ppu, addr: u16, data: &[u8]) {
+        ppu.write_to_ppu_addr((addr >> 8) as u8);
+        ppu.write_to_ppu_addr(addr as u8);
+        for &value in data {
+            ppu.write_to_data(value);
+        }
+    }
+
+    #[test]
+    fn accurate_mode_draws_dot_n_at_x_n_minus_1() {
+        let mut ppu =
+            Ppu::new_ppu_with_mode(vec![0; 0x2000], Mirroring::HORIZONTAL, PpuMode::Accurate);
+        //ドット0はアイドル
+        ppu.tick(1);
+        assert_eq!(ppu.rendered_x, 0);
+        //ドット1～2でX座標0～1を描画する
+        ppu.tick(2);
+        assert_eq!(ppu.rendered_x, 2);
+        ppu.tick(255);
+        assert_eq!(ppu.rendered_x, 256);
+    }
+
+    #[test]
+    fn write_pattern_table() {
+        //CHR-ROMへの書き込みは無視する
+        let mut ppu = Ppu::new_ppu(vec![0x55; 0x2000], Mirroring::HORIZONTAL);
+        write_vram(&mut ppu, 0x1ffe, &[0x11, 0x22]);
+        assert_eq!(ppu.read_chr(0x1ffe), 0x55);
+        assert_eq!(ppu.read_chr(0x1fff), 0x55);
+        assert_eq!(ppu.addr.get(), 0x2000);
+
+        //CHR-RAMには書き込める
+        let mut ppu = Ppu::from_cartridge(&RamCartridge::new(&[], 0x2000));
+        write_vram(&mut ppu, 0x1ffe, &[0x11, 0x22]);
+        assert_eq!(ppu.read_chr(0x1ffe), 0x11);
+        assert_eq!(ppu.read_chr(0x1fff), 0x22);
+        write_vram(&mut ppu, 0x0000, &[0x33]);
+        assert_eq!(ppu.pattern_tile(0x0000)[0], 0x33);
+    }
+
+    #[test]
+    fn read_nametable_sequence_lags_one_read() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+        write_vram(&mut ppu, 0x2100, &[0x11, 0x22, 0x33]);
+
+        ppu.write_to_ppu_addr(0x21);
+        ppu.write_to_ppu_addr(0x00);
+        //最初の読み出しはダミー(以前のバッファ)
+        assert_eq!(ppu.read_data(), 0x00);
+        assert_eq!(ppu.read_data(), 0x11);
+        assert_eq!(ppu.read_data(), 0x22);
+        assert_eq!(ppu.read_data(), 0x33);
+        assert_eq!(ppu.addr.get(), 0x2104);
+    }
+
+    #[test]
+    fn read_nametable_column_lags_one_read() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+        write_vram(&mut ppu, 0x2000, &[0x11]);
+        write_vram(&mut ppu, 0x2020, &[0x22]);
+        write_vram(&mut ppu, 0x2040, &[0x33]);
+
+        //32byte単位でインクリメント
+        ppu.write_to_ctrl(0b100);
+        ppu.write_to_ppu_addr(0x20);
+        ppu.write_to_ppu_addr(0x00);
+        ppu.read_data();
+        assert_eq!(ppu.read_data(), 0x11);
+        assert_eq!(ppu.read_data(), 0x22);
+        assert_eq!(ppu.read_data(), 0x33);
+        assert_eq!(ppu.addr.get(), 0x2080);
+    }
+
+    #[test]
+    fn read_buffer_survives_address_change() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+        write_vram(&mut ppu, 0x2100, &[0x11]);
+        write_vram(&mut ppu, 0x2200, &[0x22]);
+
+        ppu.write_to_ppu_addr(0x21);
+        ppu.write_to_ppu_addr(0x00);
+        ppu.read_data();
+        //アドレスを変えても、0x2002を読んでもバッファは残る
+        ppu.write_to_ppu_addr(0x22);
+        ppu.write_to_ppu_addr(0x00);
+        ppu.read_status();
+        assert_eq!(ppu.read_data(), 0x11);
+        assert_eq!(ppu.read_data(), 0x22);
+
+        //リセットでクリアされる
+        ppu.reset();
+        ppu.write_to_ppu_addr(0x21);
+        ppu.write_to_ppu_addr(0x00);
+        assert_eq!(ppu.read_data(), 0x00);
+    }
+
+    #[test]
+    fn ppu_addr_above_0x3fff_wraps() {
+        let mut ppu = Ppu::new_ppu(vec![0x11; 0x2000], Mirroring::HORIZONTAL);
+        //0x6305 → 0x2305
+        ppu.write_to_ppu_addr(0x63);
+        ppu.write_to_ppu_addr(0x05);
+        assert_eq!(ppu.addr.get(), 0x2305);
+        ppu.write_to_data(0x66);
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.read_data();
+        assert_eq!(ppu.read_data(), 0x66);
+
+        //0xFFFF → 0x3FFF、インクリメントで0x0000
+        ppu.write_to_ppu_addr(0xff);
+        ppu.write_to_ppu_addr(0xff);
+        assert_eq!(ppu.addr.get(), 0x3fff);
+        ppu.read_data();
+        assert_eq!(ppu.addr.get(), 0x0000);
+        assert_eq!(ppu.read_data(), 0x00);
+        assert_eq!(ppu.read_data(), 0x11);
+    }
+
+    #[test]
+    fn read_nametable_mirror_above_0x3000() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+        write_vram(&mut ppu, 0x2305, &[0x66]);
+
+        ppu.write_to_ppu_addr(0x33);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.read_data();
+        assert_eq!(ppu.read_data(), 0x66);
+    }
+
+    #[test]
+    fn read_palette_has_no_delay() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+        write_vram(&mut ppu, 0x3f00, &[0x0f, 0x16, 0x27]);
+
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x01);
+        assert_eq!(ppu.read_data(), 0x16);
+        assert_eq!(ppu.read_data(), 0x27);
+
+        //0x3f10は0x3f00、0x3f21は0x3f01のミラー
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x10);
+        assert_eq!(ppu.read_data(), 0x0f);
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x21);
+        assert_eq!(ppu.read_data(), 0x16);
+    }
+
+    #[test]
+    fn background_tile_at() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::VERTICAL);
+        //ネームテーブル1の(x=6, y=6)
+        write_vram(&mut ppu, 0x2400 + 6 * 32 + 6, &[0x42]);
+        //(6, 6)は属性テーブルの(1, 1)のbyteの右下(bit6-7)
+        write_vram(&mut ppu, 0x2400 + 0x3c0 + 8 + 1, &[0b1000_0000]);
+        ppu.write_to_ctrl(0b0001_0000);
+
+        assert_eq!(
+            ppu.background_tile_at(1, 6, 6),
+            TileInfo {
+                tile_index: 0x42,
+                palette: 2,
+                pattern_addr: 0x1000 + 0x42 * 16,
+            }
+        );
+        //左上(4, 4)は同じ属性byteのbit0-1
+        assert_eq!(ppu.background_tile_at(1, 4, 4).palette, 0);
+        assert_eq!(ppu.background_tile_at(0, 6, 6).tile_index, 0);
+    }
+
+    /// 0x8000への書き込みで8KiBのCHRバンクを切り替えるカートリッジ
+    #[derive(Debug)]
+    struct BankedChr {
+        banks: Vec<u8>,
+        bank: usize,
+    }
+
+    impl ChrMemory for Rc<RefCell<BankedChr>> {
+        fn read_chr(&self, addr: u16) -> u8 {
+            let chr = self.borrow();
+            chr.banks[chr.bank * 0x2000 + addr as usize]
+        }
+    }
+
+    struct BankedCartridge {
+        chr: Rc<RefCell<BankedChr>>,
+    }
+
+    impl Cartridge for BankedCartridge {
+        fn read_prg(&self, _addr: u16) -> u8 {
+            0
+        }
+
+        fn write_prg(&mut self, _addr: u16, data: u8) {
+            self.chr.borrow_mut().bank = data as usize & 1;
+        }
+
+        fn char_data(&self) -> Vec<u8> {
+            let chr = self.chr.borrow();
+            chr.banks[chr.bank * 0x2000..(chr.bank + 1) * 0x2000].to_vec()
+        }
+
+        fn chr_memory(&self) -> Box<dyn ChrMemory> {
+            Box::new(self.chr.clone())
+        }
+
+        fn mirroring(&self) -> Mirroring {
+            Mirroring::VERTICAL
+        }
+    }
+
+    #[test]
+    fn from_cartridge_reads_selected_chr_bank() {
+        let mut banks = vec![0x11; 0x4000];
+        banks[0x2000..].fill(0x22);
+        let mut cartridge = BankedCartridge {
+            chr: Rc::new(RefCell::new(BankedChr { banks, bank: 0 })),
+        };
+        let mut ppu = Ppu::from_cartridge(&cartridge);
+        assert_eq!(ppu.mirroring, Mirroring::VERTICAL);
+        assert_eq!(ppu.pattern_tile(0x0010), [0x11; 16]);
+
+        cartridge.write_prg(0x8000, 1);
+        assert_eq!(ppu.pattern_tile(0x0010), [0x22; 16]);
+
+        //PPUDATA経由の読み出しも切り替えたバンクを参照する
+        ppu.write_to_ppu_addr(0x00);
+        ppu.write_to_ppu_addr(0x10);
+        ppu.read_data();
+        assert_eq!(ppu.read_data(), 0x22);
+    }
+
+    #[test]
+    fn mirror_vram_addr_by_mirroring() {
+        //同じアドレス(3つ目のネームテーブル)がミラーリング毎に別の領域になる
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+        assert_eq!(ppu.mirror_vram_addr(0x2810), 0x0410);
+        ppu.set_mirroring(Mirroring::VERTICAL);
+        assert_eq!(ppu.mirror_vram_addr(0x2810), 0x0010);
+        ppu.set_mirroring(Mirroring::FOUR_SCREEN);
+        assert_eq!(ppu.mirror_vram_addr(0x2810), 0x0810);
+        //0x3000～0x3effは0x2000～0x2effのミラー
+        assert_eq!(ppu.mirror_vram_addr(0x3c10), 0x0c10);
+    }
+
+    #[test]
+    fn set_mirroring_switches_vram_addressing() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::VERTICAL);
+        write_vram(&mut ppu, 0x2000, &[0x11]);
+        write_vram(&mut ppu, 0x2400, &[0x22]);
+        //垂直ミラーリング: 0x2800は0x2000と同じ
+        assert_eq!(ppu.nametable(2)[0], 0x11);
+
+        ppu.set_mirroring(Mirroring::HORIZONTAL);
+        //水平ミラーリング: 0x2400は0x2000と同じ、0x2800は2つ目の領域
+        assert_eq!(ppu.nametable(1)[0], 0x11);
+        assert_eq!(ppu.nametable(2)[0], 0x22);
+    }
+
+    #[test]
+    fn palette_ram_and_nametable() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::VERTICAL);
+
+        ppu.write_to_ppu_addr(0x24);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.write_to_data(0x66);
+        ppu.write_to_data(0x77);
+
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x01);
+        ppu.write_to_data(0x12);
+
+        let nametable = ppu.nametable(1);
+        assert_eq!(nametable.len(), 0x400);
+        assert_eq!(nametable[5..7], [0x66, 0x77]);
+        //垂直ミラーリングではネームテーブル3は1と同じ
+        assert_eq!(ppu.nametable(3), nametable);
+        assert_eq!(ppu.nametable(0)[5], 0);
+
+        assert_eq!(ppu.palette_ram()[1], 0x12);
+    }
+
+    #[test]
+    fn oam_dma_wraps_from_oam_addr() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+        let mut data = [0u8; 256];
+        for (i, value) in data.iter_mut().enumerate() {
+            *value = i as u8;
+        }
+
+        ppu.write_to_oam_addr(0x10);
+        ppu.write_oam_dma(&data);
+
+        assert_eq!(ppu.oam_addr, 0x10);
+        assert_eq!(ppu.oam_data[0x10], 0x00);
+        assert_eq!(ppu.oam_data[0xff], 0xef);
+        assert_eq!(ppu.oam_data[0x00], 0xf0);
+        assert_eq!(ppu.oam_data[0x0f], 0xff);
+        assert_eq!(ppu.read_oam_data(), 0x00);
+    }
+
+    #[test]
+    fn oam_attribute_unused_bits_read_as_zero() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+        ppu.write_to_oam_addr(0x04);
+        for _ in 0..4 {
+            ppu.write_to_oam_data(0xff);
+        }
+
+        let read = |ppu: &mut Ppu, addr: u8| {
+            ppu.write_to_oam_addr(addr);
+            ppu.read_oam_data()
+        };
+        assert_eq!(read(&mut ppu, 0x04), 0xff);
+        assert_eq!(read(&mut ppu, 0x05), 0xff);
+        assert_eq!(read(&mut ppu, 0x06), 0xe3);
+        assert_eq!(read(&mut ppu, 0x07), 0xff);
+    }
+
+    #[test]
+    fn is_rendering_by_mask_and_scanline() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+        assert!(!ppu.mask.is_rendering_enabled());
+        assert!(!ppu.is_rendering());
+        for mask in [0b0000_1000, 0b0001_0000, 0b0001_1000] {
+            ppu.write_to_mask(mask);
+            assert!(ppu.mask.is_rendering_enabled());
+            assert!(ppu.is_rendering());
+        }
+        //左端8ドットやグレースケールのビットだけでは描画しない
+        ppu.write_to_mask(0b0000_0111);
+        assert!(!ppu.is_rendering());
+
+        ppu.write_to_mask(0b0000_1000);
+        while ppu.scanline() < 240 {
+            ppu.tick(1);
+        }
+        assert!(!ppu.is_rendering());
+        while ppu.scanline() < 261 {
+            ppu.tick(1);
+        }
+        assert!(ppu.is_rendering());
+    }
+
+    #[test]
+    fn oam_data_write_ignored_while_rendering() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+        ppu.write_to_mask(0b0001_1000);
+        while ppu.scanline() < 100 {
+            ppu.tick(1);
+        }
+        ppu.write_to_oam_addr(0x10);
+        ppu.write_to_oam_data(0x66);
+        ppu.write_to_oam_data(0x77);
+        assert_eq!(ppu.oam_data[0x10], 0);
+        assert_eq!(ppu.oam_data[0x14], 0);
+        assert_eq!(ppu.oam_addr, 0x18);
+
+        //VBLANK中は書き込める
+        while ppu.scanline() < 241 {
+            ppu.tick(1);
+        }
+        ppu.write_to_oam_addr(0x10);
+        ppu.write_to_oam_data(0x66);
+        assert_eq!(ppu.oam_data[0x10], 0x66);
+        assert_eq!(ppu.oam_addr, 0x11);
+    }
+
+    #[test]
+    fn nametable_select_copied_at_scanline_boundary() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::VERTICAL);
+        ppu.write_to_mask(0b0001_1000);
+        while ppu.scanline() < 100 {
+            ppu.tick(1);
+        }
+        while ppu.dot() < 100 {
+            ppu.tick(1);
+        }
+        ppu.write_to_ctrl(0b11);
+        //現在のスキャンラインには反映されない
+        assert_eq!(ppu.nametable_select(), 0);
+        while ppu.dot() < 257 {
+            ppu.tick(1);
+        }
+        //水平方向はドット257から
+        assert_eq!(ppu.nametable_select(), 0b01);
+        assert_eq!(ppu.scanline(), 100);
+
+        //垂直方向は次のフレームのプリレンダーラインから
+        while ppu.scanline() != 0 {
+            ppu.tick(1);
+        }
+        assert_eq!(ppu.nametable_select(), 0b11);
+    }
+
+    #[test]
+    fn nametable_select_from_ppu_addr() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::VERTICAL);
+        ppu.write_to_ppu_addr(0x24);
+        assert_eq!(ppu.nametable_select(), 0);
+        ppu.write_to_ppu_addr(0x00);
+        assert_eq!(ppu.nametable_select(), 1);
+    }
+
+    #[test]
+    fn oam_data_write_wraps() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+        ppu.write_to_oam_addr(0xff);
+        ppu.write_to_oam_data(0x11);
+        ppu.write_to_oam_data(0x22);
+
+        assert_eq!(ppu.oam_data[0xff], 0x11);
+        assert_eq!(ppu.oam_data[0x00], 0x22);
+        assert_eq!(ppu.oam_addr, 0x01);
+    }
+
+    fn ppu_at_vblank_start(vblank_read_suppression: bool) -> Ppu {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+        ppu.vblank_read_suppression = vblank_read_suppression;
+        ppu.write_to_ctrl(0b1000_0000);
+        while ppu.scanline() < 241 {
+            ppu.tick(1);
+        }
+        ppu
+    }
+
+    #[test]
+    fn vblank_read_suppression() {
+        let mut ppu = ppu_at_vblank_start(true);
+        assert_eq!(ppu.read_status() & 0b1000_0000, 0);
+        assert_eq!(ppu.read_status() & 0b1000_0000, 0);
+        assert_eq!(ppu.nmi_interrupt, None);
+    }
+
+    #[test]
+    fn vblank_read_suppression_disabled() {
+        let mut ppu = ppu_at_vblank_start(false);
+        assert_eq!(ppu.read_status() & 0b1000_0000, 0b1000_0000);
+        assert!(ppu.nmi_interrupt.is_some());
+    }
+
+    #[test]
+    fn vblank_read_after_race_window() {
+        let mut ppu = ppu_at_vblank_start(true);
+        ppu.tick(3);
+        assert_eq!(ppu.read_status() & 0b1000_0000, 0b1000_0000);
+    }
+
+    #[test]
+    fn read_status_resets_addr_latch_mid_write() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+
+        //上位バイトだけ書き込んだ状態で0x2002を読む
+        ppu.write_to_ppu_addr(0x21);
+        ppu.read_status();
+
+        //ラッチがリセットされているので、次の書き込みは再び上位バイトになる
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+        assert_eq!(ppu.addr.get(), 0x2305);
+
+        ppu.write_to_data(0x77);
+        assert_eq!(ppu.vram[ppu.mirror_vram_addr(0x2305) as usize], 0x77);
+    }
+
+    #[test]
+    fn read_status_resets_scroll_latch_mid_write() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+
+        ppu.write_to_scroll(0x10);
+        ppu.read_status();
+
+        ppu.write_to_scroll(0x20);
+        ppu.write_to_scroll(0x30);
+        assert_eq!(ppu.scroll.scroll_x, 0x20);
+        assert_eq!(ppu.scroll.scroll_y, 0x30);
+    }
+
+    #[test]
+    fn scroll_and_addr_share_write_latch() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+
+        //0x2005の1回目の後の0x2006は2回目(下位バイト)の書き込みになる
+        ppu.write_to_scroll(0x10);
+        ppu.write_to_ppu_addr(0x05);
+        assert_eq!(ppu.scroll.scroll_x, 0x10);
+        assert_eq!(ppu.addr.get(), 0x0005);
+
+        //0x2006の1回目の後に0x2002を読むと、次の0x2005は再び1回目になる
+        ppu.write_to_ppu_addr(0x21);
+        ppu.read_status();
+        ppu.write_to_scroll(0x20);
+        assert_eq!(ppu.scroll.scroll_x, 0x20);
+        assert_eq!(ppu.scroll.scroll_y, 0x00);
+
+        //0x2005の1回目の後なので、0x2006は下位バイトになる
+        ppu.write_to_ppu_addr(0x07);
+        assert_eq!(ppu.addr.get(), 0x2107);
+    }
+
+    #[test]
+    fn sprite_overflow_ignores_sprite_limit_hack() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+        ppu.disable_sprite_limit = true;
+        ppu.write_to_mask(0b0001_1000);
+        for sprite in 0..64 {
+            ppu.oam_data[sprite * 4] = if sprite < 9 { 100 } else { 0xf0 };
+        }
+
+        while ppu.scanline() < 100 {
+            ppu.tick(1);
+        }
+        assert!(ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
+    }
+
+    #[test]
+    fn sprite_overflow_requires_rendering() {
+        let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+        for sprite in 0..64 {
+            ppu.oam_data[sprite * 4] = if sprite < 9 { 100 } else { 0xf0 };
+        }
+
+        while ppu.scanline() < 100 {
+            ppu.tick(1);
+        }
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
+    }
+
+    #[test]
+    fn sprite_overflow_8x16() {
+        //y=92に5個、y=100に4個. 8x16ではライン100～107で9個が重なる
+        let overflow = |sprite_size: bool| {
+            let mut ppu = Ppu::new_ppu(vec![0; 0x2000], Mirroring::HORIZONTAL);
+            ppu.ctrl.set(ControlRegister::SPRITE_SIZE, sprite_size);
+            ppu.write_to_mask(0b0001_1000);
+            for sprite in 0..64 {
+                ppu.oam_data[sprite * 4] = match sprite {
+                    0..=4 => 92,
+                    5..=8 => 100,
+                    _ => 0xf0,
+                };
+            }
+            while ppu.scanline() < 110 {
+                ppu.tick(1);
+            }
+            ppu.status.contains(StatusRegister::SPRITE_OVERFLOW)
+        };
+        assert!(!overflow(false));
+        assert!(overflow(true));
+    }
 }
@@ -1,8 +1,9 @@
-#[derive(Debug)]
+/// Scroll Register Struct (0x2005)
+/// 書き込みの順番(1回目: X, 2回目: Y)は0x2006と共有するPPUの`w`ラッチで決まる.
+#[derive(Debug, Clone)]
 pub struct ScrollRegister {
     pub scroll_x: u8,
     pub scroll_y: u8,
-    pub latch: bool,
 }
 
 impl ScrollRegister {
@@ -10,20 +11,23 @@ impl ScrollRegister {
         ScrollRegister {
             scroll_x: 0,
             scroll_y: 0,
-            latch: false,
         }
     }
 
-    pub fn write(&mut self, data: u8) {
-        if !self.latch {
+    /// # Parameters
+    /// * `data` - 書き込む値
+    /// * `second_write` - `w`ラッチが2回目の書き込みを示しているかどうか
+    pub fn write(&mut self, data: u8, second_write: bool) {
+        if !second_write {
             self.scroll_x = data;
         } else {
             self.scroll_y = data;
         }
-        self.latch = !self.latch;
     }
+}
 
-    pub fn reset_latch(&mut self) {
-        self.latch = false;
+impl Default for ScrollRegister {
+    fn default() -> Self {
+        Self::new()
     }
 }
@@ -45,9 +45,9 @@ impl StatusRegister {
         self.set(StatusRegister::SPRITE_ZERO_HIT, status);
     }
 
-    // pub fn set_sprite_overflow(&mut self, status: bool) {
-    //     self.set(StatusRegister::SPRITE_OVERFLOW, status);
-    // }
+    pub fn set_sprite_overflow(&mut self, status: bool) {
+        self.set(StatusRegister::SPRITE_OVERFLOW, status);
+    }
 
     pub fn reset_vblank_status(&mut self) {
         self.remove(StatusRegister::VBLANK_STARTED);
@@ -61,3 +61,9 @@ impl StatusRegister {
         self.bits
     }
 }
+
+impl Default for StatusRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,12 +1,29 @@
+pub mod filter;
 pub mod frame;
 pub mod palette;
 
 use crate::ppu::ppu::Ppu;
-use frame::Frame;
+use filter::FrameFilter;
+use frame::{put_pixel, Frame, ScaleMode};
+use palette::Palette;
+use std::ops::Range;
 
-fn bg_pallette(ppu: &Ppu, tile_column: usize, tile_row: usize) -> [u8; 4] {
+/// 背景のタイルに使うパレットを属性テーブルから求める.
+/// 0番目は背景色(パレットテーブルの0x00)になる.
+///
+/// # Parameters
+/// * `ppu` - PPU
+/// * `nametable` - ネームテーブルの先頭のVRAM上の位置(`Ppu::nametable_base`)
+/// * `tile_column` - タイルの列(0～31)
+/// * `tile_row` - タイルの行(0～29)
+pub fn palette_for_tile(
+    ppu: &Ppu,
+    nametable: usize,
+    tile_column: usize,
+    tile_row: usize,
+) -> Palette {
     let attr_table_idx = tile_row / 4 * 8 + tile_column / 4;
-    let attr_byte = ppu.vram[0x3c0 + attr_table_idx];
+    let attr_byte = ppu.vram[nametable + 0x3c0 + attr_table_idx];
 
     let pallet_idx = match (tile_column % 4 / 2, tile_row % 4 / 2) {
         (0, 0) => attr_byte & 0b11,
@@ -25,7 +42,30 @@ fn bg_pallette(ppu: &Ppu, tile_column: usize, tile_row: usize) -> [u8; 4] {
     ]
 }
 
-fn sprite_palette(ppu: &Ppu, pallete_idx: u8) -> [u8; 4] {
+/// 1スキャンラインに描画できるスプライトの数
+const SPRITE_LIMIT: usize = 8;
+
+/// スキャンライン上の各スプライトを描画するかどうかを返す.
+/// 実機と同様にOAMの先頭から評価し、1スキャンラインで9個目以降となるスプライトは描画しない.
+/// `disable_sprite_limit`が有効な場合はスキャンライン上の全てのスプライトを描画する.
+fn sprites_visible_on_line(ppu: &Ppu, line: usize) -> [bool; 64] {
+    let height = ppu.ctrl.sprite_size() as usize;
+    let mut visible = [false; 64];
+    let mut count = 0;
+    for (sprite, visible) in visible.iter_mut().enumerate() {
+        let tile_y = ppu.oam_data[sprite * 4] as usize;
+        if line < tile_y || line >= tile_y + height {
+            continue;
+        }
+        if ppu.disable_sprite_limit || count < SPRITE_LIMIT {
+            *visible = true;
+        }
+        count += 1;
+    }
+    visible
+}
+
+fn sprite_palette(ppu: &Ppu, pallete_idx: u8) -> Palette {
     let start = 0x11 + (pallete_idx * 4) as usize;
     [
         0,
@@ -35,37 +75,117 @@ fn sprite_palette(ppu: &Ppu, pallete_idx: u8) -> [u8; 4] {
     ]
 }
 
+/// スプライトの(上の)タイルのパターンテーブル上のアドレス.
+/// 8x16ではタイル番号のbit0でパターンテーブルを選び、上下に2タイル並べる
+fn sprite_pattern_addr(ppu: &Ppu, tile_idx: u16) -> u16 {
+    if ppu.ctrl.sprite_size() == 16 {
+        (tile_idx & 1) * 0x1000 + (tile_idx & 0xfe) * 16
+    } else {
+        ppu.ctrl.sprt_pattern_addr() + tile_idx * 16
+    }
+}
+
+/// 画面を描画する.
+///
+/// * `PpuMode::Fast` - この時点のPPUの状態から全スキャンラインを描画する
+/// * `PpuMode::Accurate` - PPUがドット毎に描画したフレームをコピーする
 pub fn render(ppu: &Ppu, frame: &mut Frame) {
+    render_into(ppu, frame.buffer_mut());
+}
+
+/// 内部解像度をfactor倍にして画面を描画し、フィルタを適用してから元の解像度に戻す(スクリーンショット用).
+/// ドットは1色の矩形なので、拡大した各ドットはfactor x factorの同じ色になり、境界はぼけない.
+/// フィルタ(走査線等)は拡大した解像度で適用するため、縮小後は1ドットより細かい効果が平均される.
+///
+/// # Parameters
+/// * `ppu` - PPU
+/// * `factor` - 内部解像度の倍率
+/// * `filter` - 拡大した解像度で適用するフィルタ
+pub fn render_supersampled(ppu: &Ppu, factor: usize, filter: Option<&dyn FrameFilter>) -> Frame {
+    let factor = factor.max(1);
+    let mut frame = Frame::new();
+    render(ppu, &mut frame);
+    let data = frame.scaled(factor, ScaleMode::Nearest);
+    let data = match filter {
+        Some(filter) => filter.apply_scaled(&data, factor),
+        None => data,
+    };
+    Frame::from_scaled(&data, factor)
+}
+
+/// 呼び出し元のRGBバッファに画面を描画する. ヒープの確保は行わない.
+/// 多数のインスタンスを動かす場合等、フレーム毎の確保を避けたい場合に使う.
+///
+/// # Parameters
+/// * `ppu` - PPU
+/// * `buf` - 描画先のRGBバッファ
+pub fn render_into(ppu: &Ppu, buf: &mut [u8; Frame::SIZE]) {
+    match ppu.rendered_frame() {
+        Some(rendered) => buf.copy_from_slice(&rendered.data),
+        None => {
+            for line in 0..Frame::HIGHT {
+                draw_pixels(ppu, buf, line, 0..Frame::WIDTH);
+            }
+        }
+    }
+}
+
+/// 1スキャンライン分の背景とスプライトを描画する.
+///
+/// # Parameters
+/// * `ppu` - PPU
+/// * `frame` - 描画先
+/// * `line` - スキャンライン(0～239)
+pub fn render_scanline(ppu: &Ppu, frame: &mut Frame, line: usize) {
+    render_pixels(ppu, frame, line, 0..Frame::WIDTH);
+}
+
+/// スキャンライン上の指定範囲のドットを描画する.
+/// パレット等はこの時点のPPUの状態を読むため、ドット単位の描画ではフレーム途中の変更が反映される.
+///
+/// # Parameters
+/// * `ppu` - PPU
+/// * `frame` - 描画先
+/// * `line` - スキャンライン(0～239)
+/// * `pixels` - 描画するX座標の範囲(0～255)
+pub fn render_pixels(ppu: &Ppu, frame: &mut Frame, line: usize, pixels: Range<usize>) {
+    draw_pixels(ppu, &mut frame.data, line, pixels);
+}
+
+/// `render_pixels`の描画先をRGBバッファにしたもの
+fn draw_pixels(ppu: &Ppu, buf: &mut [u8], line: usize, pixels: Range<usize>) {
+    if pixels.is_empty() {
+        return;
+    }
     let bank = ppu.ctrl.bknd_pattern_addr();
+    let nametable = ppu.nametable_base(ppu.nametable_select());
+    //グレースケール/カラーエンファシスを適用済みのパレットを選ぶ
+    let colors = palette::PALETTE_CACHE.table(ppu.mask.bits());
+
+    let tile_row = line / 8;
+    let y = line % 8;
+    for tile_column in pixels.start / 8..pixels.end.div_ceil(8) {
+        let tile = ppu.vram[nametable + tile_row * 32 + tile_column] as u16;
+        let tile = ppu.pattern_tile(bank + tile * 16);
+        let palette = palette_for_tile(ppu, nametable, tile_column, tile_row);
 
-    for i in 0..0x3c0 {
-        let tile = ppu.vram[i] as u16;
-        let tile_column = i % 32;
-        let tile_row = i / 32;
-        let tile = &ppu.char_data[(bank + tile * 16) as usize..=(bank + tile * 16 + 15) as usize];
-        let palette = bg_pallette(ppu, tile_column, tile_row);
-
-        for y in 0..=7 {
-            let mut upper = tile[y];
-            let mut lower = tile[y + 8];
-
-            for x in (0..=7).rev() {
-                let value = (1 & lower) << 1 | (1 & upper);
-                upper >>= 1;
-                lower >>= 1;
-                let rgb = match value {
-                    0 => palette::SYSTEM_PALLETE[ppu.palette_table[0] as usize],
-                    1 => palette::SYSTEM_PALLETE[palette[1] as usize],
-                    2 => palette::SYSTEM_PALLETE[palette[2] as usize],
-                    3 => palette::SYSTEM_PALLETE[palette[3] as usize],
-                    _ => panic!("should not happen"),
-                };
-                frame.set_pixel(tile_column * 8 + x, tile_row * 8 + y, rgb)
+        for x in 0..8 {
+            let screen_x = tile_column * 8 + x;
+            if !pixels.contains(&screen_x) {
+                continue;
             }
+            let value = (tile[y + 8] >> (7 - x) & 1) << 1 | (tile[y] >> (7 - x) & 1);
+            let rgb = colors[palette[value as usize] as usize];
+            put_pixel(buf, screen_x, line, rgb)
         }
     }
 
+    let sprites_visible = sprites_visible_on_line(ppu, line);
+    let height = ppu.ctrl.sprite_size() as usize;
     for i in (0..ppu.oam_data.len()).step_by(4).rev() {
+        if !sprites_visible[i / 4] {
+            continue;
+        }
         let tile_idx = ppu.oam_data[i + 1] as u16;
         let tile_x = ppu.oam_data[i + 3] as usize;
         let tile_y = ppu.oam_data[i] as usize;
@@ -74,32 +194,353 @@ pub fn render(ppu: &Ppu, frame: &mut Frame) {
         let flip_horizontal = ppu.oam_data[i + 2] >> 6 & 1 == 1;
         let pallette_idx = ppu.oam_data[i + 2] & 0b11;
         let sprite_palette = sprite_palette(ppu, pallette_idx);
-        let bank: u16 = ppu.ctrl.sprt_pattern_addr();
-
-        let tile =
-            &ppu.char_data[(bank + tile_idx * 16) as usize..=(bank + tile_idx * 16 + 15) as usize];
-
-        for y in 0..=7 {
-            let mut upper = tile[y];
-            let mut lower = tile[y + 8];
-            'ololo: for x in (0..=7).rev() {
-                let value = (1 & lower) << 1 | (1 & upper);
-                upper >>= 1;
-                lower >>= 1;
-                let rgb = match value {
-                    0 => continue 'ololo, // skip coloring the pixel
-                    1 => palette::SYSTEM_PALLETE[sprite_palette[1] as usize],
-                    2 => palette::SYSTEM_PALLETE[sprite_palette[2] as usize],
-                    3 => palette::SYSTEM_PALLETE[sprite_palette[3] as usize],
-                    _ => panic!("should not happen"),
-                };
-                match (flip_horizontal, flip_vertical) {
-                    (false, false) => frame.set_pixel(tile_x + x, tile_y + y, rgb),
-                    (true, false) => frame.set_pixel(tile_x + 7 - x, tile_y + y, rgb),
-                    (false, true) => frame.set_pixel(tile_x + x, tile_y + 7 - y, rgb),
-                    (true, true) => frame.set_pixel(tile_x + 7 - x, tile_y + 7 - y, rgb),
-                }
+
+        let row = line - tile_y;
+        let y = if flip_vertical { height - 1 - row } else { row };
+        let tile = ppu.pattern_tile(sprite_pattern_addr(ppu, tile_idx) + (y / 8) as u16 * 16);
+        let y = y % 8;
+        for x in 0..8 {
+            let screen_x = if flip_horizontal {
+                tile_x + 7 - x
+            } else {
+                tile_x + x
+            };
+            if !pixels.contains(&screen_x) {
+                continue;
             }
+            let value = (tile[y + 8] >> (7 - x) & 1) << 1 | (tile[y] >> (7 - x) & 1);
+            let rgb = match value {
+                0 => continue, // skip coloring the pixel
+                1 => colors[sprite_palette[1] as usize],
+                2 => colors[sprite_palette[2] as usize],
+                3 => colors[sprite_palette[3] as usize],
+                _ => panic!("should not happen"),
+            };
+            put_pixel(buf, screen_x, line, rgb)
+        }
+    }
+}
+
+/// OAMのスプライト1個を描画する(スプライトビューア用).
+/// フレームの左上に8x8(8x16モードでは8x16)で描画し、透明なドットは背景色で塗る.
+///
+/// # Parameters
+/// * `ppu` - PPU
+/// * `index` - スプライト番号(0～63)
+pub fn render_sprite(ppu: &Ppu, index: u8) -> Frame {
+    let i = (index as usize % 64) * 4;
+    let tile_idx = ppu.oam_data[i + 1] as u16;
+    let flip_vertical = ppu.oam_data[i + 2] >> 7 & 1 == 1;
+    let flip_horizontal = ppu.oam_data[i + 2] >> 6 & 1 == 1;
+    let sprite_palette = sprite_palette(ppu, ppu.oam_data[i + 2] & 0b11);
+    let colors = palette::PALETTE_CACHE.table(ppu.mask.bits());
+    let height = ppu.ctrl.sprite_size() as usize;
+
+    let top = sprite_pattern_addr(ppu, tile_idx);
+
+    let mut frame = Frame::new();
+    for row in 0..height {
+        let y = if flip_vertical { height - 1 - row } else { row };
+        let tile = ppu.pattern_tile(top + (y / 8) as u16 * 16);
+        let y = y % 8;
+        for x in 0..8 {
+            let screen_x = if flip_horizontal { 7 - x } else { x };
+            let value = (tile[y + 8] >> (7 - x) & 1) << 1 | (tile[y] >> (7 - x) & 1);
+            let rgb = match value {
+                0 => colors[ppu.palette_table[0] as usize],
+                _ => colors[sprite_palette[value as usize] as usize],
+            };
+            frame.set_pixel(screen_x, row, rgb)
+        }
+    }
+    frame
+}
+
+#[cfg(test)]
+mod render_test {
+    use super::*;
+    use crate::ppu::control::ControlRegister;
+    use crate::ppu::ppu::{PpuMode, TPpu};
+    use crate::render::filter::ScanlineFilter;
+    use crate::rom::rom::Mirroring;
+
+    /// y=100の行に10個のスプライトを並べたPPU
+    fn ppu_with_sprites_on_line() -> Ppu {
+        let mut char_data = vec![0; 0x2000];
+        //タイル1は全て色1. 8x16ではタイル1の下半分(0x1010)も色1
+        for y in 0..8 {
+            char_data[16 + y] = 0xff;
+            char_data[0x1010 + y] = 0xff;
+        }
+        let mut ppu = Ppu::new_ppu(char_data, Mirroring::HORIZONTAL);
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[0x11] = 0x16;
+        for sprite in 0..64 {
+            //画面外に配置
+            ppu.oam_data[sprite * 4] = 0xf0;
+        }
+        for sprite in 0..10 {
+            ppu.oam_data[sprite * 4] = 100;
+            ppu.oam_data[sprite * 4 + 1] = 1;
+            ppu.oam_data[sprite * 4 + 2] = 0;
+            ppu.oam_data[sprite * 4 + 3] = (sprite * 16) as u8;
+        }
+        ppu
+    }
+
+    fn rendered_sprites(ppu: &Ppu, line: usize) -> usize {
+        let mut frame = Frame::new();
+        render(ppu, &mut frame);
+        (0..10)
+            .filter(|sprite| {
+                let base = (line * 256 + sprite * 16) * 3;
+                (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+                    == palette::SYSTEM_PALLETE[0x16]
+            })
+            .count()
+    }
+
+    #[test]
+    fn sprite_limit() {
+        let ppu = ppu_with_sprites_on_line();
+        assert_eq!(rendered_sprites(&ppu, 100), 8);
+        assert_eq!(rendered_sprites(&ppu, 108), 0);
+    }
+
+    #[test]
+    fn sprite_limit_8x16() {
+        let mut ppu = ppu_with_sprites_on_line();
+        ppu.ctrl.insert(ControlRegister::SPRITE_SIZE);
+        //下半分の行もスプライトの範囲に入り、8個に制限される
+        assert_eq!(rendered_sprites(&ppu, 108), 8);
+    }
+
+    #[test]
+    fn sprite_limit_disabled() {
+        let mut ppu = ppu_with_sprites_on_line();
+        ppu.disable_sprite_limit = true;
+        assert_eq!(rendered_sprites(&ppu, 100), 10);
+    }
+
+    /// 背景とスプライトを配置した静止画面
+    fn static_scene(mode: PpuMode) -> Ppu {
+        let mut ppu = ppu_with_sprites_on_line();
+        ppu.set_mode(mode);
+        for (i, tile) in ppu.vram[..0x3c0].iter_mut().enumerate() {
+            *tile = (i % 2) as u8;
         }
+        ppu.vram[0x3c0] = 0b1110_0100;
+        ppu.palette_table[1] = 0x21;
+        ppu.palette_table[5] = 0x2a;
+        //上下左右反転したスプライト
+        ppu.oam_data[40] = 20;
+        ppu.oam_data[41] = 1;
+        ppu.oam_data[42] = 0b1100_0001;
+        ppu.oam_data[43] = 30;
+        ppu
+    }
+
+    /// スキャンライン240まで(可視領域の描画が終わるまで)PPUを進める
+    fn run_visible_lines(ppu: &mut Ppu) {
+        while ppu.scanline() < 240 {
+            ppu.tick(3);
+        }
+    }
+
+    #[test]
+    fn fast_and_accurate_render_same_static_scene() {
+        let fast = static_scene(PpuMode::Fast);
+        let mut fast_frame = Frame::new();
+        render(&fast, &mut fast_frame);
+
+        let mut accurate = static_scene(PpuMode::Accurate);
+        run_visible_lines(&mut accurate);
+        let mut accurate_frame = Frame::new();
+        render(&accurate, &mut accurate_frame);
+
+        assert!(fast_frame.data.iter().any(|&c| c != 0));
+        assert!(fast_frame.data == accurate_frame.data);
+    }
+
+    #[test]
+    fn render_into_matches_render() {
+        let mut accurate = static_scene(PpuMode::Accurate);
+        run_visible_lines(&mut accurate);
+        let mut buf = [0u8; Frame::SIZE];
+        render_into(&accurate, &mut buf);
+        let mut frame = Frame::new();
+        render(&accurate, &mut frame);
+        assert!(frame.data[..] == buf[..]);
+    }
+
+    #[test]
+    fn supersampled_render_averages_filter_at_edge() {
+        //左上のタイル(x=0～7, y=0～7)だけ色1、他は背景色(黒)
+        let mut char_data = vec![0; 0x2000];
+        for y in 0..8 {
+            char_data[16 + y] = 0xff;
+        }
+        let mut ppu = Ppu::new_ppu(char_data, Mirroring::HORIZONTAL);
+        ppu.vram[0] = 1;
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[1] = 0x30;
+        let filter = ScanlineFilter { brightness: 0.5 };
+
+        let frame = render_supersampled(&ppu, 2, Some(&filter));
+        assert_eq!(frame.data.len(), Frame::SIZE);
+
+        //2倍では各行の上半分が元の色、下半分が半分の明るさになり、2x2ドットの平均をとる
+        let average = |(r, g, b): (u8, u8, u8)| {
+            [r, g, b].map(|v| ((v as u32 + (v as f32 * 0.5) as u8 as u32 + 1) / 2) as u8)
+        };
+        let expected = average(palette::SYSTEM_PALLETE[0x30]);
+        let pixel = |x: usize, y: usize| {
+            let base = (y * Frame::WIDTH + x) * 3;
+            [frame.data[base], frame.data[base + 1], frame.data[base + 2]]
+        };
+        assert_eq!(pixel(7, 0), expected);
+        assert_eq!(pixel(7, 1), expected);
+        //境界はぼけない
+        let black = average(palette::SYSTEM_PALLETE[0x0f]);
+        assert_eq!(pixel(8, 0), black);
+        assert_eq!(pixel(7, 8), black);
+
+        //フィルタなしでは1倍の描画と同じ
+        let mut plain = Frame::new();
+        render(&ppu, &mut plain);
+        assert!(render_supersampled(&ppu, 2, None).data == plain.data);
+    }
+
+    #[test]
+    fn accurate_mode_captures_mid_frame_palette_change() {
+        let mut ppu = static_scene(PpuMode::Accurate);
+        ppu.vram[..0x3c0].fill(0);
+        ppu.palette_table[0] = 0x01;
+        while ppu.scanline() < 120 {
+            ppu.tick(3);
+        }
+        ppu.palette_table[0] = 0x02;
+        run_visible_lines(&mut ppu);
+
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        let pixel = |y: usize| {
+            let base = (y * 256 + 255) * 3;
+            (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+        };
+        assert_eq!(pixel(0), palette::SYSTEM_PALLETE[0x01]);
+        assert_eq!(pixel(239), palette::SYSTEM_PALLETE[0x02]);
+    }
+
+    #[test]
+    fn accurate_mode_captures_mid_scanline_backdrop_change() {
+        let mut ppu = static_scene(PpuMode::Accurate);
+        ppu.vram[..0x3c0].fill(0);
+        ppu.palette_table[0] = 0x01;
+        //スキャンライン120の中央まで進める
+        while ppu.scanline() < 120 {
+            ppu.tick(3);
+        }
+        while ppu.dot() < 128 {
+            ppu.tick(1);
+        }
+        ppu.palette_table[0] = 0x02;
+        run_visible_lines(&mut ppu);
+
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        let pixel = |x: usize, y: usize| {
+            let base = (y * 256 + x) * 3;
+            (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+        };
+        assert_eq!(pixel(0, 0), palette::SYSTEM_PALLETE[0x01]);
+        assert_eq!(pixel(200, 119), palette::SYSTEM_PALLETE[0x01]);
+        assert_eq!(pixel(100, 120), palette::SYSTEM_PALLETE[0x01]);
+        assert_eq!(pixel(200, 120), palette::SYSTEM_PALLETE[0x02]);
+        assert_eq!(pixel(0, 239), palette::SYSTEM_PALLETE[0x02]);
+    }
+
+    #[test]
+    fn accurate_mode_switches_nametable_at_next_scanline() {
+        let mut ppu = static_scene(PpuMode::Accurate);
+        ppu.set_mirroring(Mirroring::VERTICAL);
+        //ネームテーブル0は背景色、ネームテーブル1はタイル1(色1)
+        ppu.vram[..0x400].fill(0);
+        ppu.vram[0x400..0x7c0].fill(1);
+        ppu.vram[0x7c0..0x800].fill(0);
+        ppu.write_to_mask(0b0001_1000);
+        while ppu.scanline() < 120 {
+            ppu.tick(3);
+        }
+        while ppu.dot() < 128 {
+            ppu.tick(1);
+        }
+        ppu.write_to_ctrl(0b01);
+        run_visible_lines(&mut ppu);
+
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        let pixel = |x: usize, y: usize| {
+            let base = (y * 256 + x) * 3;
+            (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+        };
+        assert_eq!(pixel(200, 120), palette::SYSTEM_PALLETE[0x0f]);
+        assert_eq!(pixel(0, 121), palette::SYSTEM_PALLETE[0x21]);
+        assert_eq!(pixel(200, 239), palette::SYSTEM_PALLETE[0x21]);
+    }
+
+    /// 左上のドットだけ色3のタイル2と、左上だけ色1のタイル3
+    fn ppu_with_sprite_tiles() -> Ppu {
+        let mut char_data = vec![0; 0x2000];
+        char_data[2 * 16] = 0b1000_0000;
+        char_data[2 * 16 + 8] = 0b1000_0000;
+        char_data[3 * 16] = 0b1000_0000;
+        let mut ppu = Ppu::new_ppu(char_data, Mirroring::HORIZONTAL);
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[0x15] = 0x16;
+        ppu.palette_table[0x17] = 0x2a;
+        ppu.oam_data[4 * 5 + 1] = 2;
+        ppu.oam_data[4 * 5 + 2] = 0b0000_0001;
+        ppu
+    }
+
+    #[test]
+    fn render_sprite_with_palette_and_flip() {
+        let mut ppu = ppu_with_sprite_tiles();
+        let pixel = |frame: &Frame, x: usize, y: usize| {
+            let base = (y * 256 + x) * 3;
+            (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+        };
+
+        let frame = ppu.render_sprite(5);
+        assert_eq!(pixel(&frame, 0, 0), palette::SYSTEM_PALLETE[0x2a]);
+        assert_eq!(pixel(&frame, 7, 7), palette::SYSTEM_PALLETE[0x0f]);
+
+        //上下左右反転
+        ppu.oam_data[4 * 5 + 2] = 0b1100_0001;
+        let frame = ppu.render_sprite(5);
+        assert_eq!(pixel(&frame, 0, 0), palette::SYSTEM_PALLETE[0x0f]);
+        assert_eq!(pixel(&frame, 7, 7), palette::SYSTEM_PALLETE[0x2a]);
+    }
+
+    #[test]
+    fn render_sprite_8x16() {
+        let mut ppu = ppu_with_sprite_tiles();
+        ppu.write_to_ctrl(0b0010_0000);
+        let pixel = |frame: &Frame, x: usize, y: usize| {
+            let base = (y * 256 + x) * 3;
+            (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+        };
+
+        //タイル2が上、タイル3が下
+        let frame = ppu.render_sprite(5);
+        assert_eq!(pixel(&frame, 0, 0), palette::SYSTEM_PALLETE[0x2a]);
+        assert_eq!(pixel(&frame, 0, 8), palette::SYSTEM_PALLETE[0x16]);
+
+        //上下反転すると下のタイルが上に来る
+        ppu.oam_data[4 * 5 + 2] = 0b1000_0001;
+        let frame = ppu.render_sprite(5);
+        assert_eq!(pixel(&frame, 0, 7), palette::SYSTEM_PALLETE[0x16]);
+        assert_eq!(pixel(&frame, 0, 15), palette::SYSTEM_PALLETE[0x2a]);
     }
 }
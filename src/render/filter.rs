@@ -0,0 +1,118 @@
+use crate::render::frame::{Frame, ScaleMode};
+
+/// FrameFilter Trait
+/// 描画済みのフレームに対する後処理(CRT風の走査線、NTSCコンポジット等)を定義する
+pub trait FrameFilter {
+    fn apply(&self, input: &Frame) -> Frame;
+
+    /// factor倍に拡大したRGBバッファ(`render::render_supersampled`)に適用する.
+    /// デフォルトは元の解像度に戻して`apply`を適用し、再び拡大する.
+    /// 高い解像度で処理した方がきれいになるフィルタは実装し直す.
+    ///
+    /// # Parameters
+    /// * `data` - (WIDTH * factor) x (HIGHT * factor) のRGBバッファ
+    /// * `factor` - 倍率
+    fn apply_scaled(&self, data: &[u8], factor: usize) -> Vec<u8> {
+        self.apply(&Frame::from_scaled(data, factor))
+            .scaled(factor, ScaleMode::Nearest)
+    }
+}
+
+/// ScanlineFilter Struct
+/// 奇数行を暗くしてCRTの走査線を再現する
+///
+/// # Parameters
+/// * `brightness` - 暗くする行の明るさ(0.0~1.0)
+pub struct ScanlineFilter {
+    pub brightness: f32,
+}
+
+impl ScanlineFilter {
+    pub fn new() -> Self {
+        ScanlineFilter { brightness: 0.6 }
+    }
+}
+
+impl Default for ScanlineFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameFilter for ScanlineFilter {
+    fn apply(&self, input: &Frame) -> Frame {
+        let mut output = input.clone();
+        let row_len = Frame::WIDTH * 3;
+        for (y, row) in output.data.chunks_mut(row_len).enumerate() {
+            if y % 2 == 1 {
+                for value in row.iter_mut() {
+                    *value = (*value as f32 * self.brightness) as u8;
+                }
+            }
+        }
+        output
+    }
+
+    /// 拡大した解像度の奇数行を暗くする. 2倍では各スキャンラインの下半分が暗くなる.
+    fn apply_scaled(&self, data: &[u8], factor: usize) -> Vec<u8> {
+        let mut output = data.to_vec();
+        let row_len = Frame::WIDTH * factor.max(1) * 3;
+        for row in output.chunks_mut(row_len).skip(1).step_by(2) {
+            for value in row.iter_mut() {
+                *value = (*value as f32 * self.brightness) as u8;
+            }
+        }
+        output
+    }
+}
+
+/// 名前からフィルタを作成する. 未知の名前の場合はNoneを返す.
+///
+/// # Parameters
+/// * `name` - フィルタ名(`scanline`)
+pub fn from_name(name: &str) -> Option<Box<dyn FrameFilter>> {
+    match name {
+        "scanline" => Some(Box::new(ScanlineFilter::new())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod filter_test {
+    use super::*;
+
+    #[test]
+    fn scanline_darkens_odd_rows() {
+        let mut frame = Frame::new();
+        for y in 0..Frame::HIGHT {
+            for x in 0..Frame::WIDTH {
+                frame.set_pixel(x, y, (200, 100, 50));
+            }
+        }
+
+        let output = ScanlineFilter { brightness: 0.5 }.apply(&frame);
+
+        let pixel = |frame: &Frame, x: usize, y: usize| {
+            let base = (y * Frame::WIDTH + x) * 3;
+            (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+        };
+        assert_eq!(pixel(&output, 10, 0), (200, 100, 50));
+        assert_eq!(pixel(&output, 10, 1), (100, 50, 25));
+        assert_eq!(pixel(&output, 255, 238), (200, 100, 50));
+        assert_eq!(pixel(&output, 255, 239), (100, 50, 25));
+    }
+
+    #[test]
+    fn scanline_scaled_by_one_matches_apply() {
+        let mut frame = Frame::new();
+        frame.set_pixel(3, 1, (200, 100, 50));
+        let filter = ScanlineFilter::new();
+        assert!(filter.apply_scaled(&frame.data, 1) == filter.apply(&frame).data);
+    }
+
+    #[test]
+    fn from_name_unknown() {
+        assert!(from_name("scanline").is_some());
+        assert!(from_name("unknown").is_none());
+    }
+}
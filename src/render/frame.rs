@@ -1,25 +1,211 @@
+/// 拡大方法
+///
+/// | mode |  detail |
+/// |---------|---------|
+/// |Nearest | 最近傍補間(ドットをそのまま拡大する)|
+/// |Bilinear | 双線形補間(周囲4ドットから補間する)|
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScaleMode {
+    Nearest,
+    Bilinear,
+}
+
+/// 画面のRGBバッファ(WIDTH x HIGHT x 3byte)の座標(x, y)に色を書き込む.
+/// 範囲外の座標は無視する.
+///
+/// # Parameters
+/// * `data` - RGBバッファ
+/// * `x` - X座標
+/// * `y` - Y座標
+/// * `rgb` - 色
+pub fn put_pixel(data: &mut [u8], x: usize, y: usize, rgb: (u8, u8, u8)) {
+    let base = y * 3 * Frame::WIDTH + x * 3;
+    if base + 2 < data.len() {
+        data[base] = rgb.0;
+        data[base + 1] = rgb.1;
+        data[base + 2] = rgb.2;
+    }
+}
+
 /// Frame Struct.
+/// 画面のRGBバッファ(`Frame::SIZE`byte). 描画は`render::render_into`でバッファに行う.
+#[derive(Debug, Clone)]
 pub struct Frame {
     pub data: Vec<u8>,
 }
 
 impl Frame {
-    const WIDTH: usize = 256;
-    const HIGHT: usize = 240;
+    pub const WIDTH: usize = 256;
+    pub const HIGHT: usize = 240;
+    /// RGBバッファのサイズ
+    pub const SIZE: usize = Frame::WIDTH * Frame::HIGHT * 3;
 
     ///Frameコンストラクタ.
     pub fn new() -> Self {
         Frame {
-            data: vec![0; (Frame::WIDTH) * (Frame::HIGHT) * 3],
+            data: vec![0; Frame::SIZE],
         }
     }
 
     pub fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
-        let base = y * 3 * Frame::WIDTH + x * 3;
-        if base + 2 < self.data.len() {
-            self.data[base] = rgb.0;
-            self.data[base + 1] = rgb.1;
-            self.data[base + 2] = rgb.2;
+        put_pixel(&mut self.data, x, y, rgb);
+    }
+
+    /// RGBバッファ. `render::render_into`の描画先に使う.
+    pub fn buffer_mut(&mut self) -> &mut [u8; Frame::SIZE] {
+        (&mut self.data[..])
+            .try_into()
+            .expect("frame buffer must be WIDTH * HIGHT * 3 bytes")
+    }
+
+    /// PNGとして保存する.
+    ///
+    /// # Parameters
+    /// * `path` - 保存先
+    pub fn save_png(&self, path: &str) -> image::ImageResult<()> {
+        image::save_buffer(
+            path,
+            &self.data,
+            Frame::WIDTH as u32,
+            Frame::HIGHT as u32,
+            image::ColorType::Rgb8,
+        )
+    }
+
+    /// 拡大したRGBバッファを返す. サイズは (WIDTH * factor) x (HIGHT * factor).
+    ///
+    /// # Parameters
+    /// * `factor` - 倍率
+    /// * `mode` - 拡大方法
+    pub fn scaled(&self, factor: usize, mode: ScaleMode) -> Vec<u8> {
+        let factor = factor.max(1);
+        let width = Frame::WIDTH * factor;
+        let height = Frame::HIGHT * factor;
+        let mut data = vec![0; width * height * 3];
+
+        for y in 0..height {
+            for x in 0..width {
+                let rgb = match mode {
+                    ScaleMode::Nearest => self.pixel(x / factor, y / factor),
+                    ScaleMode::Bilinear => self.bilinear(x, y, factor),
+                };
+                let base = (y * width + x) * 3;
+                data[base..base + 3].copy_from_slice(&rgb);
+            }
+        }
+        data
+    }
+
+    /// `scaled`で拡大したRGBバッファを、factor x factor ドットの平均で元の解像度に戻す.
+    ///
+    /// # Parameters
+    /// * `data` - (WIDTH * factor) x (HIGHT * factor) のRGBバッファ
+    /// * `factor` - 倍率
+    pub fn from_scaled(data: &[u8], factor: usize) -> Frame {
+        let factor = factor.max(1);
+        let width = Frame::WIDTH * factor;
+        let samples = (factor * factor) as u32;
+        let mut frame = Frame::new();
+        for y in 0..Frame::HIGHT {
+            for x in 0..Frame::WIDTH {
+                let mut sum = [0u32; 3];
+                for sy in y * factor..(y + 1) * factor {
+                    for sx in x * factor..(x + 1) * factor {
+                        let base = (sy * width + sx) * 3;
+                        for c in 0..3 {
+                            sum[c] += data[base + c] as u32;
+                        }
+                    }
+                }
+                let average = |c: usize| ((sum[c] + samples / 2) / samples) as u8;
+                frame.set_pixel(x, y, (average(0), average(1), average(2)));
+            }
+        }
+        frame
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let base = (y * Frame::WIDTH + x) * 3;
+        [self.data[base], self.data[base + 1], self.data[base + 2]]
+    }
+
+    /// 拡大後の座標(x, y)の色を周囲4ドットから補間する.
+    /// 拡大後のドットの中心を元の画像の座標に戻し、端は端のドットの色を使う.
+    fn bilinear(&self, x: usize, y: usize, factor: usize) -> [u8; 3] {
+        let source = |position: usize, size: usize| {
+            let position = ((position as f32 + 0.5) / factor as f32 - 0.5).max(0.0);
+            let low = (position as usize).min(size - 1);
+            let high = (low + 1).min(size - 1);
+            (low, high, position - low as f32)
+        };
+        let (x0, x1, fx) = source(x, Frame::WIDTH);
+        let (y0, y1, fy) = source(y, Frame::HIGHT);
+
+        let (p00, p10) = (self.pixel(x0, y0), self.pixel(x1, y0));
+        let (p01, p11) = (self.pixel(x0, y1), self.pixel(x1, y1));
+        let mut rgb = [0; 3];
+        for c in 0..3 {
+            let top = p00[c] as f32 * (1.0 - fx) + p10[c] as f32 * fx;
+            let bottom = p01[c] as f32 * (1.0 - fx) + p11[c] as f32 * fx;
+            rgb[c] = (top * (1.0 - fy) + bottom * fy).round() as u8;
         }
+        rgb
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod frame_test {
+    use super::*;
+
+    fn scaled_pixel(data: &[u8], factor: usize, x: usize, y: usize) -> (u8, u8, u8) {
+        let base = (y * Frame::WIDTH * factor + x) * 3;
+        (data[base], data[base + 1], data[base + 2])
+    }
+
+    #[test]
+    fn scaled_nearest_doubles_pixels() {
+        let mut frame = Frame::new();
+        frame.set_pixel(1, 0, (0x11, 0x22, 0x33));
+        frame.set_pixel(255, 239, (0xff, 0x00, 0x00));
+
+        let data = frame.scaled(2, ScaleMode::Nearest);
+        assert_eq!(data.len(), 512 * 480 * 3);
+        for (x, y) in [(2, 0), (3, 0), (2, 1), (3, 1)] {
+            assert_eq!(scaled_pixel(&data, 2, x, y), (0x11, 0x22, 0x33));
+        }
+        assert_eq!(scaled_pixel(&data, 2, 1, 0), (0, 0, 0));
+        assert_eq!(scaled_pixel(&data, 2, 4, 0), (0, 0, 0));
+        assert_eq!(scaled_pixel(&data, 2, 511, 479), (0xff, 0x00, 0x00));
+    }
+
+    #[test]
+    fn scaled_bilinear_interpolates() {
+        let mut frame = Frame::new();
+        for y in 0..Frame::HIGHT {
+            frame.set_pixel(0, y, (200, 100, 0));
+        }
+
+        let data = frame.scaled(2, ScaleMode::Bilinear);
+        //端は元の色のまま
+        assert_eq!(scaled_pixel(&data, 2, 0, 10), (200, 100, 0));
+        //元の座標 0.25 → 0.75 * 200
+        assert_eq!(scaled_pixel(&data, 2, 1, 10), (150, 75, 0));
+        //元の座標 0.75 → 0.25 * 200
+        assert_eq!(scaled_pixel(&data, 2, 2, 10), (50, 25, 0));
+        assert_eq!(scaled_pixel(&data, 2, 3, 10), (0, 0, 0));
+    }
+
+    #[test]
+    fn from_scaled_inverts_nearest() {
+        let mut frame = Frame::new();
+        frame.set_pixel(7, 3, (1, 2, 3));
+        let restored = Frame::from_scaled(&frame.scaled(3, ScaleMode::Nearest), 3);
+        assert!(restored.data == frame.data);
     }
 }
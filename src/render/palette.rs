@@ -1,3 +1,5 @@
+use lazy_static::lazy_static;
+
 #[rustfmt::skip]
 
 /// カラーパレット
@@ -16,3 +18,148 @@ pub static SYSTEM_PALLETE: [(u8,u8,u8); 64] = [
     (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
     (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
 ];
+
+/// Palette
+/// パレットテーブルの4色の組(パレット番号0～63). 0番目は背景色
+pub type Palette = [u8; 4];
+
+/// タイルのピクセル値(0～3)を`SYSTEM_PALLETE`の色にする.
+/// グレースケール/カラーエンファシスは適用しない(画面の描画は`PALETTE_CACHE`を使う).
+///
+/// # Parameters
+/// * `palette_index` - タイルのピクセル値(0～3). 上位bitは無視する
+/// * `palette` - 使用するパレット
+pub fn nes_color(palette_index: u8, palette: &Palette) -> (u8, u8, u8) {
+    SYSTEM_PALLETE[(palette[(palette_index & 0b11) as usize] & 0x3f) as usize]
+}
+
+/// カラーエンファシスで強調されない色成分の減衰率
+const EMPHASIS_ATTENUATION: f32 = 0.816328;
+
+/// PPUMASKのうち色に影響するビット(グレースケールとカラーエンファシス)
+const COLOR_MASK_BITS: u8 = 0b1110_0001;
+
+lazy_static! {
+    /// グレースケール/カラーエンファシスを適用済みのパレット
+    pub static ref PALETTE_CACHE: PaletteCache = PaletteCache::new();
+}
+
+/// グレースケールとカラーエンファシスを適用した色を計算する.
+///
+/// * グレースケール - パレット番号の下位4bitを0にする(灰色の列を使う)
+/// * カラーエンファシス - 他の色成分が強調されている色成分を減衰させる
+///   (3色全てを強調すると全体が暗くなる)
+///
+/// # Parameters
+/// * `index` - パレット番号(0～63)
+/// * `mask` - PPUMASKの値
+pub fn apply_mask(index: u8, mask: u8) -> (u8, u8, u8) {
+    let index = if mask & 0b0000_0001 != 0 {
+        index & 0x30
+    } else {
+        index
+    };
+    let (r, g, b) = SYSTEM_PALLETE[(index & 0x3f) as usize];
+
+    let emphasis = mask >> 5;
+    if emphasis == 0 {
+        return (r, g, b);
+    }
+    let attenuate = |value: u8, channel: u8| {
+        if emphasis & !channel == 0 {
+            value
+        } else {
+            (value as f32 * EMPHASIS_ATTENUATION) as u8
+        }
+    };
+    (
+        attenuate(r, 0b001),
+        attenuate(g, 0b010),
+        attenuate(b, 0b100),
+    )
+}
+
+/// PaletteCache Struct
+/// グレースケール/カラーエンファシスの全16通りの組み合わせについて
+/// 適用済みの64色のパレットを事前に計算しておく.
+/// 描画時はフレーム毎にテーブルを1つ選び、ピクセル毎の計算を配列の参照だけにする.
+pub struct PaletteCache {
+    tables: [[(u8, u8, u8); 64]; 16],
+}
+
+impl PaletteCache {
+    ///PaletteCacheコンストラクタ
+    pub fn new() -> Self {
+        let mut tables = [[(0, 0, 0); 64]; 16];
+        for (i, table) in tables.iter_mut().enumerate() {
+            let mask = PaletteCache::mask_bits(i);
+            for (index, color) in table.iter_mut().enumerate() {
+                *color = apply_mask(index as u8, mask);
+            }
+        }
+        PaletteCache { tables }
+    }
+
+    /// PPUMASKの値に対応するパレット
+    ///
+    /// # Parameters
+    /// * `mask` - PPUMASKの値
+    pub fn table(&self, mask: u8) -> &[(u8, u8, u8); 64] {
+        let mask = mask & COLOR_MASK_BITS;
+        &self.tables[((mask >> 4) | (mask & 1)) as usize]
+    }
+
+    /// テーブルのインデックス(bit0: グレースケール, bit1～3: エンファシス)からPPUMASKの値を求める
+    fn mask_bits(table_index: usize) -> u8 {
+        ((table_index as u8 & 0b1110) << 4) | (table_index as u8 & 1)
+    }
+}
+
+impl Default for PaletteCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod palette_test {
+    use super::*;
+
+    #[test]
+    fn cache_matches_apply_mask() {
+        let cache = PaletteCache::new();
+        for emphasis in 0..8u8 {
+            for grayscale in 0..2u8 {
+                //色に影響しないビットは無視される
+                let mask = emphasis << 5 | 0b0001_1110 | grayscale;
+                let table = cache.table(mask);
+                for index in 0..64u8 {
+                    assert_eq!(table[index as usize], apply_mask(index, mask));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nes_color_values() {
+        let palette = [0x0f, 0x16, 0x2a, 0x30];
+        assert_eq!(nes_color(0, &palette), (0x05, 0x05, 0x05));
+        assert_eq!(nes_color(1, &palette), (0xff, 0x22, 0x00));
+        assert_eq!(nes_color(2, &palette), (0x2b, 0xf0, 0x35));
+        assert_eq!(nes_color(3, &palette), (0xff, 0xff, 0xff));
+        //ピクセル値の上位bitとパレット番号の上位2bitは無視する
+        assert_eq!(
+            nes_color(0b101, &[0, 0x40 | 0x12, 0, 0]),
+            (0x21, 0x55, 0xff)
+        );
+    }
+
+    #[test]
+    fn apply_mask_values() {
+        assert_eq!(apply_mask(0x16, 0), SYSTEM_PALLETE[0x16]);
+        assert_eq!(apply_mask(0x16, 0b0000_0001), SYSTEM_PALLETE[0x10]);
+        //赤を強調すると緑と青が減衰する
+        assert_eq!(apply_mask(0x30, 0b0010_0000), (0xff, 0xd0, 0xd0));
+        assert_eq!(apply_mask(0x30, 0b1110_0000), (0xd0, 0xd0, 0xd0));
+    }
+}
@@ -1,2 +1,3 @@
+pub mod cartridge;
 pub mod header;
 pub mod rom;
@@ -0,0 +1,323 @@
+use super::rom::Mirroring;
+use crate::cpu::state::{StateReader, StateWriter};
+use crate::error::NesError;
+use std::fmt::Debug;
+
+const PRG_RAM_SIZE: usize = 0x2000;
+const PRG_SIZE: usize = 0x8000;
+/// CHR-RAMのサイズ(キャラクターROMを持たないカートリッジ)
+pub const CHR_RAM_SIZE: usize = 0x2000;
+
+/// ChrMemory Trait
+/// PPUから見たパターンテーブル(0x0000～0x1FFF)へのアクセスを定義する.
+/// CHRバンク切り替えを行うカートリッジは、バンクの状態を共有する実装をPPUに渡す.
+pub trait ChrMemory: Debug + ChrMemoryClone {
+    /// パターンテーブルの読み出し
+    fn read_chr(&self, addr: u16) -> u8;
+
+    /// パターンテーブルへの書き込み. CHR-ROMは無視する
+    fn write_chr(&mut self, _addr: u16, _data: u8) {}
+
+    /// 書き込み可能なCHR-RAMかどうか
+    fn is_ram(&self) -> bool {
+        false
+    }
+}
+
+/// ChrMemoryを複製するためのTrait. Cloneを実装した型には自動で実装される.
+/// バンクの状態をカートリッジと共有する実装(Rc等)では、複製も同じ状態を共有する.
+pub trait ChrMemoryClone {
+    fn clone_box(&self) -> Box<dyn ChrMemory>;
+}
+
+impl<T: ChrMemory + Clone + 'static> ChrMemoryClone for T {
+    fn clone_box(&self) -> Box<dyn ChrMemory> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn ChrMemory> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// CHR-ROM
+impl ChrMemory for Vec<u8> {
+    fn read_chr(&self, addr: u16) -> u8 {
+        self[addr as usize]
+    }
+}
+
+/// ChrRam Struct
+/// 書き込み可能なパターンテーブル. PPUが保持し、0x2007からの書き込みで更新される.
+#[derive(Debug, Clone)]
+pub struct ChrRam {
+    data: Vec<u8>,
+}
+
+impl ChrRam {
+    ///ChrRamコンストラクタ
+    ///
+    /// # Parameters
+    /// * `data` - 初期値
+    pub fn new(data: Vec<u8>) -> Self {
+        ChrRam { data }
+    }
+}
+
+impl ChrMemory for ChrRam {
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.data[addr as usize]
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.data[addr as usize] = data;
+    }
+
+    fn is_ram(&self) -> bool {
+        true
+    }
+}
+
+/// Cartridge Trait
+/// CPU/PPUから見たカートリッジへのアクセスを定義する
+///
+/// | address |  purpose |
+/// |---------|---------|
+/// |0x6000～0x7FFF| PRG-RAM|
+/// |0x8000～0xFFFF| PRG-ROM|
+pub trait Cartridge {
+    /// PRG領域(0x8000～0xFFFF)の読み出し
+    fn read_prg(&self, addr: u16) -> u8;
+
+    /// PRG領域(0x8000～0xFFFF)への書き込み
+    fn write_prg(&mut self, addr: u16, data: u8);
+
+    /// PRG-RAM(0x6000～0x7FFF)の読み出し. 持たないカートリッジは0を返す
+    fn read_prg_ram(&self, _addr: u16) -> u8 {
+        0
+    }
+
+    /// PRG-RAM(0x6000～0x7FFF)への書き込み. 持たないカートリッジは無視する
+    fn write_prg_ram(&mut self, _addr: u16, _data: u8) {}
+
+    /// PRG-RAM全体(セーブRAMの保存用). 持たないカートリッジは空
+    fn prg_ram(&self) -> &[u8] {
+        &[]
+    }
+
+    /// PRG-ROM全体(ROMのハッシュ用). デフォルトは0x8000～0xFFFFに見えている範囲
+    fn prg_rom(&self) -> Vec<u8> {
+        (0x8000..=0xffff).map(|addr| self.read_prg(addr)).collect()
+    }
+
+    /// PPUに渡すキャラクターデータ
+    fn char_data(&self) -> Vec<u8>;
+
+    /// PPUが使用するパターンテーブル. デフォルトはキャラクターデータのコピー(CHR-ROM)
+    fn chr_memory(&self) -> Box<dyn ChrMemory> {
+        Box::new(self.char_data())
+    }
+
+    /// ミラーリング
+    fn mirroring(&self) -> Mirroring;
+
+    /// マッパーが処理せずに無視したPRG領域への書き込みの回数(診断用)
+    fn ignored_writes(&self) -> u64 {
+        0
+    }
+
+    /// マッパーのレジスタやPRG-RAM等、セーブステートに含める状態を書き出す.
+    /// CHR-RAMはPPUの状態に含まれる.
+    ///
+    /// # Parameters
+    /// * `w` - 書き出し先
+    fn write_state(&self, _w: &mut StateWriter) {}
+
+    /// `write_state`で書き出した状態を読み込む.
+    /// 不正な値の場合は`NesError::InvalidState`を返し、状態を変更しない.
+    ///
+    /// # Parameters
+    /// * `r` - 読み込み元
+    fn read_state(&mut self, _r: &mut StateReader) -> Result<(), NesError> {
+        Ok(())
+    }
+}
+
+/// マッパー番号から作ったカートリッジ(`mapper::create`)をそのままBus/Nesに渡せるようにする
+impl<C: Cartridge + ?Sized> Cartridge for Box<C> {
+    fn read_prg(&self, addr: u16) -> u8 {
+        (**self).read_prg(addr)
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        (**self).write_prg(addr, data)
+    }
+
+    fn read_prg_ram(&self, addr: u16) -> u8 {
+        (**self).read_prg_ram(addr)
+    }
+
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        (**self).write_prg_ram(addr, data)
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        (**self).prg_ram()
+    }
+
+    fn prg_rom(&self) -> Vec<u8> {
+        (**self).prg_rom()
+    }
+
+    fn char_data(&self) -> Vec<u8> {
+        (**self).char_data()
+    }
+
+    fn chr_memory(&self) -> Box<dyn ChrMemory> {
+        (**self).chr_memory()
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        (**self).mirroring()
+    }
+
+    fn ignored_writes(&self) -> u64 {
+        (**self).ignored_writes()
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        (**self).write_state(w)
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), NesError> {
+        (**self).read_state(r)
+    }
+}
+
+/// RamCartridge struct
+/// 0x6000～0xFFFFの全域を書き込み可能なRAMとして扱うテスト用カートリッジ.
+/// 自己書き換えを行うプログラムやPRG領域へ書き込むCPUテストで使用する.
+///
+/// # Parameters
+/// * `prg_ram` - 0x6000～0x7FFF
+/// * `program_data` - 0x8000～0xFFFF
+/// * `char_data` - CHR-RAM
+/// * `screen_mirroring` - ミラーリング
+pub struct RamCartridge {
+    pub prg_ram: Vec<u8>,
+    pub program_data: Vec<u8>,
+    pub char_data: Vec<u8>,
+    pub screen_mirroring: Mirroring,
+}
+
+impl RamCartridge {
+    ///RamCartridgeコンストラクタ
+    ///プログラムを0x8000に配置し、リセットベクタ(0xFFFC)を0x8000に設定する.
+    ///
+    /// # Parameters
+    /// * `program` - 0x8000から配置するプログラム
+    /// * `chr_ram_size` - CHR-RAMのサイズ
+    pub fn new(program: &[u8], chr_ram_size: usize) -> Self {
+        let mut program_data = vec![0; PRG_SIZE];
+        program_data[..program.len()].copy_from_slice(program);
+        program_data[0x7ffc] = 0x00;
+        program_data[0x7ffd] = 0x80;
+
+        RamCartridge {
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            program_data,
+            char_data: vec![0; chr_ram_size],
+            screen_mirroring: Mirroring::HORIZONTAL,
+        }
+    }
+}
+
+impl Cartridge for RamCartridge {
+    fn read_prg(&self, addr: u16) -> u8 {
+        self.program_data[(addr - 0x8000) as usize]
+    }
+
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        self.program_data[(addr - 0x8000) as usize] = data;
+    }
+
+    fn read_prg_ram(&self, addr: u16) -> u8 {
+        self.prg_ram[(addr - 0x6000) as usize]
+    }
+
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        self.prg_ram[(addr - 0x6000) as usize] = data;
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn char_data(&self) -> Vec<u8> {
+        self.char_data.clone()
+    }
+
+    fn chr_memory(&self) -> Box<dyn ChrMemory> {
+        Box::new(ChrRam::new(self.char_data.clone()))
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.screen_mirroring.clone()
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.prg_ram);
+        w.bytes(&self.program_data);
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), NesError> {
+        let prg_ram = r.bytes(self.prg_ram.len())?;
+        let program_data = r.bytes(self.program_data.len())?;
+        self.prg_ram.copy_from_slice(prg_ram);
+        self.program_data.copy_from_slice(program_data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod cartridge_test {
+    use super::*;
+    use crate::cpu::bus::Bus;
+    use crate::cpu::cpu::{Cpu, Memory};
+
+    #[test]
+    fn ram_cartridge_self_modifying_program() {
+        let program = [
+            0xa2, 0x00, //       LDX #$00
+            0xa9, 0xe8, //       LDA #$E8 (INX)
+            0x8d, 0x07, 0x80, // STA $8007
+            0xea, //             NOP -> INX に書き換えられる
+            0x00, //             BRK
+        ];
+        let bus = Bus::new(RamCartridge::new(&program, 0x2000), |_| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.run();
+
+        assert_eq!(cpu.mem_read(0x8007), 0xe8);
+        assert_eq!(cpu.reg_x, 1);
+    }
+
+    #[test]
+    fn ram_cartridge_prg_ram() {
+        let program = [
+            0xa9, 0x42, //       LDA #$42
+            0x8d, 0x00, 0x60, // STA $6000
+            0xae, 0x00, 0x60, // LDX $6000
+            0x00, //             BRK
+        ];
+        let bus = Bus::new(RamCartridge::new(&program, 0x2000), |_| {});
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu.run();
+
+        assert_eq!(cpu.reg_x, 0x42);
+    }
+}
@@ -1,4 +1,22 @@
-use std::io::{Error, ErrorKind};
+use std::io::Error;
+
+/// iNESヘッダのサイズ
+pub const NES_HEADER_SIZE: usize = 0x10;
+
+/// ROMが対象とするテレビ方式
+///
+/// | variant |  detail |
+/// |---------|---------|
+/// |Ntsc | NTSC(日本・北米). ヘッダに指定がない場合|
+/// |Pal | PAL(欧州)|
+/// |Dual | NTSC/PALの両対応|
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum TvSystem {
+    #[default]
+    Ntsc,
+    Pal,
+    Dual,
+}
 
 /// Header Struct
 ///
@@ -6,11 +24,15 @@ use std::io::{Error, ErrorKind};
 /// * `nes_header_const` - ASCII letters 'NES' followed by 0x1A(EOF)
 /// * `program_size` - プログラムROMサイズ
 /// * `char_size` - キャラクターROMサイズ
+/// * `tv_system` - テレビ方式(byte 9、byte 10)
+/// * `has_prg_ram` - 0x6000～0x7FFFにPRG-RAMがあるか(byte 10)
 #[derive(Debug, PartialEq)]
 pub struct Header {
     pub nes_header_const: [u8; 4],
     pub program_size: u32,
     pub char_size: u32,
+    pub tv_system: TvSystem,
+    pub has_prg_ram: bool,
 }
 
 impl Header {
@@ -19,8 +41,19 @@ impl Header {
         // 0-3: Constant $4E $45 $53 $1A ("NES" followed by MS-DOS end-of-file)
         // 4: Size of PRG ROM in 16 KB units
         // 5: Size of CHR ROM in 8 KB units (Value 0 means the board uses CHR RAM)
+        // 9: bit0 TV system (0: NTSC, 1: PAL)
+        // 10: bit0-1 TV system (0: NTSC, 2: PAL, 1/3: dual compatible),
+        //     bit4 PRG RAM ($6000-$7FFF) (0: present, 1: not present)
+        //     (非公式の拡張で、多くのROMでは0)
         // refer: https://wiki.nesdev.com/w/index.php/INES
 
+        if buf.len() < NES_HEADER_SIZE {
+            return Err(Error::other(format!(
+                "Invalid file header. too short: {} bytes",
+                buf.len()
+            )));
+        }
+
         let headers = *array_ref!(buf, 0, 4);
         match headers {
             [78, 69, 83, 26] => Ok(Header {
@@ -29,17 +62,26 @@ impl Header {
                 program_size: (buf[4] as u32) * 0x4000,
                 //allocates a buffer of 8KiB. 0x2000 means 2000 in hexadecimal, which is 8192 in decimal.
                 char_size: (buf[5] as u32) * 0x2000,
+                tv_system: tv_system(buf[9], buf[10]),
+                has_prg_ram: buf[10] & 0b0001_0000 == 0,
             }),
-            _ => {
-                return Err(std::io::Error::new(
-                    ErrorKind::Other,
-                    format!("Invalid file header. {:?}", headers),
-                ))
-            }
+            _ => Err(Error::other(format!("Invalid file header. {:?}", headers))),
         }
     }
 }
 
+/// byte 9とbyte 10からテレビ方式を求める. byte 9のPALの指定を優先する.
+fn tv_system(flags9: u8, flags10: u8) -> TvSystem {
+    if flags9 & 1 == 1 {
+        return TvSystem::Pal;
+    }
+    match flags10 & 0b11 {
+        0 => TvSystem::Ntsc,
+        2 => TvSystem::Pal,
+        _ => TvSystem::Dual,
+    }
+}
+
 #[cfg(test)]
 mod header_test {
 
@@ -51,27 +93,63 @@ mod header_test {
         let rom_bytes = [78, 69, 83, 26, 53, 51];
         assert_eq!(rom_bytes, *"NES\x1A53".as_bytes());
 
-        let header = Header::new(&rom_bytes.to_vec()).unwrap();
+        let mut buf = rom_bytes.to_vec();
+        buf.resize(NES_HEADER_SIZE, 0);
+        let header = Header::new(&buf).unwrap();
         assert_eq!(
             header,
             Header {
                 nes_header_const: [rom_bytes[0], rom_bytes[1], rom_bytes[2], rom_bytes[3],],
                 program_size: (rom_bytes[4] as u32) * 0x4000,
                 char_size: (rom_bytes[5] as u32) * 0x2000,
+                tv_system: TvSystem::Ntsc,
+                has_prg_ram: true,
             }
         );
     }
 
+    #[test]
+    fn tv_system_and_prg_ram_flags() {
+        let header = |flags9: u8, flags10: u8| {
+            let mut buf = b"NES\x1a\x01\x01".to_vec();
+            buf.resize(NES_HEADER_SIZE, 0);
+            buf[9] = flags9;
+            buf[10] = flags10;
+            Header::new(&buf).unwrap()
+        };
+        assert_eq!(header(0x01, 0x00).tv_system, TvSystem::Pal);
+        assert_eq!(header(0x00, 0x02).tv_system, TvSystem::Pal);
+        assert_eq!(header(0x00, 0x03).tv_system, TvSystem::Dual);
+        //byte 9が優先
+        assert_eq!(header(0x01, 0x01).tv_system, TvSystem::Pal);
+
+        assert!(header(0x01, 0x00).has_prg_ram);
+        assert!(!header(0x00, 0x10).has_prg_ram);
+    }
+
     #[test]
     fn new_format_error() {
         // "N" "X" "S" "\x1A" "5" "3"
         let rom_bytes = [78, 88, 83, 26, 53, 51];
         assert_eq!(rom_bytes, *"NXS\x1A53".as_bytes());
 
-        let ines_header = Header::new(&rom_bytes.to_vec());
+        let mut buf = rom_bytes.to_vec();
+        buf.resize(NES_HEADER_SIZE, 0);
+        let ines_header = Header::new(&buf);
         assert!(match ines_header {
             Err(_error) => true,
             _ => false,
         });
     }
+
+    #[test]
+    fn new_too_short_error() {
+        let rom_bytes = [78, 69];
+
+        let ines_header = Header::new(&rom_bytes.to_vec());
+        assert!(match ines_header {
+            Err(error) => error.to_string().starts_with("Invalid file header."),
+            _ => false,
+        });
+    }
 }
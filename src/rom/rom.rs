@@ -1,9 +1,71 @@
-use super::header::Header;
+use super::header::{Header, NES_HEADER_SIZE};
+use crate::error::NesError;
+use std::fmt;
 use std::fs::File;
-use std::io;
-use std::io::Read;
+use std::io::{Error, ErrorKind, Read};
+
+/// fwNESヘッダ付きのFDSイメージの先頭 ("FDS" followed by MS-DOS end-of-file)
+const FDS_HEADER_CONST: [u8; 4] = [70, 68, 83, 26];
+/// ヘッダなしのFDSイメージの1面のサイズ
+const FDS_DISK_SIDE_SIZE: usize = 65500;
+/// FDSのディスク情報ブロック (ブロックコード 0x01 + "*NINTENDO-HVC*")
+const FDS_DISK_INFO: &[u8] = b"\x01*NINTENDO-HVC*";
+/// iNES/NES 2.0の先頭 ("NES" followed by MS-DOS end-of-file)
+const NES_HEADER_CONST: [u8; 4] = [78, 69, 83, 26];
+/// UNIFの先頭
+const UNIF_HEADER_CONST: &[u8] = b"UNIF";
+
+/// ROMファイルの形式
+///
+/// | name |  detail |
+/// |---------|---------|
+/// |INes | iNES|
+/// |Nes20 | NES 2.0 (byte 7のbit2-3が2). iNESと互換性のある項目のみ読み込む|
+/// |Fds | ディスクシステムのイメージ(fwNESヘッダ付き、またはヘッダなし). 未対応|
+/// |Unif | UNIF. 未対応|
+/// |Unknown | 上記のいずれでもない|
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RomFormat {
+    INes,
+    Nes20,
+    Fds,
+    Unif,
+    Unknown,
+}
+
+impl RomFormat {
+    /// ファイルの先頭から形式を判定する.
+    ///
+    /// # Parameters
+    /// * `buffer` - ROM buffer
+    pub fn detect(buffer: &[u8]) -> RomFormat {
+        if buffer.starts_with(&NES_HEADER_CONST) {
+            match buffer.get(7) {
+                Some(flags7) if flags7 & 0b0000_1100 == 0b0000_1000 => RomFormat::Nes20,
+                _ => RomFormat::INes,
+            }
+        } else if is_fds(buffer) {
+            RomFormat::Fds
+        } else if buffer.starts_with(UNIF_HEADER_CONST) {
+            RomFormat::Unif
+        } else {
+            RomFormat::Unknown
+        }
+    }
+}
 
-const NES_HEADER_SIZE: usize = 0x10;
+impl fmt::Display for RomFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RomFormat::INes => "iNES",
+            RomFormat::Nes20 => "NES 2.0",
+            RomFormat::Fds => "FDS",
+            RomFormat::Unif => "UNIF",
+            RomFormat::Unknown => "unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Mirroring {
@@ -32,18 +94,34 @@ impl Rom {
     ///
     /// # Parameters
     /// * `path` - Path of ROM file
-    pub fn load(path: &str) -> Result<Self, io::Error> {
+    pub fn load(path: &str) -> Result<Self, NesError> {
         //read Rom file
         let rom_buffer = load_file(path);
+        Rom::from_bytes(&rom_buffer)
+    }
+
+    /// parse rom data
+    ///
+    /// # Parameters
+    /// * `rom_buffer` - ROM file contents
+    pub fn from_bytes(rom_buffer: &[u8]) -> Result<Self, NesError> {
+        //FDS(ディスクシステム)とUNIFのイメージは未対応.
+        //形式が分からない場合はヘッダの解析でエラーになる
+        match RomFormat::detect(rom_buffer) {
+            format @ (RomFormat::Fds | RomFormat::Unif) => {
+                return Err(NesError::UnsupportedFormat(format))
+            }
+            RomFormat::INes | RomFormat::Nes20 | RomFormat::Unknown => {}
+        }
 
         //read Header
         let nes_header = Header::new(&rom_buffer.to_vec())?;
-        println!("{:?}", nes_header);
+        log::debug!("{:?}", nes_header);
 
         //read program data
-        let program_data = load_program(&rom_buffer, &nes_header)?;
+        let program_data = load_program(rom_buffer, &nes_header)?;
         //read charctor data
-        let char_data = load_char(&rom_buffer, &nes_header)?;
+        let char_data = load_char(rom_buffer, &nes_header)?;
 
         //mapper
         let mapper = (rom_buffer[7] & 0b1111_0000) | (rom_buffer[6] >> 4);
@@ -67,6 +145,55 @@ impl Rom {
     }
 }
 
+#[cfg(test)]
+impl Rom {
+    /// テスト用のROM. NTSC, PRG-RAMあり, 8KiBのCHR-ROM(0), mapper 0, 水平ミラーリング
+    ///
+    /// # Parameters
+    /// * `program_data` - PRG-ROM
+    pub(crate) fn test(program_data: Vec<u8>) -> Self {
+        Rom {
+            header: Header {
+                nes_header_const: [78, 69, 83, 26],
+                program_size: program_data.len() as u32,
+                char_size: 0x2000,
+                tv_system: super::header::TvSystem::Ntsc,
+                has_prg_ram: true,
+            },
+            program_data,
+            char_data: vec![0; 0x2000],
+            mapper: 0,
+            screen_mirroring: Mirroring::HORIZONTAL,
+        }
+    }
+
+    /// CHR-ROMを置き換える. 空ならCHR-RAMになる
+    pub(crate) fn with_char_data(mut self, char_data: Vec<u8>) -> Self {
+        self.header.char_size = char_data.len() as u32;
+        self.char_data = char_data;
+        self
+    }
+
+    /// マッパー番号とミラーリングを置き換える
+    pub(crate) fn with_mapper(mut self, mapper: u8, mirroring: Mirroring) -> Self {
+        self.mapper = mapper;
+        self.screen_mirroring = mirroring;
+        self
+    }
+}
+
+/// FDS(ディスクシステム)のイメージかどうか.
+/// fwNESヘッダ付き、またはディスク情報ブロックで始まる1面65500バイトのイメージを判定する.
+///
+/// # Parameters
+/// * `buffer` - ROM buffer
+fn is_fds(buffer: &[u8]) -> bool {
+    buffer.starts_with(&FDS_HEADER_CONST)
+        || (!buffer.is_empty()
+            && buffer.len().is_multiple_of(FDS_DISK_SIDE_SIZE)
+            && buffer.starts_with(FDS_DISK_INFO))
+}
+
 /// read Rom file. Returns ROM buffer.
 ///
 /// # Parameters
@@ -94,14 +221,18 @@ fn load_file(path: &str) -> Vec<u8> {
 }
 
 ///load Program data from buffer. Returns Program buffer.
+///ファイルがヘッダのサイズより短い(書き込み途中等)場合はエラーを返す.
 ///
 /// # Parameters
 /// * `buffer` - ROM buffer
 /// * `header` - Header struct
-fn load_program(buffer: &[u8], header: &Header) -> Result<Vec<u8>, std::io::Error> {
+fn load_program(buffer: &[u8], header: &Header) -> Result<Vec<u8>, Error> {
     let start: usize = NES_HEADER_SIZE;
     let end = start + header.program_size as usize;
-    Ok(buffer[start..end].to_vec())
+    buffer
+        .get(start..end)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "PRG-ROM is truncated"))
 }
 
 ///load Charactor data from buffer. Returns Charactor buffer.
@@ -109,15 +240,19 @@ fn load_program(buffer: &[u8], header: &Header) -> Result<Vec<u8>, std::io::Erro
 /// # Parameters
 /// * `buffer` - ROM buffer
 /// * `header` - Header struct
-fn load_char(buffer: &[u8], header: &Header) -> Result<Vec<u8>, std::io::Error> {
+fn load_char(buffer: &[u8], header: &Header) -> Result<Vec<u8>, Error> {
     let start: usize = NES_HEADER_SIZE + header.program_size as usize;
     let end = start + header.char_size as usize;
-    Ok(buffer[start..end].to_vec())
+    buffer
+        .get(start..end)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "CHR-ROM is truncated"))
 }
 
 #[cfg(test)]
 mod rom_tests {
     use super::*;
+    use crate::render::palette::{nes_color, Palette};
 
     fn img(rom: &Rom) -> Option<image::RgbaImage> {
         let num = rom.char_data.len() / 16;
@@ -136,16 +271,8 @@ mod rom_tests {
 
         // img.put_pixel(x: u32, y: u32, pixel: P);
 
-        const COLOR_PALLETTE: [[u8; 4]; 4] = [
-            // [0x69, 0xA2, 0xFF, 255], //transparent
-            // [0xBA, 0x06, 0, 255],    //color index = 6
-            // [0xFF, 0x88, 0x33, 255], //color index = 38
-            // [0xC4, 0x62, 0x00, 255], //color index = 8
-            [0, 0, 0, 255],       //Black
-            [0, 0, 0, 255],       //Black
-            [255, 0, 0, 255],     //Black
-            [255, 255, 255, 255], //White
-        ];
+        //黒, 黒, 赤, 白
+        const PALETTE: Palette = [0x0f, 0x0f, 0x16, 0x30];
 
         (0..num).for_each(|sprite_index| {
             let sprite: [u8; 16] = rom
@@ -165,7 +292,8 @@ mod rom_tests {
             (0..8).for_each(|y| {
                 let indexes = &cindexes[y * 8..(y + 1) * 8];
                 indexes.iter().enumerate().for_each(|(x, c)| {
-                    let pixel = image::Rgba(COLOR_PALLETTE[*c]);
+                    let (r, g, b) = nes_color(*c as u8, &PALETTE);
+                    let pixel = image::Rgba([r, g, b, 255]);
                     img.put_pixel((x + xoffset) as u32, (y + yoffset) as u32, pixel);
                 });
             });
@@ -201,6 +329,69 @@ mod rom_tests {
         palette
     }
 
+    #[test]
+    fn fds_with_header_is_unsupported() {
+        let mut buffer = b"FDS\x1a\x01".to_vec();
+        buffer.resize(16 + FDS_DISK_SIDE_SIZE, 0);
+        assert!(matches!(
+            Rom::from_bytes(&buffer),
+            Err(NesError::UnsupportedFormat(RomFormat::Fds))
+        ));
+    }
+
+    #[test]
+    fn raw_fds_disk_is_unsupported() {
+        let mut buffer = FDS_DISK_INFO.to_vec();
+        buffer.resize(FDS_DISK_SIDE_SIZE * 2, 0);
+        assert!(matches!(
+            Rom::from_bytes(&buffer),
+            Err(NesError::UnsupportedFormat(RomFormat::Fds))
+        ));
+    }
+
+    #[test]
+    fn unif_is_unsupported() {
+        let mut buffer = b"UNIF".to_vec();
+        buffer.resize(32, 0);
+        assert!(matches!(
+            Rom::from_bytes(&buffer),
+            Err(NesError::UnsupportedFormat(RomFormat::Unif))
+        ));
+    }
+
+    #[test]
+    fn detect_format() {
+        let mut ines = vec![0; NES_HEADER_SIZE];
+        ines[..4].copy_from_slice(&NES_HEADER_CONST);
+        assert_eq!(RomFormat::detect(&ines), RomFormat::INes);
+        ines[7] = 0b0000_1000;
+        assert_eq!(RomFormat::detect(&ines), RomFormat::Nes20);
+        assert_eq!(RomFormat::detect(b"FDS\x1a\x01"), RomFormat::Fds);
+        assert_eq!(RomFormat::detect(b"UNIF"), RomFormat::Unif);
+        assert_eq!(RomFormat::detect(&[0; 32]), RomFormat::Unknown);
+        assert_eq!(RomFormat::detect(&[]), RomFormat::Unknown);
+    }
+
+    #[test]
+    fn invalid_header_is_io_error() {
+        let buffer = vec![0; 32];
+        assert!(matches!(Rom::from_bytes(&buffer), Err(NesError::Io(_))));
+    }
+
+    #[test]
+    fn truncated_rom_is_io_error() {
+        //PRG 16KiB, CHR 8KiB
+        let mut buffer = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, 0, 0];
+        buffer.resize(NES_HEADER_SIZE + 0x4000 + 0x2000, 0);
+        assert!(Rom::from_bytes(&buffer).is_ok());
+        for len in [NES_HEADER_SIZE, NES_HEADER_SIZE + 0x1000, buffer.len() - 1] {
+            assert!(matches!(
+                Rom::from_bytes(&buffer[..len]),
+                Err(NesError::Io(e)) if e.kind() == ErrorKind::UnexpectedEof
+            ));
+        }
+    }
+
     #[test]
     fn save_img() {
         let rom = Rom::load("./hello_world.nes").unwrap();
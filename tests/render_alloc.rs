@@ -0,0 +1,87 @@
+//! `render_into`がヒープの確保を行わないことのテスト.
+//!
+//! ヒープの確保を数えるアロケータ(`#[global_allocator]`)はテストバイナリ全体で使われるため、
+//! ライブラリのテストとは別のバイナリにしている.
+use nes_rs::ppu::ppu::{Ppu, PpuMode};
+use nes_rs::render::frame::Frame;
+use nes_rs::render::{render, render_into};
+use nes_rs::rom::rom::Mirroring;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+thread_local! {
+    /// このスレッドでのヒープの確保回数
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// ヒープの確保回数をスレッド毎に数えるアロケータ
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// `f`の実行中のヒープの確保回数
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+/// 背景とスプライトを並べたPPU. Accurateの場合は可視領域の描画が終わるまで進める
+fn scene(mode: PpuMode) -> Ppu {
+    let mut char_data = vec![0; 0x2000];
+    //タイル1は全て色1
+    for y in 0..8 {
+        char_data[16 + y] = 0xff;
+    }
+    let mut ppu = Ppu::new_ppu_with_mode(char_data, Mirroring::HORIZONTAL, mode);
+    for (i, tile) in ppu.vram[..0x3c0].iter_mut().enumerate() {
+        *tile = (i % 2) as u8;
+    }
+    ppu.palette_table[0] = 0x0f;
+    ppu.palette_table[1] = 0x21;
+    ppu.palette_table[0x11] = 0x16;
+    for sprite in 0..64 {
+        ppu.oam_data[sprite * 4] = 0xf0;
+    }
+    for sprite in 0..10 {
+        ppu.oam_data[sprite * 4] = 100;
+        ppu.oam_data[sprite * 4 + 1] = 1;
+        ppu.oam_data[sprite * 4 + 3] = (sprite * 16) as u8;
+    }
+    if mode == PpuMode::Accurate {
+        while ppu.scanline() < 240 {
+            ppu.tick(3);
+        }
+    }
+    ppu
+}
+
+#[test]
+fn render_into_does_not_allocate() {
+    let fast = scene(PpuMode::Fast);
+    let accurate = scene(PpuMode::Accurate);
+    let mut buf = [0u8; Frame::SIZE];
+    //パレットのキャッシュ(lazy_static)を初期化しておく
+    render_into(&fast, &mut buf);
+
+    assert_eq!(allocations(|| render_into(&fast, &mut buf)), 0);
+    assert_eq!(allocations(|| render_into(&accurate, &mut buf)), 0);
+    let mut frame = Frame::new();
+    assert_eq!(allocations(|| render(&fast, &mut frame)), 0);
+    //確保を数えられていること
+    assert_eq!(allocations(|| drop(Frame::new())), 1);
+    assert!(frame.data[..] == buf[..]);
+    assert!(buf.iter().any(|&c| c != 0));
+}